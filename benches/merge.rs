@@ -0,0 +1,70 @@
+//! Compares merging 500 rendered pages one at a time with
+//! `PdfMerger::add_pages`, which parses and renumbers them in parallel.
+//!
+//! Run with `cargo bench --bench merge`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use pdf_postprocess::{render_svg_to_pdf, MergeOptions, PdfMerger};
+
+const PAGE_COUNT: usize = 500;
+const RUNS: usize = 5;
+
+/// A page of a few hundred shapes, so parsing it takes a while.
+fn page(index: usize) -> Vec<u8> {
+    let shapes: String = (0..300)
+        .map(|shape| {
+            format!(
+                r#"<rect x="{}" y="{}" width="5" height="5" fill="rgb({}, 80, 120)"/>"#,
+                shape % 20 * 10,
+                shape / 20 * 10,
+                (index + shape) % 256
+            )
+        })
+        .collect();
+    render_svg_to_pdf(&format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="300">{}</svg>"#,
+        shapes
+    ))
+    .unwrap()
+}
+
+/// The fastest of [`RUNS`] runs of `merge`.
+fn fastest(merge: impl Fn() -> usize) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            assert_eq!(black_box(merge()), PAGE_COUNT);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let pages: Vec<Vec<u8>> = (0..PAGE_COUNT).map(page).collect();
+    let pages: Vec<&[u8]> = pages.iter().map(Vec::as_slice).collect();
+
+    let serial = fastest(|| {
+        let mut merger = PdfMerger::new(MergeOptions::default());
+        for page in &pages {
+            merger.add_page(page).unwrap();
+        }
+        merger.finish().unwrap().get_pages().len()
+    });
+    let parallel = fastest(|| {
+        let mut merger = PdfMerger::new(MergeOptions::default());
+        merger.add_pages(&pages).unwrap();
+        merger.finish().unwrap().get_pages().len()
+    });
+
+    println!("{} pages, fastest of {} runs", PAGE_COUNT, RUNS);
+    println!("add_page, one at a time: {:?}", serial);
+    println!(
+        "add_pages, {} threads:    {:?} ({:.1}x)",
+        rayon::current_num_threads(),
+        parallel,
+        serial.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
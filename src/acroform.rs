@@ -0,0 +1,425 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use log::warn;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Deserialize;
+
+/// Keys of a field dictionary that belong to the field rather than to its
+/// widget annotations, and are inherited by kids.
+const FIELD_KEYS: [&[u8]; 9] = [
+    b"T", b"TU", b"TM", b"FT", b"Ff", b"V", b"DV", b"Opt", b"MaxLen",
+];
+
+/// How to resolve two merged documents having top-level form fields with the
+/// same name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldClash {
+    /// Rename later fields by appending `_2`, `_3`, ... so every field stays independent
+    #[default]
+    Rename,
+    /// Join the fields into a single field whose widgets share one value
+    Merge,
+}
+
+/// Returns the interactive form of `doc` with its `Fields` resolved to a
+/// direct array, or `None` if the document has no fields.
+pub fn input_form(doc: &Document) -> Option<Dictionary> {
+    let acroform = doc.catalog().ok()?.get(b"AcroForm").ok()?;
+    let mut acroform = doc.dereference(acroform).ok()?.1.as_dict().ok()?.clone();
+
+    let fields = doc
+        .dereference(acroform.get(b"Fields").ok()?)
+        .ok()?
+        .1
+        .as_array()
+        .ok()?
+        .clone();
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    acroform.set("Fields", fields);
+
+    if let Ok(resources) = acroform.get(b"DR") {
+        if let Ok((_, Object::Dictionary(resources))) = doc.dereference(resources) {
+            let mut resources = resources.clone();
+            if let Ok(fonts) = resources.get(b"Font") {
+                if let Ok((_, fonts)) = doc.dereference(fonts) {
+                    let fonts = fonts.clone();
+                    resources.set("Font", fonts);
+                }
+            }
+            acroform.set("DR", resources);
+        }
+    }
+
+    Some(acroform)
+}
+
+/// Combines the forms of all merged inputs into one `AcroForm` dictionary.
+///
+/// The field objects must already be part of `document`. When resolving
+/// clashes with [`FieldClash::Merge`], new parent fields are added to it.
+pub fn merge_forms(
+    document: &mut Document,
+    forms: Vec<Dictionary>,
+    clash: FieldClash,
+) -> Option<Dictionary> {
+    let mut forms = forms.into_iter();
+    let mut acroform = forms.next()?;
+    let mut fields = take_fields(&mut acroform);
+
+    for mut form in forms {
+        fields.extend(take_fields(&mut form));
+        merge_default_resources(&mut acroform, &form);
+
+        if !acroform.has(b"DA") {
+            if let Ok(appearance) = form.get(b"DA") {
+                acroform.set("DA", appearance.clone());
+            }
+        }
+
+        if form
+            .get(b"NeedAppearances")
+            .and_then(Object::as_bool)
+            .unwrap_or(false)
+        {
+            acroform.set("NeedAppearances", true);
+        }
+    }
+
+    let mut merged: Vec<ObjectId> = Vec::new();
+    let mut by_name: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut names: HashSet<Vec<u8>> = fields
+        .iter()
+        .filter_map(|id| field_name(document, *id))
+        .collect();
+
+    for field_id in fields {
+        let name = match field_name(document, field_id) {
+            Some(name) => name,
+            None => {
+                merged.push(field_id);
+                continue;
+            }
+        };
+
+        let existing = match by_name.get(&name) {
+            Some(index) => *index,
+            None => {
+                by_name.insert(name, merged.len());
+                merged.push(field_id);
+                continue;
+            }
+        };
+
+        if clash == FieldClash::Merge {
+            match join_fields(document, merged[existing], field_id) {
+                Some(parent_id) => {
+                    merged[existing] = parent_id;
+                    continue;
+                }
+                None => warn!(
+                    "Form fields named {:?} have different types, renaming instead of merging",
+                    String::from_utf8_lossy(&name)
+                ),
+            }
+        }
+
+        let renamed = (2..)
+            .map(|suffix| {
+                let mut candidate = name.clone();
+                candidate.extend(format!("_{}", suffix).into_bytes());
+                candidate
+            })
+            .find(|candidate| !names.contains(candidate))
+            .unwrap_or_default();
+
+        if let Ok(field) = document.get_dictionary_mut(field_id) {
+            field.set(
+                "T",
+                Object::String(renamed.clone(), lopdf::StringFormat::Literal),
+            );
+        }
+
+        names.insert(renamed.clone());
+        by_name.insert(renamed, merged.len());
+        merged.push(field_id);
+    }
+
+    acroform.set(
+        "Fields",
+        merged
+            .into_iter()
+            .map(Object::Reference)
+            .collect::<Vec<_>>(),
+    );
+
+    Some(acroform)
+}
+
+fn take_fields(form: &mut Dictionary) -> Vec<ObjectId> {
+    form.remove(b"Fields")
+        .and_then(|fields| fields.as_array().ok().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|field| field.as_reference().ok())
+        .collect()
+}
+
+/// Unions the default resource fonts of `other` into `acroform`.
+fn merge_default_resources(acroform: &mut Dictionary, other: &Dictionary) {
+    let other_fonts = match other
+        .get(b"DR")
+        .and_then(Object::as_dict)
+        .and_then(|resources| resources.get(b"Font"))
+        .and_then(Object::as_dict)
+    {
+        Ok(fonts) => fonts.clone(),
+        Err(_) => return,
+    };
+
+    let mut resources = acroform
+        .get(b"DR")
+        .and_then(Object::as_dict)
+        .cloned()
+        .unwrap_or_default();
+    let mut fonts = resources
+        .get(b"Font")
+        .and_then(Object::as_dict)
+        .cloned()
+        .unwrap_or_default();
+
+    for (name, font) in other_fonts.iter() {
+        if !fonts.has(name) {
+            fonts.set(name.clone(), font.clone());
+        }
+    }
+
+    resources.set("Font", fonts);
+    acroform.set("DR", resources);
+}
+
+fn field_name(document: &Document, field_id: ObjectId) -> Option<Vec<u8>> {
+    document
+        .get_dictionary(field_id)
+        .ok()?
+        .get(b"T")
+        .ok()?
+        .as_str()
+        .ok()
+        .map(<[u8]>::to_vec)
+}
+
+/// Makes the widgets of `field_id` share the value of `existing_id`.
+///
+/// Returns the id of the field now representing both, or `None` when the two
+/// fields are of different types and can't be joined.
+fn join_fields(
+    document: &mut Document,
+    existing_id: ObjectId,
+    field_id: ObjectId,
+) -> Option<ObjectId> {
+    let existing = document.get_dictionary(existing_id).ok()?.clone();
+    let field = document.get_dictionary(field_id).ok()?.clone();
+
+    if existing.get(b"FT").ok() != field.get(b"FT").ok() {
+        return None;
+    }
+
+    // A field with kids already acts as the parent of its widgets. A field
+    // merged with its single widget is split so the widget, which pages
+    // reference in their /Annots, keeps its object id.
+    let parent_id = if existing.has(b"Kids") {
+        existing_id
+    } else {
+        let mut parent = Dictionary::new();
+        for key in FIELD_KEYS {
+            if let Ok(value) = existing.get(key) {
+                parent.set(key, value.clone());
+            }
+        }
+        parent.set("Kids", vec![Object::Reference(existing_id)]);
+
+        let parent_id = document.add_object(parent);
+        let widget = document.get_dictionary_mut(existing_id).ok()?;
+        for key in FIELD_KEYS {
+            widget.remove(key);
+        }
+        widget.set("Parent", parent_id);
+
+        parent_id
+    };
+
+    let widgets = match field.get(b"Kids").and_then(Object::as_array) {
+        Ok(kids) => kids
+            .iter()
+            .filter_map(|kid| kid.as_reference().ok())
+            .collect(),
+        Err(_) => {
+            let widget = document.get_dictionary_mut(field_id).ok()?;
+            for key in FIELD_KEYS {
+                widget.remove(key);
+            }
+            vec![field_id]
+        }
+    };
+
+    for widget_id in &widgets {
+        if let Ok(widget) = document.get_dictionary_mut(*widget_id) {
+            widget.set("Parent", parent_id);
+        }
+    }
+
+    if let Ok(Object::Array(kids)) = document
+        .get_dictionary_mut(parent_id)
+        .ok()?
+        .get_mut(b"Kids")
+    {
+        kids.extend(widgets.into_iter().map(Object::Reference));
+    }
+
+    Some(parent_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, StringFormat};
+
+    use super::*;
+
+    /// Adds a text field of its own widget named `name` to `doc`.
+    fn add_field(doc: &mut Document, name: &str, field_type: &str) -> ObjectId {
+        doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "FT" => field_type,
+            "T" => Object::string_literal(name),
+            "V" => Object::string_literal(format!("value of {}", name)),
+            "Rect" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+        })
+    }
+
+    fn form(fields: &[ObjectId]) -> Dictionary {
+        dictionary! {
+            "Fields" => fields.iter().copied().map(Object::Reference).collect::<Vec<_>>(),
+        }
+    }
+
+    fn fields(acroform: &Dictionary) -> Vec<ObjectId> {
+        acroform
+            .get(b"Fields")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field.as_reference().unwrap())
+            .collect()
+    }
+
+    fn name(doc: &Document, id: ObjectId) -> String {
+        String::from_utf8(field_name(doc, id).unwrap()).unwrap()
+    }
+
+    fn parent(doc: &Document, id: ObjectId) -> ObjectId {
+        doc.get_dictionary(id)
+            .unwrap()
+            .get(b"Parent")
+            .unwrap()
+            .as_reference()
+            .unwrap()
+    }
+
+    #[test]
+    fn renames_clashing_fields_past_the_names_taken() {
+        let mut doc = Document::with_version("1.5");
+        let first = add_field(&mut doc, "name", "Tx");
+        let second = add_field(&mut doc, "name", "Tx");
+        let taken = add_field(&mut doc, "name_2", "Tx");
+        let third = add_field(&mut doc, "name", "Tx");
+        let forms = vec![form(&[first]), form(&[second, taken]), form(&[third])];
+
+        let acroform = merge_forms(&mut doc, forms, FieldClash::Rename).unwrap();
+
+        assert_eq!(fields(&acroform), [first, second, taken, third]);
+        let names: Vec<String> = fields(&acroform)
+            .into_iter()
+            .map(|id| name(&doc, id))
+            .collect();
+        assert_eq!(names, ["name", "name_3", "name_2", "name_4"]);
+    }
+
+    #[test]
+    fn merges_clashing_fields_into_one_parent() {
+        let mut doc = Document::with_version("1.5");
+        let first = add_field(&mut doc, "name", "Tx");
+        let second = add_field(&mut doc, "name", "Tx");
+        let kids = [
+            doc.add_object(dictionary! { "Subtype" => "Widget" }),
+            doc.add_object(dictionary! { "Subtype" => "Widget" }),
+        ];
+        let with_kids = doc.add_object(dictionary! {
+            "FT" => "Tx",
+            "T" => Object::String(b"name".to_vec(), StringFormat::Literal),
+            "Kids" => kids.iter().copied().map(Object::Reference).collect::<Vec<_>>(),
+        });
+        for kid in kids {
+            doc.get_dictionary_mut(kid)
+                .unwrap()
+                .set("Parent", with_kids);
+        }
+        let forms = vec![form(&[first]), form(&[second]), form(&[with_kids])];
+
+        let acroform = merge_forms(&mut doc, forms, FieldClash::Merge).unwrap();
+
+        // The first field was split from its widget, which keeps its id.
+        let [parent_id] = fields(&acroform)[..] else {
+            panic!("fields are {:?}", fields(&acroform));
+        };
+        assert!(![first, second, with_kids].contains(&parent_id));
+        let merged = doc.get_dictionary(parent_id).unwrap();
+        assert_eq!(name(&doc, parent_id), "name");
+        assert_eq!(merged.get(b"FT").unwrap().as_name().unwrap(), b"Tx");
+        assert_eq!(
+            merged.get(b"V").unwrap().as_str().unwrap(),
+            b"value of name"
+        );
+        assert!(!merged.has(b"Parent"));
+        let widgets: Vec<ObjectId> = merged
+            .get(b"Kids")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|kid| kid.as_reference().unwrap())
+            .collect();
+        assert_eq!(widgets, [first, second, kids[0], kids[1]]);
+
+        for widget in widgets {
+            assert_eq!(parent(&doc, widget), parent_id);
+            let widget = doc.get_dictionary(widget).unwrap();
+            assert!(!widget.has(b"T") && !widget.has(b"FT") && !widget.has(b"V"));
+        }
+    }
+
+    #[test]
+    fn renames_clashing_fields_of_different_types_when_merging() {
+        let mut doc = Document::with_version("1.5");
+        let text = add_field(&mut doc, "choice", "Tx");
+        let button = add_field(&mut doc, "choice", "Btn");
+
+        let acroform = merge_forms(
+            &mut doc,
+            vec![form(&[text]), form(&[button])],
+            FieldClash::Merge,
+        )
+        .unwrap();
+
+        assert_eq!(fields(&acroform), [text, button]);
+        assert_eq!(name(&doc, button), "choice_2");
+        assert!(!doc.get_dictionary(text).unwrap().has(b"Parent"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use log::warn;
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+
+use crate::metadata::text_string;
+
+/// A file carried inside the merged document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// Name viewers list the file under, e.g. `chapter1/01-summary.svg`.
+    pub name: String,
+    pub mime_type: String,
+    pub content: Vec<u8>,
+}
+
+/// Embeds `attachments` in `doc` as file attachments, listed in the
+/// `/EmbeddedFiles` name tree of the catalog's `/Names`, which is where
+/// viewers look for the attachments they show.
+///
+/// Attachments the document already has are kept. Names that are taken get a
+/// counter, e.g. `page.svg (2)`. Unless `compress` is false, the files are
+/// stored Flate compressed.
+pub fn embed_files(doc: &mut Document, attachments: Vec<Attachment>, compress: bool) -> Result<()> {
+    let mut names = match existing_names(doc) {
+        Some(names) => names,
+        None => {
+            warn!("The document's attachments are not a flat name tree, not embedding any files");
+            return Ok(());
+        }
+    };
+
+    let mut taken: HashSet<Vec<u8>> = names
+        .chunks(2)
+        .filter_map(|entry| entry[0].as_str().ok().map(<[u8]>::to_vec))
+        .collect();
+
+    for attachment in attachments {
+        let mut key = text_string(&attachment.name);
+        let mut counter = 1;
+        while taken.contains(key.as_str()?) {
+            counter += 1;
+            key = text_string(&format!("{} ({})", attachment.name, counter));
+        }
+        taken.insert(key.as_str()?.to_vec());
+
+        let mut file = Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Subtype" => Object::Name(attachment.mime_type.into_bytes()),
+                "Params" => dictionary! {
+                    "Size" => attachment.content.len() as i64,
+                },
+            },
+            attachment.content,
+        );
+        if compress {
+            file.compress()?;
+        }
+        let file_id = doc.add_object(file);
+
+        let filespec_id = doc.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => key.clone(),
+            "UF" => key.clone(),
+            "EF" => dictionary! {
+                "F" => file_id,
+                "UF" => file_id,
+            },
+        });
+        names.push(key);
+        names.push(Object::Reference(filespec_id));
+    }
+
+    // Name trees have to be sorted by their keys.
+    let mut entries: Vec<(Object, Object)> = names
+        .chunks(2)
+        .map(|entry| (entry[0].clone(), entry[1].clone()))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.as_str().ok().cmp(&b.as_str().ok()));
+    let names: Vec<Object> = entries
+        .into_iter()
+        .flat_map(|(key, value)| [key, value])
+        .collect();
+
+    let mut name_dictionary = match doc.catalog()?.get(b"Names") {
+        Ok(names) => doc.dereference(names)?.1.as_dict()?.clone(),
+        Err(_) => Dictionary::new(),
+    };
+    name_dictionary.set("EmbeddedFiles", dictionary! { "Names" => names });
+    doc.catalog_mut()?.set("Names", name_dictionary);
+
+    Ok(())
+}
+
+/// The `/Names` array of the document's `/EmbeddedFiles` tree, empty if
+/// there is none, or `None` if the tree has intermediate nodes.
+fn existing_names(doc: &Document) -> Option<Vec<Object>> {
+    let tree = match doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| doc.dereference(names).ok()?.1.as_dict().ok())
+        .and_then(|names| names.get(b"EmbeddedFiles").ok())
+    {
+        Some(tree) => doc.dereference(tree).ok()?.1.as_dict().ok()?,
+        None => return Some(Vec::new()),
+    };
+
+    if tree.has(b"Kids") {
+        return None;
+    }
+
+    match tree.get(b"Names") {
+        Ok(names) => Some(doc.dereference(names).ok()?.1.as_array().ok()?.clone()),
+        Err(_) => Some(Vec::new()),
+    }
+}
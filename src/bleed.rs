@@ -0,0 +1,42 @@
+use anyhow::Result;
+use lopdf::{Document, Object, ObjectId};
+
+use crate::page::{media_box, page_box};
+
+/// Prepares every page of `doc` for print production: the visible area, its
+/// CropBox or else MediaBox, grows by `margin` on every side into the trim
+/// size, and the MediaBox extends `bleed` beyond that.
+///
+/// The CropBox and TrimBox are set to the trim size, which is what viewers
+/// show, and the BleedBox to the new MediaBox, up to which content is
+/// printed before the sheet is cut. The boxes are set on the pages
+/// themselves, overriding any inherited from the page tree. Content that
+/// stopped at the old edge isn't extended into the bleed.
+pub fn add_bleed(doc: &mut Document, bleed: f32, margin: f32) -> Result<()> {
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+
+    for page_id in page_ids {
+        let visible = match page_box(doc, page_id, b"CropBox")? {
+            Some(crop_box) => crop_box,
+            None => media_box(doc, page_id)?,
+        };
+        let trim = grow(visible, margin);
+        let media = grow(trim, bleed);
+
+        let page = doc.get_dictionary_mut(page_id)?;
+        page.set("MediaBox", rect(media));
+        page.set("BleedBox", rect(media));
+        page.set("TrimBox", rect(trim));
+        page.set("CropBox", rect(trim));
+    }
+
+    Ok(())
+}
+
+fn grow([left, bottom, right, top]: [f32; 4], amount: f32) -> [f32; 4] {
+    [left - amount, bottom - amount, right + amount, top + amount]
+}
+
+fn rect(rect: [f32; 4]) -> Object {
+    Object::Array(rect.into_iter().map(Object::Real).collect())
+}
@@ -0,0 +1,143 @@
+use std::fs::{create_dir_all, read, rename, write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::warn;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+
+use crate::{Heading, RenderOptions, RenderTimings, RenderedPage};
+
+/// Tells apart the temporary files of entries written at the same time.
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Directory of previously rendered pages, keyed by the content of their SVG
+/// and the options they were rendered with, so unchanged pages of
+/// incremental builds aren't rendered again.
+///
+/// Every entry is a JSON file named after its [`RenderCache::key`]. Nothing
+/// is ever removed; delete the directory to start over.
+#[derive(Debug, Clone)]
+pub struct RenderCache {
+    dir: PathBuf,
+}
+
+/// What an entry holds, the PDFs base64-encoded.
+#[derive(Serialize, Deserialize)]
+struct CachedPage {
+    pdf: String,
+    embedded_pdfs: Vec<String>,
+    headings: Vec<(u32, String)>,
+    title: Option<String>,
+    default_size: bool,
+}
+
+impl RenderCache {
+    /// Uses `dir` for the cache, creating it if it doesn't exist yet.
+    pub fn new(dir: &Path) -> Result<RenderCache> {
+        create_dir_all(dir).with_context(|| format!("Cannot create cache directory {:?}", dir))?;
+
+        Ok(RenderCache {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Hash of `svg_content` along with everything in `render_options` and
+    /// the version of this crate, so changing e.g. the DPI or upgrading
+    /// misses the cache.
+    ///
+    /// Files the SVG refers to, such as linked images and fonts, are not
+    /// part of it, only their paths are.
+    pub fn key(svg_content: &str, render_options: &RenderOptions) -> String {
+        Md5::new()
+            .chain_update(env!("CARGO_PKG_VERSION"))
+            .chain_update(format!("{:?}", render_options))
+            .chain_update(svg_content)
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The page stored under `key`, if any. Entries that can't be read are
+    /// treated as missing, with a warning.
+    pub fn load(&self, key: &str) -> Option<RenderedPage> {
+        let start = Instant::now();
+        let path = self.entry_path(key);
+        if !path.exists() {
+            return None;
+        }
+
+        let load = || -> Result<RenderedPage> {
+            let cached: CachedPage = serde_json::from_slice(&read(&path)?)?;
+
+            Ok(RenderedPage {
+                pdf: STANDARD.decode(cached.pdf)?,
+                headings: cached
+                    .headings
+                    .into_iter()
+                    .map(|(level, title)| Heading { level, title })
+                    .collect(),
+                title: cached.title,
+                embedded_pdfs: cached
+                    .embedded_pdfs
+                    .iter()
+                    .map(|pdf| STANDARD.decode(pdf))
+                    .collect::<Result<_, _>>()?,
+                default_size: cached.default_size,
+                timings: RenderTimings::default(),
+                total_time: start.elapsed(),
+            })
+        };
+
+        match load() {
+            Ok(page) => Some(page),
+            Err(e) => {
+                warn!("Ignoring unreadable cache entry {:?}: {:#}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Stores `page` under `key`, replacing any previous entry at once, so
+    /// concurrent runs never read a partially written one.
+    pub fn store(&self, key: &str, page: &RenderedPage) -> Result<()> {
+        let cached = CachedPage {
+            pdf: STANDARD.encode(&page.pdf),
+            embedded_pdfs: page
+                .embedded_pdfs
+                .iter()
+                .map(|pdf| STANDARD.encode(pdf))
+                .collect(),
+            headings: page
+                .headings
+                .iter()
+                .map(|heading| (heading.level, heading.title.clone()))
+                .collect(),
+            title: page.title.clone(),
+            default_size: page.default_size,
+        };
+
+        let path = self.entry_path(key);
+        let temporary_path = self.dir.join(format!(
+            "{}.{}-{}.tmp",
+            key,
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        write(&temporary_path, serde_json::to_vec(&cached)?)
+            .with_context(|| format!("Cannot write cache entry {:?}", temporary_path))?;
+        rename(&temporary_path, &path)
+            .with_context(|| format!("Cannot write cache entry {:?}", path))?;
+
+        Ok(())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension("json")
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use lopdf::{dictionary, Document, Object, Stream};
+use serde::Deserialize;
+
+use crate::metadata::text_string;
+
+/// The compact sRGB v4 profile svg2pdf tags its colors with, in the public
+/// domain.
+const SRGB_PROFILE: &[u8] = include_bytes!("icc/sRGB-v4.icc");
+
+/// Output condition of [`ColorProfile::Srgb`], as named in the ICC registry.
+const SRGB_IDENTIFIER: &str = "sRGB IEC61966-2.1";
+
+/// ICC profile the merged document declares as its output intent.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ColorProfile {
+    /// The sRGB profile bundled with this crate, `sRGB` on the command line.
+    Srgb,
+    /// An ICC profile file, e.g. a print shop's FOGRA39 profile.
+    File(PathBuf),
+}
+
+impl FromStr for ColorProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorProfile, String> {
+        if s.is_empty() {
+            return Err("expected `sRGB` or the path of an ICC profile".to_string());
+        }
+        if s.eq_ignore_ascii_case("srgb") {
+            return Ok(ColorProfile::Srgb);
+        }
+
+        Ok(ColorProfile::File(PathBuf::from(s)))
+    }
+}
+
+impl TryFrom<String> for ColorProfile {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<ColorProfile, String> {
+        s.parse()
+    }
+}
+
+impl ColorProfile {
+    /// Reads the profile, from its file for [`ColorProfile::File`].
+    pub fn load(&self) -> Result<IccProfile> {
+        match self {
+            ColorProfile::Srgb => IccProfile::new(SRGB_PROFILE.to_vec(), SRGB_IDENTIFIER),
+            ColorProfile::File(path) => {
+                let data = fs::read(path)
+                    .with_context(|| format!("Cannot read color profile {:?}", path))?;
+                let identifier = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy())
+                    .unwrap_or_default();
+                IccProfile::new(data, &identifier)
+                    .with_context(|| format!("Invalid color profile {:?}", path))
+            }
+        }
+    }
+}
+
+/// An ICC profile checked to describe a color space PDF has a device
+/// equivalent of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    data: Vec<u8>,
+    /// Number of color components, the `/N` of the profile stream.
+    components: u8,
+    /// Name of the output condition, e.g. `sRGB IEC61966-2.1`.
+    identifier: String,
+}
+
+impl IccProfile {
+    /// Checks the header of the profile in `data`, which must be for gray,
+    /// RGB or CMYK colors.
+    pub fn new(data: Vec<u8>, identifier: &str) -> Result<IccProfile> {
+        // The header is 128 bytes, with the signature at 36.
+        if data.len() < 128 || &data[36..40] != b"acsp" {
+            return Err(anyhow!("Not an ICC profile"));
+        }
+
+        let components = match &data[16..20] {
+            b"GRAY" => 1,
+            b"RGB " => 3,
+            b"CMYK" => 4,
+            color_space => {
+                return Err(anyhow!(
+                    "ICC profiles for the {:?} color space are not supported, only gray, RGB and CMYK ones",
+                    String::from_utf8_lossy(color_space).trim_end()
+                ))
+            }
+        };
+
+        Ok(IccProfile {
+            data,
+            components,
+            identifier: identifier.to_string(),
+        })
+    }
+
+    /// Number of color components of the profile's color space.
+    pub fn components(&self) -> u8 {
+        self.components
+    }
+
+    /// Device color space of the same kind, for readers that can't use the
+    /// profile.
+    fn alternate(&self) -> &'static str {
+        match self.components {
+            1 => "DeviceGray",
+            3 => "DeviceRGB",
+            _ => "DeviceCMYK",
+        }
+    }
+}
+
+/// Embeds `profile` in `doc` and declares it the document's output intent,
+/// the `/OutputIntents` of the catalog, replacing any the document had.
+///
+/// The intent is of the `GTS_PDFA1` kind PDF/A asks for, which print
+/// workflows read as well. Unless `compress` is false, the profile is
+/// stored Flate compressed.
+pub fn set_output_intent(doc: &mut Document, profile: &IccProfile, compress: bool) -> Result<()> {
+    let mut stream = Stream::new(
+        dictionary! {
+            "N" => profile.components as i64,
+            "Alternate" => profile.alternate(),
+        },
+        profile.data.clone(),
+    );
+    if compress {
+        stream.compress()?;
+    }
+    let profile_id = doc.add_object(stream);
+
+    let intent = dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFA1",
+        "OutputConditionIdentifier" => text_string(&profile.identifier),
+        "Info" => text_string(&profile.identifier),
+        "DestOutputProfile" => profile_id,
+    };
+    let intent_id = doc.add_object(intent);
+
+    doc.catalog_mut()?
+        .set("OutputIntents", vec![Object::Reference(intent_id)]);
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::io::Write;
+
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use lopdf::{Document, Object};
+
+/// Compresses every stream of `doc` with Flate at `level`, from 0 (stored
+/// uncompressed, fastest) to 9 (smallest, slowest).
+///
+/// Streams that are already Flate compressed are decompressed and compressed
+/// again at `level`. Streams using predictors or other filters, like JPEG
+/// images, are left alone. As with [`Document::compress`], a stream only keeps
+/// its compression if that actually makes it smaller.
+pub fn compress_with_level(doc: &mut Document, level: u32) -> Result<()> {
+    for object in doc.objects.values_mut() {
+        let stream = match object {
+            Object::Stream(stream) if stream.allows_compression => stream,
+            _ => continue,
+        };
+
+        let content = match stream.dict.get(b"Filter") {
+            Err(_) => stream.content.clone(),
+            Ok(Object::Name(filter))
+                if filter == b"FlateDecode" && !stream.dict.has(b"DecodeParms") =>
+            {
+                match stream.decompressed_content() {
+                    Ok(content) => content,
+                    Err(_) => continue,
+                }
+            }
+            Ok(_) => continue,
+        };
+
+        stream.dict.remove(b"Filter");
+        stream.set_content(content);
+
+        if level == 0 {
+            continue;
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(&stream.content)?;
+        let compressed = encoder.finish()?;
+        if compressed.len() + 19 < stream.content.len() {
+            stream.dict.set("Filter", "FlateDecode");
+            stream.set_content(compressed);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer};
+
+use pdf_postprocess::acroform::FieldClash;
+use pdf_postprocess::color_profile::ColorProfile;
+use pdf_postprocess::encryption::Permission;
+use pdf_postprocess::page_numbers::PageNumberPosition;
+use pdf_postprocess::page_size::{Orientation, SizeMatch};
+use pdf_postprocess::pipeline::SplitBy;
+use pdf_postprocess::sort::SortOrder;
+use pdf_postprocess::{Background, DuplicateTitles, PageBox, PdfVersion};
+
+/// Name of the config file picked up from the working directory.
+pub const CONFIG_FILE_NAME: &str = "pdf-postprocess.toml";
+
+/// Settings read from a `pdf-postprocess.toml`, mirroring the command line
+/// options. Options given on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// A single directory, or a list of them merged in order like several
+    /// `SVG_DIR`s on the command line.
+    #[serde(deserialize_with = "one_or_many")]
+    pub svg_dir: Option<Vec<PathBuf>>,
+    pub manifest: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub split_by: Option<SplitBy>,
+    pub include_pdfs: Option<bool>,
+    pub strict: Option<bool>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub sort: Option<SortOrder>,
+    /// Same format as `--pages`, e.g. `"1-5,8,10-"`.
+    pub pages: Option<String>,
+    pub autocrop: Option<f32>,
+    pub autocrop_box: Option<PageBox>,
+    pub page_size_from_first: Option<SizeMatch>,
+    /// Same format as `--page-size`, e.g. `"a4"`.
+    pub page_size: Option<String>,
+    pub orientation: Option<Orientation>,
+    /// Same format as `--page-size`, e.g. `"a4"`.
+    pub default_size: Option<String>,
+    /// Same format as `--rotate`, e.g. `["3:90"]`.
+    pub rotate: Option<Vec<String>>,
+    /// Same format as `--page-labels`, e.g. `"roman:1-3,decimal:4-"`.
+    pub page_labels: Option<String>,
+    pub background: Option<Background>,
+    pub heading_bookmarks: Option<bool>,
+    pub form_field_clash: Option<FieldClash>,
+    pub continuous: Option<bool>,
+    pub duplex_align: Option<bool>,
+    pub warn_duplicates: Option<bool>,
+    pub warn_blank: Option<bool>,
+    pub drop_blank: Option<bool>,
+    pub embed_sources: Option<bool>,
+    pub preserve_sizes: Option<bool>,
+    pub base: Option<PathBuf>,
+    pub insert_at: Option<u32>,
+    pub jobs: Option<u16>,
+    /// Same format as `--deadline`, e.g. `"90s"`.
+    pub deadline: Option<String>,
+    /// Same format as `--timeout`, e.g. `"30s"`.
+    pub timeout: Option<String>,
+    pub page_tree_fanout: Option<u16>,
+    pub precision: Option<u8>,
+    pub thumbnails: Option<u32>,
+    pub max_image_size: Option<u32>,
+    pub max_image_pixels: Option<u64>,
+    pub dpi: Option<u32>,
+    pub outline_text: Option<bool>,
+    pub preserve_layers: Option<bool>,
+    pub font_dir: Option<Vec<PathBuf>>,
+    pub no_system_fonts: Option<bool>,
+    /// Same format as `--font-family`, e.g. `["sans:Noto Sans"]`.
+    pub font_family: Option<Vec<String>>,
+    pub lang: Option<Vec<String>>,
+    pub validate: Option<bool>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub encrypt_password: Option<String>,
+    pub owner_password: Option<String>,
+    pub permissions: Option<Vec<Permission>>,
+    pub draft: Option<bool>,
+    pub watermark: Option<String>,
+    pub watermark_opacity: Option<f32>,
+    pub watermark_rotation: Option<f32>,
+    pub toc: Option<bool>,
+    pub margin: Option<f32>,
+    pub bleed: Option<f32>,
+    /// Same format as `--watermark-color`, e.g. `"#c00000"`.
+    pub watermark_color: Option<String>,
+    pub page_numbers: Option<bool>,
+    pub page_number_position: Option<PageNumberPosition>,
+    pub page_number_format: Option<String>,
+    pub duplicate_titles: Option<DuplicateTitles>,
+    pub compression_level: Option<u32>,
+    pub dedup_images: Option<bool>,
+    pub no_compress: Option<bool>,
+    pub deterministic: Option<bool>,
+    pub pdf_version: Option<PdfVersion>,
+    /// `"sRGB"` or the path of an ICC profile.
+    pub color_profile: Option<ColorProfile>,
+    pub linearize: Option<bool>,
+    pub preserve_source_trees: Option<bool>,
+    pub preserve_outlines: Option<bool>,
+    pub report_json: Option<PathBuf>,
+    pub keep_intermediate: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    pub progress: Option<bool>,
+    pub skip_errors: Option<bool>,
+}
+
+impl Config {
+    /// Reads the config file at `path`. Relative paths inside it are resolved
+    /// against the directory containing the file.
+    pub fn load(path: &Path) -> Result<Config> {
+        let content =
+            read_to_string(path).with_context(|| format!("Cannot read config file {:?}", path))?;
+        let mut config: Config =
+            toml::from_str(&content).with_context(|| format!("Invalid config file {:?}", path))?;
+
+        if config.insert_at == Some(0) {
+            return Err(anyhow!(
+                "insert-at in {:?} is 1-based and must be at least 1.",
+                path
+            ));
+        }
+        if config.jobs == Some(0) {
+            return Err(anyhow!("jobs in {:?} must be at least 1.", path));
+        }
+        if config.page_tree_fanout.is_some_and(|fanout| fanout < 2) {
+            return Err(anyhow!(
+                "page-tree-fanout in {:?} must be at least 2.",
+                path
+            ));
+        }
+        if config.precision.is_some_and(|precision| precision > 6) {
+            return Err(anyhow!("precision in {:?} must be between 0 and 6.", path));
+        }
+
+        if config
+            .thumbnails
+            .is_some_and(|size| !(1..=1024).contains(&size))
+        {
+            return Err(anyhow!(
+                "thumbnails in {:?} must be between 1 and 1024.",
+                path
+            ));
+        }
+
+        if config.dpi.is_some_and(|dpi| !(1..=2400).contains(&dpi)) {
+            return Err(anyhow!("dpi in {:?} must be between 1 and 2400.", path));
+        }
+
+        if config.max_image_size == Some(0) {
+            return Err(anyhow!("max-image-size in {:?} must be at least 1.", path));
+        }
+
+        if config.max_image_pixels == Some(0) {
+            return Err(anyhow!(
+                "max-image-pixels in {:?} must be at least 1.",
+                path
+            ));
+        }
+
+        if config.compression_level.is_some_and(|level| level > 9) {
+            return Err(anyhow!(
+                "compression-level in {:?} must be between 0 and 9.",
+                path
+            ));
+        }
+
+        let config_dir = path.parent().unwrap_or(Path::new(""));
+        for option in [
+            &mut config.manifest,
+            &mut config.output,
+            &mut config.base,
+            &mut config.report_json,
+            &mut config.keep_intermediate,
+            &mut config.cache_dir,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(config.svg_dir.iter_mut().flatten())
+        .chain(config.font_dir.iter_mut().flatten())
+        .chain(match &mut config.color_profile {
+            Some(ColorProfile::File(path)) => Some(path),
+            _ => None,
+        }) {
+            *option = config_dir.join(&*option);
+        }
+
+        Ok(config)
+    }
+
+    /// Looks for a config file in the working directory.
+    pub fn find() -> Option<PathBuf> {
+        let path = PathBuf::from(CONFIG_FILE_NAME);
+        path.is_file().then_some(path)
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<PathBuf>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(Some(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    }))
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::page::{inherited_page_attribute, media_box, page_box};
+
+/// A page turned into a Form XObject, along with where it ended up.
+struct PlacedPage {
+    xobject_id: ObjectId,
+    visible_box: [f32; 4],
+    left: f32,
+    bottom: f32,
+}
+
+/// Stacks all pages of `doc` vertically onto a single page.
+///
+/// The resulting page is as wide as the widest page and as tall as all pages
+/// combined. Narrower pages are centered horizontally. Every page becomes a
+/// Form XObject so its content and resources stay isolated from the others,
+/// and destinations pointing at the original pages are redirected to the
+/// top of the corresponding region of the new page. Annotations such as
+/// links and form widgets are moved onto the new page, over their content.
+pub fn make_continuous(doc: &mut Document) -> Result<()> {
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+    if page_ids.is_empty() {
+        return Ok(());
+    }
+
+    let root_pages_id = doc
+        .catalog()?
+        .get(b"Pages")
+        .and_then(Object::as_reference)?;
+
+    let visible_boxes = page_ids
+        .iter()
+        .map(|page_id| visible_box(doc, *page_id))
+        .collect::<Result<Vec<_>>>()?;
+
+    let width = visible_boxes
+        .iter()
+        .map(|[left, _, right, _]| right - left)
+        .fold(0.0, f32::max);
+    let height: f32 = visible_boxes
+        .iter()
+        .map(|[_, bottom, _, top]| top - bottom)
+        .sum();
+
+    let mut placed = Vec::with_capacity(page_ids.len());
+    let mut cursor = height;
+
+    for (page_id, visible_box) in page_ids.iter().zip(visible_boxes) {
+        let [left, bottom, right, top] = visible_box;
+        cursor -= top - bottom;
+
+        let xobject_id = page_to_xobject(doc, *page_id, visible_box)?;
+        placed.push(PlacedPage {
+            xobject_id,
+            visible_box,
+            left: (width - (right - left)) / 2.0,
+            bottom: cursor,
+        });
+    }
+
+    let mut content = Vec::new();
+    let mut xobjects = Dictionary::new();
+
+    for (index, page) in placed.iter().enumerate() {
+        let name = format!("P{}", index + 1);
+        let [left, bottom, _, _] = page.visible_box;
+
+        content.extend(
+            format!(
+                "q 1 0 0 1 {} {} cm /{} Do Q\n",
+                page.left - left,
+                page.bottom - bottom,
+                name
+            )
+            .into_bytes(),
+        );
+        xobjects.set(name, page.xobject_id);
+    }
+
+    let mut content_stream = Stream::new(Dictionary::new(), content);
+    let _ = content_stream.compress();
+    let content_id = doc.add_object(content_stream);
+
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => root_pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        "Resources" => dictionary! { "XObject" => xobjects },
+        "Contents" => content_id,
+    });
+
+    // Point destinations at the top of each page's region on the new page.
+    let targets: HashMap<ObjectId, f32> = page_ids
+        .iter()
+        .zip(&placed)
+        .map(|(old_id, page)| {
+            let [_, bottom, _, top] = page.visible_box;
+            (*old_id, page.bottom + (top - bottom))
+        })
+        .collect();
+    redirect_destinations(doc, &targets, page_id);
+
+    // Links and form widgets move along with the content of their page.
+    let mut annotations = Vec::new();
+    for (old_id, page) in page_ids.iter().zip(&placed) {
+        let [left, bottom, _, _] = page.visible_box;
+        annotations.extend(move_annotations(
+            doc,
+            *old_id,
+            page_id,
+            page.left - left,
+            page.bottom - bottom,
+        ));
+    }
+    if !annotations.is_empty() {
+        doc.get_dictionary_mut(page_id)?.set("Annots", annotations);
+    }
+
+    for old_id in &page_ids {
+        doc.objects.remove(old_id);
+    }
+
+    doc.objects.retain(|id, object| {
+        *id == root_pages_id || object.type_name().map_or(true, |name| name != "Pages")
+    });
+
+    let root_pages = doc.get_dictionary_mut(root_pages_id)?;
+    root_pages.set("Kids", vec![Object::Reference(page_id)]);
+    root_pages.set("Count", 1);
+
+    Ok(())
+}
+
+/// Takes the annotations of the page `old_id` over to `new_page`, moving
+/// their `/Rect` and `/QuadPoints` by `(dx, dy)`, and returns them for its
+/// `/Annots`.
+fn move_annotations(
+    doc: &mut Document,
+    old_id: ObjectId,
+    new_page: ObjectId,
+    dx: f32,
+    dy: f32,
+) -> Vec<Object> {
+    let annotations = match doc
+        .get_dictionary(old_id)
+        .and_then(|page| page.get(b"Annots"))
+        .and_then(|annots| doc.dereference(annots))
+        .and_then(|(_, annots)| annots.as_array())
+    {
+        Ok(annotations) => annotations.clone(),
+        Err(_) => return Vec::new(),
+    };
+
+    let translate = |numbers: &mut Vec<Object>| {
+        for (index, number) in numbers.iter_mut().enumerate() {
+            if let Ok(value) = number.as_float() {
+                let offset = if index % 2 == 0 { dx } else { dy };
+                *number = Object::Real(value + offset);
+            }
+        }
+    };
+
+    let mut moved = Vec::with_capacity(annotations.len());
+    for mut annotation in annotations {
+        let dict = match &mut annotation {
+            Object::Reference(id) => match doc.get_dictionary_mut(*id) {
+                Ok(dict) => dict,
+                Err(_) => continue,
+            },
+            Object::Dictionary(dict) => dict,
+            _ => continue,
+        };
+        for key in [b"Rect".as_slice(), b"QuadPoints"] {
+            if let Ok(Object::Array(numbers)) = dict.get_mut(key) {
+                translate(numbers);
+            }
+        }
+        dict.set("P", new_page);
+        moved.push(annotation);
+    }
+
+    moved
+}
+
+/// The part of a page viewers show: its CropBox, or MediaBox if it has none.
+fn visible_box(doc: &Document, page_id: ObjectId) -> Result<[f32; 4]> {
+    match page_box(doc, page_id, b"CropBox")? {
+        Some(crop_box) => Ok(crop_box),
+        None => media_box(doc, page_id),
+    }
+}
+
+/// Wraps a page's content and resources into a Form XObject clipped to
+/// `visible_box`, removing the page's own content streams.
+pub(crate) fn page_to_xobject(
+    doc: &mut Document,
+    page_id: ObjectId,
+    visible_box: [f32; 4],
+) -> Result<ObjectId> {
+    let content = doc.get_page_content(page_id)?;
+    let content_ids = doc.get_page_contents(page_id);
+
+    let mut dict = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Form",
+        "BBox" => visible_box.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>(),
+    };
+
+    if let Some(resources) = inherited_page_attribute(doc, page_id, b"Resources") {
+        dict.set("Resources", resources);
+    }
+
+    if let Ok(group) = doc.get_dictionary(page_id)?.get(b"Group") {
+        dict.set("Group", group.clone());
+    }
+
+    for content_id in content_ids {
+        doc.objects.remove(&content_id);
+    }
+
+    let mut stream = Stream::new(dict, content);
+    let _ = stream.compress();
+
+    Ok(doc.add_object(stream))
+}
+
+/// Rewrites explicit destinations (`[page /Fit]` and friends) pointing at one
+/// of `targets` into `[new_page /XYZ null top null]`.
+fn redirect_destinations(doc: &mut Document, targets: &HashMap<ObjectId, f32>, new_page: ObjectId) {
+    let redirect = |array: &mut Vec<Object>| {
+        let top = match array.first().and_then(|page| page.as_reference().ok()) {
+            Some(page) => match targets.get(&page) {
+                Some(top) => *top,
+                None => return,
+            },
+            None => return,
+        };
+
+        if !matches!(array.get(1), Some(Object::Name(_))) {
+            return;
+        }
+
+        *array = vec![
+            Object::Reference(new_page),
+            Object::Name(b"XYZ".to_vec()),
+            Object::Null,
+            Object::Real(top),
+            Object::Null,
+        ];
+    };
+
+    for object in doc.objects.values_mut() {
+        match object {
+            Object::Array(array) => redirect(array),
+            Object::Dictionary(dict) => {
+                for (_, value) in dict.iter_mut() {
+                    if let Object::Array(array) = value {
+                        redirect(array);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merge_pdfs_with, render_page, MergeOptions, RenderOptions};
+
+    fn linked_page(height: u32) -> Vec<u8> {
+        render_page(
+            &format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="{}"><a href="https://example.com"><rect x="10" y="10" width="20" height="20"/></a></svg>"#,
+                height
+            ),
+            &RenderOptions::default(),
+        )
+        .unwrap()
+        .pdf
+    }
+
+    fn rect(doc: &Document, annotation: &Object) -> Vec<f32> {
+        doc.dereference(annotation)
+            .unwrap()
+            .1
+            .as_dict()
+            .unwrap()
+            .get(b"Rect")
+            .and_then(Object::as_array)
+            .unwrap()
+            .iter()
+            .map(|number| number.as_float().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn moves_links_onto_the_continuous_page() {
+        let (top, bottom) = (linked_page(100), linked_page(50));
+        let mut doc = merge_pdfs_with(vec![&top, &bottom], &MergeOptions::default()).unwrap();
+        let rects_before: Vec<Vec<f32>> = doc
+            .page_iter()
+            .map(|page_id| {
+                let annots = doc
+                    .get_dictionary(page_id)
+                    .unwrap()
+                    .get(b"Annots")
+                    .and_then(Object::as_array)
+                    .unwrap();
+                rect(&doc, &annots[0])
+            })
+            .collect();
+
+        make_continuous(&mut doc).unwrap();
+
+        let page_id = doc.page_iter().next().unwrap();
+        let annots = doc
+            .get_dictionary(page_id)
+            .unwrap()
+            .get(b"Annots")
+            .and_then(Object::as_array)
+            .unwrap()
+            .clone();
+        assert_eq!(annots.len(), 2);
+        // The first page is stacked above the 50 points of the second one.
+        let moved: Vec<Vec<f32>> = annots.iter().map(|annot| rect(&doc, annot)).collect();
+        let expected_top: Vec<f32> = rects_before[0]
+            .iter()
+            .enumerate()
+            .map(|(index, value)| if index % 2 == 0 { *value } else { value + 50.0 })
+            .collect();
+        assert_eq!(moved, [expected_top, rects_before[1].clone()]);
+        for annot in &annots {
+            let annot = doc.dereference(annot).unwrap().1.as_dict().unwrap();
+            assert_eq!(
+                annot.get(b"P").and_then(Object::as_reference).unwrap(),
+                page_id
+            );
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+/// Parses durations such as `90`, `90s`, `1500ms`, `5m` or `1h`. A bare
+/// number is read as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", value))?;
+    let seconds = match unit.trim() {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" | "min" => amount * 60.0,
+        "h" => amount * 3600.0,
+        unit => {
+            return Err(format!(
+                "unknown duration unit {:?}, use ms, s, m or h",
+                unit
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+/// Runs `render` for every index in `0..count` on the rayon pool, handing out
+/// indices in order and starting no new work once `deadline` has passed.
+///
+/// Renders already running when the deadline passes are allowed to finish.
+/// Because work is started in order, the completed results form a prefix
+/// up to the handful that were still in flight.
+pub fn render_in_order<T, F>(count: usize, deadline: Instant, render: F) -> Vec<Option<T>>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<T>>> = (0..count).map(|_| Mutex::new(None)).collect();
+
+    rayon::scope(|scope| {
+        for _ in 0..rayon::current_num_threads() {
+            scope.spawn(|_| loop {
+                if Instant::now() >= deadline {
+                    break;
+                }
+
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= count {
+                    break;
+                }
+
+                let result = render(index);
+                *results[index].lock().unwrap() = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap())
+        .collect()
+}
+
+/// Number of threads whose work timed out and is still running, see
+/// [`run_with_timeout`].
+static ABANDONED: Mutex<usize> = Mutex::new(0);
+/// Signalled whenever an abandoned thread finishes.
+static ABANDONED_FINISHED: Condvar = Condvar::new();
+
+fn abandoned_count() -> MutexGuard<'static, usize> {
+    ABANDONED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Held by the thread running the work, to take it off [`ABANDONED`] once
+/// it finished or panicked after timing out.
+struct Running {
+    abandoned: Arc<AtomicBool>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut count = abandoned_count();
+        if self.abandoned.load(Ordering::Relaxed) {
+            *count -= 1;
+            ABANDONED_FINISHED.notify_all();
+        }
+    }
+}
+
+/// Runs `work` on a thread of its own and waits at most `timeout` for it,
+/// returning `None` if it didn't finish in time. `what` names the work in
+/// errors and as the name of the thread, e.g. `rendering SVG file "a.svg"`.
+///
+/// Rust can't stop a thread from the outside, so work that times out keeps
+/// running, and using CPU, in the background until it completes on its own.
+/// Its result is then dropped and the thread exits. At most `max_abandoned`
+/// such threads run at once: once that many are left behind, new work waits
+/// for one of them to finish before it starts, so a batch of runaway pages
+/// can't pile up threads without bound. `max_abandoned` must be at least 1.
+pub fn run_with_timeout<T, F>(
+    what: String,
+    timeout: Duration,
+    max_abandoned: usize,
+    work: F,
+) -> Result<Option<T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let mut count = abandoned_count();
+    while *count >= max_abandoned {
+        count = ABANDONED_FINISHED
+            .wait(count)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+    drop(count);
+
+    let abandoned = Arc::new(AtomicBool::new(false));
+    let running = Running {
+        abandoned: Arc::clone(&abandoned),
+    };
+    // A pool of its own, as parallel work inside `work`, e.g. lopdf parsing
+    // a PDF, would otherwise wait for threads of the global pool that may
+    // all be blocked right here. Panics end the job instead of the process.
+    let thread_name = what.clone();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .thread_name(move |_| thread_name.clone())
+        .panic_handler(|_| {})
+        .build()?;
+    let (sender, receiver) = mpsc::channel();
+    pool.spawn(move || {
+        let result = work();
+        // Checked under the lock, so the result is either sent or the
+        // thread counted as abandoned, never both.
+        let _count = abandoned_count();
+        if !running.abandoned.load(Ordering::Relaxed) {
+            let _ = sender.send(result);
+        }
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result.map(Some),
+        Err(RecvTimeoutError::Timeout) => {
+            let mut count = abandoned_count();
+            // The work may have finished while the lock was taken.
+            match receiver.try_recv() {
+                Ok(result) => result.map(Some),
+                Err(TryRecvError::Empty) => {
+                    abandoned.store(true, Ordering::Relaxed);
+                    *count += 1;
+                    Ok(None)
+                }
+                Err(TryRecvError::Disconnected) => Err(anyhow!("The thread {} panicked", what)),
+            }
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(anyhow!("The thread {} panicked", what)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn parses_durations_with_and_without_units() {
+        for (value, expected) in [
+            ("90", Duration::from_secs(90)),
+            ("90s", Duration::from_secs(90)),
+            ("1500ms", Duration::from_millis(1500)),
+            ("5m", Duration::from_secs(300)),
+            ("2 min", Duration::from_secs(120)),
+            ("1.5h", Duration::from_secs(5400)),
+            (" 0 ", Duration::ZERO),
+        ] {
+            assert_eq!(parse_duration(value), Ok(expected), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for value in ["", "s", "-5", "5d", "1e3", "1.2.3s"] {
+            assert!(parse_duration(value).is_err(), "{:?} was accepted", value);
+        }
+        assert_eq!(
+            parse_duration("5d").unwrap_err(),
+            "unknown duration unit \"d\", use ms, s, m or h"
+        );
+    }
+
+    #[test]
+    fn runs_parallel_work_while_the_pool_waits() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+
+        let sum = pool.install(|| {
+            run_with_timeout("summing".to_string(), Duration::from_secs(30), 4, || {
+                Ok((1..=100).into_par_iter().sum::<u32>())
+            })
+        });
+
+        assert_eq!(sum.unwrap(), Some(5050));
+    }
+
+    #[test]
+    fn waits_for_abandoned_work_before_starting_more() {
+        let start = Instant::now();
+        let slow = run_with_timeout("sleeping".to_string(), Duration::from_millis(10), 1, || {
+            thread::sleep(Duration::from_millis(300));
+            Ok(())
+        });
+        assert!(slow.unwrap().is_none());
+
+        let fast = run_with_timeout("adding".to_string(), Duration::from_secs(30), 1, || {
+            Ok(1 + 1)
+        });
+        assert_eq!(fast.unwrap(), Some(2));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        let panicked = run_with_timeout::<(), _>(
+            "rendering a.svg".to_string(),
+            Duration::from_secs(30),
+            1,
+            || panic!("out of luck"),
+        );
+        assert_eq!(
+            panicked.unwrap_err().to_string(),
+            "The thread rendering a.svg panicked"
+        );
+    }
+}
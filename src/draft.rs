@@ -0,0 +1,75 @@
+use anyhow::Result;
+use lopdf::{dictionary, Document};
+
+use crate::page::{add_page_resources, media_box, page_box, wrap_page_contents};
+
+/// Banner stamped across every page. `\x97` is the em dash in WinAnsiEncoding.
+const DRAFT_TEXT: &[u8] = b"DRAFT \x97 DO NOT DISTRIBUTE";
+
+/// Advance width of [`DRAFT_TEXT`] in Helvetica-Bold, in text space units
+/// per point of font size.
+const DRAFT_TEXT_WIDTH: f32 = 15.111;
+
+/// Cap height of Helvetica-Bold, relative to the font size.
+const CAP_HEIGHT: f32 = 0.718;
+
+/// Resource names used for the stamp, unlikely to clash with the page's own.
+const FONT_NAME: &str = "DraftStampFont";
+const GRAPHICS_STATE_NAME: &str = "DraftStampGS";
+
+/// Stamps a translucent red "DRAFT — DO NOT DISTRIBUTE" diagonally across the
+/// visible area of every page of `doc`.
+///
+/// The banner uses the standard Helvetica-Bold font, so nothing is embedded,
+/// and is drawn on top of the existing content, which is left unchanged.
+pub fn stamp_draft(doc: &mut Document) -> Result<()> {
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica-Bold",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let graphics_state_id = doc.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => 0.25,
+    });
+
+    for page_id in doc.page_iter().collect::<Vec<_>>() {
+        add_page_resources(
+            doc,
+            page_id,
+            &[
+                (b"Font", FONT_NAME, font_id),
+                (b"ExtGState", GRAPHICS_STATE_NAME, graphics_state_id),
+            ],
+        )?;
+
+        let [left, bottom, right, top] = match page_box(doc, page_id, b"CropBox")? {
+            Some(crop_box) => crop_box,
+            None => media_box(doc, page_id)?,
+        };
+        let (width, height) = (right - left, top - bottom);
+
+        // Run the text along the diagonal, filling most of it.
+        let diagonal = width.hypot(height);
+        let (sin, cos) = (height / diagonal, width / diagonal);
+        let size = diagonal * 0.8 / DRAFT_TEXT_WIDTH;
+
+        // Start the baseline so the text ends up centered on the page.
+        let (half_width, half_height) = (DRAFT_TEXT_WIDTH * size / 2.0, CAP_HEIGHT * size / 2.0);
+        let x = left + width / 2.0 - cos * half_width + sin * half_height;
+        let y = bottom + height / 2.0 - sin * half_width - cos * half_height;
+
+        let mut stamp = format!(
+            "Q\nq /{} gs 0.85 0 0 rg BT /{} {} Tf {} {} {} {} {} {} Tm (",
+            GRAPHICS_STATE_NAME, FONT_NAME, size, cos, sin, -sin, cos, x, y
+        )
+        .into_bytes();
+        stamp.extend_from_slice(DRAFT_TEXT);
+        stamp.extend_from_slice(b") Tj ET Q\n");
+
+        wrap_page_contents(doc, page_id, b"q\n".to_vec(), stamp)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,372 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use lopdf::{dictionary, Document, Object, ObjectId, StringFormat};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+
+/// Padding the standard security handler appends to passwords shorter than
+/// 32 bytes.
+const PASSWORD_PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// Length of the encryption key in bytes, the 128 bits of an MD5 digest.
+const KEY_LENGTH: usize = 16;
+
+/// Bits of `/P` that revision 3 requires to be set: 7, 8 and 13 to 32.
+const RESERVED_PERMISSION_BITS: u32 = 0xFFFF_F0C0;
+
+/// Bit 10 of `/P`, extracting text for accessibility tools, which is always
+/// granted so screen readers keep working.
+const ACCESSIBILITY_PERMISSION_BIT: u32 = 1 << 9;
+
+/// Something readers allow users who only know the user password to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    /// Print, in full quality
+    Print,
+    /// Change the page content
+    Modify,
+    /// Copy text and images
+    Copy,
+    /// Add or change annotations, and fill in forms
+    Annotate,
+    /// Fill in forms
+    FillForms,
+    /// Insert, delete and rotate pages, and create bookmarks
+    Assemble,
+}
+
+impl Permission {
+    /// Bits this permission sets in `/P`, counting from 1 as the PDF
+    /// specification does.
+    fn bits(self) -> u32 {
+        match self {
+            Permission::Print => 1 << 2 | 1 << 11,
+            Permission::Modify => 1 << 3,
+            Permission::Copy => 1 << 4,
+            Permission::Annotate => 1 << 5,
+            Permission::FillForms => 1 << 8,
+            Permission::Assemble => 1 << 10,
+        }
+    }
+}
+
+/// Passwords and permissions the merged document is protected with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encryption {
+    /// Password needed to open the document.
+    pub user_password: String,
+    /// Password granting full access regardless of
+    /// [`Encryption::permissions`]. A random one nobody knows is used if not
+    /// set, so the permissions can't be lifted.
+    pub owner_password: Option<String>,
+    pub permissions: Vec<Permission>,
+}
+
+/// Encrypts every string and stream of `doc` with the standard security
+/// handler, revision 3: RC4 with a 128-bit key, which every PDF 1.4 reader
+/// supports. lopdf's `Document::decrypt` accepts the user password, but only
+/// decrypts strings and streams that are objects of their own, leaving the
+/// strings inside dictionaries encrypted.
+///
+/// `file_id` becomes the document's `/ID` and goes into the key, see
+/// [`unique_file_id`] and [`content_file_id`].
+///
+/// Has to run last, after compressing and anything else that reads or
+/// changes content, right before the document is saved. Passwords are limited
+/// to Latin-1, which is what readers pass on for the characters typed in.
+pub fn encrypt(
+    doc: &mut Document,
+    encryption: &Encryption,
+    file_id: [u8; KEY_LENGTH],
+) -> Result<()> {
+    let user_password = pad_password(&encryption.user_password)?;
+    let owner_password = match &encryption.owner_password {
+        Some(owner_password) => pad_password(owner_password)?,
+        None => {
+            let random = Md5::new()
+                .chain_update(file_id)
+                .chain_update(encryption.user_password.as_bytes())
+                .finalize();
+            pad_password(&format!("{:x}", random))?
+        }
+    };
+    let permissions = encryption.permissions.iter().fold(
+        RESERVED_PERMISSION_BITS | ACCESSIBILITY_PERMISSION_BIT,
+        |p, permission| p | permission.bits(),
+    );
+
+    // Algorithm 3 of the specification, the owner password entry.
+    let mut owner_key = Md5::digest(owner_password).to_vec();
+    for _ in 0..50 {
+        owner_key = Md5::digest(&owner_key).to_vec();
+    }
+    let owner_entry = rc4_rounds(&owner_key, &user_password);
+
+    // Algorithm 2, the key everything is encrypted with.
+    let mut key = Md5::new()
+        .chain_update(user_password)
+        .chain_update(&owner_entry)
+        .chain_update(permissions.to_le_bytes())
+        .chain_update(file_id)
+        .finalize()
+        .to_vec();
+    for _ in 0..50 {
+        key = Md5::digest(&key).to_vec();
+    }
+
+    // Algorithm 5, the user password entry, padded to 32 bytes.
+    let mut user_entry = rc4_rounds(
+        &key,
+        &Md5::new()
+            .chain_update(PASSWORD_PADDING)
+            .chain_update(file_id)
+            .finalize(),
+    );
+    user_entry.extend_from_slice(&PASSWORD_PADDING[..16]);
+
+    for (id, object) in doc.objects.iter_mut() {
+        encrypt_object(&key, *id, object);
+    }
+
+    let encrypt_id = doc.add_object(dictionary! {
+        "Filter" => "Standard",
+        "V" => 2,
+        "R" => 3,
+        "Length" => (KEY_LENGTH * 8) as i64,
+        "O" => Object::String(owner_entry, StringFormat::Hexadecimal),
+        "U" => Object::String(user_entry, StringFormat::Hexadecimal),
+        "P" => permissions as i32,
+    });
+    doc.trailer.set("Encrypt", encrypt_id);
+    let id = Object::String(file_id.to_vec(), StringFormat::Hexadecimal);
+    doc.trailer.set("ID", vec![id.clone(), id]);
+
+    Ok(())
+}
+
+/// A file identifier unique to this run.
+pub fn unique_file_id(doc: &Document) -> [u8; KEY_LENGTH] {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_nanos());
+
+    Md5::new()
+        .chain_update(now.to_le_bytes())
+        .chain_update(std::process::id().to_le_bytes())
+        .chain_update(doc.objects.len().to_le_bytes())
+        .finalize()
+        .into()
+}
+
+/// A file identifier derived from the content of `doc` alone, so the same
+/// document is encrypted to the same bytes every time.
+pub fn content_file_id(doc: &mut Document) -> Result<[u8; KEY_LENGTH]> {
+    let mut content = Vec::new();
+    doc.save_to(&mut content)?;
+
+    Ok(Md5::digest(content).into())
+}
+
+/// Pads or truncates `password` to the 32 bytes the key derivation uses.
+fn pad_password(password: &str) -> Result<[u8; 32]> {
+    let mut padded = [0; 32];
+    let bytes = password
+        .chars()
+        .map(|c| u8::try_from(c).map_err(|_| anyhow!("Passwords can only use Latin-1 characters")))
+        .collect::<Result<Vec<u8>>>()?;
+    let length = bytes.len().min(32);
+
+    padded[..length].copy_from_slice(&bytes[..length]);
+    padded[length..].copy_from_slice(&PASSWORD_PADDING[..32 - length]);
+
+    Ok(padded)
+}
+
+/// Encrypts `data` with `key`, then 19 more times with every byte of the key
+/// XORed with the round number, as revision 3 does for the password entries.
+fn rc4_rounds(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut data = rc4(key, data);
+    for round in 1..=19 {
+        let round_key: Vec<u8> = key.iter().map(|byte| byte ^ round).collect();
+        data = rc4(&round_key, &data);
+    }
+
+    data
+}
+
+/// Encrypts the strings and stream content of a single indirect object with
+/// the key derived from the document key and the object's id.
+fn encrypt_object(key: &[u8], id: ObjectId, object: &mut Object) {
+    let object_key = Md5::new()
+        .chain_update(key)
+        .chain_update(&id.0.to_le_bytes()[..3])
+        .chain_update(&id.1.to_le_bytes()[..2])
+        .finalize();
+
+    encrypt_strings(&object_key, object);
+}
+
+fn encrypt_strings(key: &[u8], object: &mut Object) {
+    match object {
+        Object::String(content, _) => *content = rc4(key, content),
+        Object::Array(array) => {
+            for item in array {
+                encrypt_strings(key, item);
+            }
+        }
+        Object::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                encrypt_strings(key, value);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                encrypt_strings(key, value);
+            }
+            stream.content = rc4(key, &stream.content);
+        }
+        _ => {}
+    }
+}
+
+/// The RC4 stream cipher, which encrypts and decrypts alike.
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j: u8 = 0;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, usize::from(j));
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[usize::from(i)]);
+            state.swap(usize::from(i), usize::from(j));
+            let index = state[usize::from(i)].wrapping_add(state[usize::from(j)]);
+            byte ^ state[usize::from(index)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::Stream;
+
+    use super::*;
+
+    const FILE_ID: [u8; KEY_LENGTH] = *b"0123456789abcdef";
+
+    fn encryption() -> Encryption {
+        Encryption {
+            user_password: "user".to_string(),
+            owner_password: Some("owner".to_string()),
+            permissions: vec![Permission::Print],
+        }
+    }
+
+    /// A document with a stream, a string of its own and a string inside a
+    /// dictionary, returning their ids in that order.
+    fn document() -> (Document, [ObjectId; 3]) {
+        let mut doc = Document::with_version("1.4");
+        let stream_id = doc.add_object(Stream::new(dictionary! {}, b"0 0 m 10 10 l S".to_vec()));
+        let string_id = doc.add_object(Object::string_literal("standalone"));
+        let dict_id = doc.add_object(dictionary! { "URI" => Object::string_literal("nested") });
+
+        (doc, [stream_id, string_id, dict_id])
+    }
+
+    fn encrypt_dict(doc: &Document) -> &lopdf::Dictionary {
+        doc.get_encrypted().unwrap()
+    }
+
+    #[test]
+    fn rc4_matches_known_vectors() {
+        for (key, plain, cipher) in [
+            ("Key", "Plaintext", "BBF316E8D940AF0AD3"),
+            ("Wiki", "pedia", "1021BF0420"),
+            ("Secret", "Attack at dawn", "45A01F645FC35B383552544B9BF5"),
+        ] {
+            let encrypted: String = rc4(key.as_bytes(), plain.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            assert_eq!(encrypted, cipher, "RC4 with key {:?}", key);
+        }
+    }
+
+    #[test]
+    fn owner_entry_decrypts_to_the_user_password() {
+        let (mut doc, _) = document();
+        encrypt(&mut doc, &encryption(), FILE_ID).unwrap();
+        let owner_entry = encrypt_dict(&doc).get(b"O").unwrap().as_str().unwrap();
+
+        // Algorithm 7 of the specification, undoing the 20 rounds in reverse.
+        let mut owner_key = Md5::digest(pad_password("owner").unwrap()).to_vec();
+        for _ in 0..50 {
+            owner_key = Md5::digest(&owner_key).to_vec();
+        }
+        let mut user_password = owner_entry.to_vec();
+        for round in (0..=19).rev() {
+            let round_key: Vec<u8> = owner_key.iter().map(|byte| byte ^ round).collect();
+            user_password = rc4(&round_key, &user_password);
+        }
+
+        assert_eq!(user_password, pad_password("user").unwrap());
+    }
+
+    #[test]
+    fn user_password_decrypts_the_document() {
+        let (mut doc, [stream_id, string_id, dict_id]) = document();
+        encrypt(&mut doc, &encryption(), FILE_ID).unwrap();
+        let nested = doc
+            .get_dictionary(dict_id)
+            .unwrap()
+            .get(b"URI")
+            .unwrap()
+            .clone();
+        assert_ne!(nested.as_str().unwrap(), b"nested");
+
+        // lopdf checks the password against `/U` with its own implementation
+        // of algorithms 2 and 5.
+        assert!(lopdf::encryption::get_encryption_key(&doc, "wrong", true).is_err());
+        let key = lopdf::encryption::get_encryption_key(&doc, "user", true).unwrap();
+        assert_eq!(
+            lopdf::encryption::decrypt_object(&key, dict_id, &nested).unwrap(),
+            b"nested"
+        );
+
+        doc.decrypt("user").unwrap();
+        assert_eq!(
+            doc.get_object(stream_id)
+                .unwrap()
+                .as_stream()
+                .unwrap()
+                .content,
+            b"0 0 m 10 10 l S"
+        );
+        assert_eq!(
+            doc.get_object(string_id).unwrap().as_str().unwrap(),
+            b"standalone"
+        );
+    }
+
+    #[test]
+    fn sets_the_bits_of_the_permissions_granted() {
+        let (mut doc, _) = document();
+        encrypt(&mut doc, &encryption(), FILE_ID).unwrap();
+
+        // The reserved bits, accessibility, and bits 3 and 12 for printing.
+        assert_eq!(
+            encrypt_dict(&doc).get(b"P").unwrap().as_i64().unwrap(),
+            -1340
+        );
+    }
+}
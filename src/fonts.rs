@@ -0,0 +1,229 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use lopdf::{Document, Object, ObjectId};
+use svg2pdf::usvg;
+
+/// Keys of a font descriptor that refer to the embedded font program.
+const FONT_FILE_KEYS: [&[u8]; 3] = [b"FontFile", b"FontFile2", b"FontFile3"];
+
+/// Collapses byte-identical embedded font programs into a single object and
+/// removes the copies, returning how many were removed.
+///
+/// Every rendered page embeds its own subset of the fonts it uses, so pages
+/// using the same glyphs of a font carry identical copies. Font programs are
+/// compared by their stream dictionary and raw, still compressed content;
+/// subsets that differ in any byte are kept apart.
+pub fn dedup_font_files(doc: &mut Document) -> usize {
+    let mut descriptors: Vec<(ObjectId, &'static [u8], ObjectId)> = Vec::new();
+    for (id, object) in &doc.objects {
+        let descriptor = match object.as_dict() {
+            Ok(dict)
+                if object
+                    .type_name()
+                    .is_ok_and(|name| name == "FontDescriptor") =>
+            {
+                dict
+            }
+            _ => continue,
+        };
+        for key in FONT_FILE_KEYS {
+            if let Ok(font_file) = descriptor.get(key).and_then(Object::as_reference) {
+                descriptors.push((*id, key, font_file));
+            }
+        }
+    }
+
+    // Font program ids by the hash of their content, to find candidates
+    // without keeping copies of the fonts around.
+    let mut by_hash: HashMap<u64, Vec<ObjectId>> = HashMap::new();
+    let mut replacements: HashMap<ObjectId, ObjectId> = HashMap::new();
+
+    for (_, _, font_file) in &descriptors {
+        if replacements.contains_key(font_file) {
+            continue;
+        }
+        let stream = match doc.get_object(*font_file).and_then(Object::as_stream) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        stream.content.hash(&mut hasher);
+        let candidates = by_hash.entry(hasher.finish()).or_default();
+
+        let original = candidates.iter().copied().find(|candidate| {
+            *candidate != *font_file
+                && doc
+                    .get_object(*candidate)
+                    .and_then(Object::as_stream)
+                    .is_ok_and(|original| {
+                        original.dict == stream.dict && original.content == stream.content
+                    })
+        });
+        match original {
+            Some(original) => {
+                replacements.insert(*font_file, original);
+            }
+            None if !candidates.contains(font_file) => candidates.push(*font_file),
+            None => {}
+        }
+    }
+
+    for (descriptor, key, font_file) in descriptors {
+        if let Some(original) = replacements.get(&font_file) {
+            if let Ok(descriptor) = doc.get_dictionary_mut(descriptor) {
+                descriptor.set(key, Object::Reference(*original));
+            }
+        }
+    }
+
+    for duplicate in replacements.keys() {
+        doc.objects.remove(duplicate);
+    }
+
+    replacements.len()
+}
+
+/// Which font a [`FontFamily`] chooses, the generic CSS families and the
+/// font of text without a `font-family`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenericFamily {
+    /// Used for text that doesn't set a `font-family`, Times New Roman
+    /// unless chosen.
+    Default,
+    Serif,
+    SansSerif,
+    Monospace,
+    Cursive,
+    Fantasy,
+}
+
+/// The font to use for a generic family, e.g. `sans:Noto Sans` for text set
+/// in `font-family: sans-serif`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontFamily {
+    pub generic: GenericFamily,
+    /// Family name of an installed font or one loaded from
+    /// [`crate::RenderOptions::font_dirs`].
+    pub name: String,
+}
+
+impl FromStr for FontFamily {
+    type Err = String;
+
+    /// Parses `GENERIC:NAME`, `GENERIC` being one of `default`, `serif`,
+    /// `sans` (or `sans-serif`), `monospace` (or `mono`), `cursive` and
+    /// `fantasy`. Quotes around the name are dropped.
+    fn from_str(value: &str) -> Result<FontFamily, String> {
+        let invalid = || {
+            format!(
+                "invalid font family {:?}, expected e.g. sans:\"Noto Sans\" with one of default, serif, sans, monospace, cursive or fantasy",
+                value
+            )
+        };
+        let (generic, name) = value.split_once(':').ok_or_else(invalid)?;
+        let generic = match generic.trim() {
+            "default" => GenericFamily::Default,
+            "serif" => GenericFamily::Serif,
+            "sans" | "sans-serif" => GenericFamily::SansSerif,
+            "monospace" | "mono" => GenericFamily::Monospace,
+            "cursive" => GenericFamily::Cursive,
+            "fantasy" => GenericFamily::Fantasy,
+            _ => return Err(invalid()),
+        };
+        let name = name.trim().trim_matches(|c| c == '"' || c == '\'');
+        if name.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(FontFamily {
+            generic,
+            name: name.to_string(),
+        })
+    }
+}
+
+impl FontFamily {
+    /// Makes usvg use this font for its generic family. Fonts have to be
+    /// loaded into the font database first.
+    pub fn apply(&self, options: &mut usvg::Options) {
+        let name = self.name.clone();
+        match self.generic {
+            GenericFamily::Default => options.font_family = name,
+            GenericFamily::Serif => options.fontdb_mut().set_serif_family(name),
+            GenericFamily::SansSerif => options.fontdb_mut().set_sans_serif_family(name),
+            GenericFamily::Monospace => options.fontdb_mut().set_monospace_family(name),
+            GenericFamily::Cursive => options.fontdb_mut().set_cursive_family(name),
+            GenericFamily::Fantasy => options.fontdb_mut().set_fantasy_family(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, Stream};
+
+    use super::*;
+
+    /// Adds a page using a TrueType font embedding `program` to `doc`,
+    /// returning the id of its font descriptor.
+    fn add_page_with_font(doc: &mut Document, pages_id: ObjectId, program: &[u8]) -> ObjectId {
+        let font_file_id = doc.add_object(Stream::new(
+            dictionary! { "Length1" => program.len() as i64 },
+            program.to_vec(),
+        ));
+        let descriptor_id = doc.add_object(dictionary! {
+            "Type" => "FontDescriptor",
+            "FontName" => "ABCDEF+DejaVuSans",
+            "FontFile2" => font_file_id,
+        });
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "TrueType",
+            "BaseFont" => "ABCDEF+DejaVuSans",
+            "FontDescriptor" => descriptor_id,
+        });
+        let content_id = doc.add_object(Stream::new(
+            dictionary! {},
+            b"BT /F1 12 Tf (Hi) Tj ET".to_vec(),
+        ));
+        doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "F1" => font_id },
+            },
+        });
+
+        descriptor_id
+    }
+
+    fn font_file(doc: &Document, descriptor_id: ObjectId) -> ObjectId {
+        doc.get_dictionary(descriptor_id)
+            .unwrap()
+            .get(b"FontFile2")
+            .and_then(Object::as_reference)
+            .unwrap()
+    }
+
+    #[test]
+    fn collapses_identical_font_programs() {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let first = add_page_with_font(&mut doc, pages_id, b"same glyphs");
+        let second = add_page_with_font(&mut doc, pages_id, b"same glyphs");
+        let other = add_page_with_font(&mut doc, pages_id, b"other glyphs");
+        let object_count = doc.objects.len();
+
+        assert_eq!(dedup_font_files(&mut doc), 1);
+
+        assert_eq!(doc.objects.len(), object_count - 1);
+        assert_eq!(font_file(&doc, first), font_file(&doc, second));
+        assert_ne!(font_file(&doc, first), font_file(&doc, other));
+        assert!(doc.get_object(font_file(&doc, second)).is_ok());
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+
+use lopdf::{Document, Object, ObjectId};
+
+/// Collapses byte-identical image XObjects into a single object shared by
+/// all pages drawing them and removes the copies, returning how many were
+/// removed.
+///
+/// Every rendered page embeds its own copy of the images it draws, so an
+/// image on every page, e.g. a logo, is stored once per page. Images are
+/// compared by their stream dictionary and raw, still compressed content,
+/// like the fonts of [`crate::fonts::dedup_font_files`], once the soft masks
+/// and ICC profiles they refer to were collapsed the same way.
+pub fn dedup_images(doc: &mut Document) -> usize {
+    let mut removed = 0;
+
+    // Collapsing what images refer to can make images identical that
+    // weren't before, so repeat until nothing changes.
+    loop {
+        let replacements = duplicates(doc, &image_streams(doc));
+        if replacements.is_empty() {
+            return removed;
+        }
+
+        for object in doc.objects.values_mut() {
+            replace_references(object, &replacements);
+        }
+        for duplicate in replacements.keys() {
+            doc.objects.remove(duplicate);
+        }
+        removed += replacements.len();
+    }
+}
+
+/// Ids of the image XObjects of `doc` and of the streams their dictionaries
+/// refer to, e.g. their `SMask` or an ICC profile of their `ColorSpace`.
+fn image_streams(doc: &Document) -> BTreeSet<ObjectId> {
+    let mut streams = BTreeSet::new();
+    for (id, object) in &doc.objects {
+        let image = match object {
+            Object::Stream(stream)
+                if stream
+                    .dict
+                    .get(b"Subtype")
+                    .and_then(Object::as_name)
+                    .is_ok_and(|subtype| subtype == b"Image") =>
+            {
+                stream
+            }
+            _ => continue,
+        };
+
+        streams.insert(*id);
+        let mut pending: Vec<&Object> = image.dict.iter().map(|(_, value)| value).collect();
+        while let Some(value) = pending.pop() {
+            match value {
+                Object::Reference(id) => {
+                    if let Some(Object::Stream(_)) = doc.objects.get(id) {
+                        streams.insert(*id);
+                    }
+                }
+                Object::Array(items) => pending.extend(items),
+                Object::Dictionary(dict) => pending.extend(dict.iter().map(|(_, value)| value)),
+                _ => {}
+            }
+        }
+    }
+
+    streams
+}
+
+/// Maps every stream of `candidates` identical to one before it, in object
+/// order, to that one.
+fn duplicates(doc: &Document, candidates: &BTreeSet<ObjectId>) -> HashMap<ObjectId, ObjectId> {
+    // Stream ids by the hash of their content, to find candidates without
+    // keeping copies of the images around.
+    let mut by_hash: HashMap<u64, Vec<ObjectId>> = HashMap::new();
+    let mut replacements = HashMap::new();
+
+    for id in candidates {
+        let stream = match doc.get_object(*id).and_then(Object::as_stream) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        stream.content.hash(&mut hasher);
+        let originals = by_hash.entry(hasher.finish()).or_default();
+
+        let original = originals.iter().copied().find(|original| {
+            doc.get_object(*original)
+                .and_then(Object::as_stream)
+                .is_ok_and(|original| {
+                    original.dict == stream.dict && original.content == stream.content
+                })
+        });
+        match original {
+            Some(original) => {
+                replacements.insert(*id, original);
+            }
+            None => originals.push(*id),
+        }
+    }
+
+    replacements
+}
+
+fn replace_references(object: &mut Object, replacements: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(original) = replacements.get(id) {
+                *id = *original;
+            }
+        }
+        Object::Array(items) => {
+            for item in items {
+                replace_references(item, replacements);
+            }
+        }
+        Object::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                replace_references(value, replacements);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                replace_references(value, replacements);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use base64::prelude::{Engine, BASE64_STANDARD};
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    use super::*;
+    use crate::{merge_pdfs_with, render_svg_to_pdf, MergeOptions};
+
+    fn image_count(doc: &Document) -> usize {
+        doc.objects
+            .values()
+            .filter(|object| {
+                object.as_stream().is_ok_and(|stream| {
+                    stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image")
+                })
+            })
+            .count()
+    }
+
+    #[test]
+    fn shares_an_image_drawn_on_two_pages() {
+        let mut png = Vec::new();
+        RgbImage::from_fn(8, 8, |x, y| Rgb([x as u8 * 30, y as u8 * 30, 100]))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let page = render_svg_to_pdf(&format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100"><image href="data:image/png;base64,{}" width="80" height="80"/></svg>"#,
+            BASE64_STANDARD.encode(&png)
+        ))
+        .unwrap();
+        let merge = |dedup_images| {
+            let merge_options = MergeOptions {
+                dedup_images,
+                skip_compression: true,
+                ..MergeOptions::default()
+            };
+            merge_pdfs_with(vec![&page, &page], &merge_options).unwrap()
+        };
+
+        assert_eq!(image_count(&merge(false)), 2);
+
+        let merged = merge(true);
+        assert_eq!(image_count(&merged), 1);
+        assert_eq!(merged.get_pages().len(), 2);
+        let image_ids: Vec<ObjectId> = merged
+            .page_iter()
+            .map(|page_id| {
+                let (resources, _) = merged.get_page_resources(page_id);
+                let xobjects = resources
+                    .unwrap()
+                    .get_deref(b"XObject", &merged)
+                    .and_then(Object::as_dict)
+                    .unwrap();
+                let (_, image) = xobjects.iter().next().unwrap();
+                image.as_reference().unwrap()
+            })
+            .collect();
+        assert_eq!(image_ids[0], image_ids[1]);
+    }
+}
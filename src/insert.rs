@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+
+use crate::page::{flatten_page_attributes, INHERITABLE_PAGE_KEYS};
+use crate::{layers, links};
+
+/// Guards against cyclic `Next` chains in malformed documents.
+const MAX_CHAIN_LENGTH: usize = 1 << 16;
+
+/// Inserts every page of `pages` into `base` so that the first inserted page
+/// ends up at the zero-based position `index`.
+///
+/// The page tree of `base` is flattened into its root `Pages` node, the
+/// top-level outline items of `pages` are grafted into the outline of `base`
+/// next to the pages they point at, and `/PageLabels` ranges following the
+/// insertion point are shifted so existing labels stay attached to their
+/// pages. Named destinations of `pages` are added to those of `base`, and
+/// layers are joined by name, see [`layers::merge_layers`].
+pub fn insert_pages(mut base: Document, mut pages: Document, index: usize) -> Result<Document> {
+    let base_pages: Vec<ObjectId> = base.page_iter().collect();
+    if index > base_pages.len() {
+        return Err(anyhow!(
+            "Cannot insert at page {}, the base document only has {} pages.",
+            index + 1,
+            base_pages.len()
+        ));
+    }
+
+    let root_pages_id = base
+        .catalog()?
+        .get(b"Pages")
+        .and_then(Object::as_reference)?;
+    flatten_page_attributes(&mut base, &base_pages);
+
+    pages.renumber_objects_with(base.max_id + 1);
+    let new_pages: Vec<ObjectId> = pages.page_iter().collect();
+    flatten_page_attributes(&mut pages, &new_pages);
+
+    let new_catalog = pages.catalog()?.clone();
+    let new_outlines_id = new_catalog
+        .get(b"Outlines")
+        .and_then(Object::as_reference)
+        .ok();
+    let new_catalog_id = pages.trailer.get(b"Root").and_then(Object::as_reference)?;
+    let new_outline_items = new_outlines_id
+        .map(|id| outline_children(&pages, id))
+        .unwrap_or_default();
+    let new_dests = links::input_dests(&pages);
+
+    // Drop the intermediate page tree nodes of the base document, every page
+    // is re-parented to the root below.
+    base.objects.retain(|id, object| {
+        *id == root_pages_id || object.type_name().map_or(true, |name| name != "Pages")
+    });
+
+    for (object_id, object) in pages.objects {
+        if object_id == new_catalog_id || Some(object_id) == new_outlines_id {
+            continue;
+        }
+
+        match object.type_name().unwrap_or("") {
+            "Pages" => {}
+            _ => {
+                base.objects.insert(object_id, object);
+            }
+        }
+    }
+
+    for page_id in &new_pages {
+        base.get_dictionary_mut(*page_id)?
+            .set("Parent", root_pages_id);
+    }
+
+    for page_id in &base_pages {
+        base.get_dictionary_mut(*page_id)?
+            .set("Parent", root_pages_id);
+    }
+
+    let kids: Vec<Object> = base_pages[..index]
+        .iter()
+        .chain(new_pages.iter())
+        .chain(base_pages[index..].iter())
+        .copied()
+        .map(Object::Reference)
+        .collect();
+
+    let root_pages = base.get_dictionary_mut(root_pages_id)?;
+    root_pages.set("Count", kids.len() as u32);
+    root_pages.set("Kids", kids);
+    for key in INHERITABLE_PAGE_KEYS {
+        root_pages.remove(key);
+    }
+
+    base.max_id = base.max_id.max(pages.max_id);
+
+    graft_outline_items(&mut base, &base_pages, index, &new_outline_items)?;
+    shift_page_labels(&mut base, index, new_pages.len() as i64)?;
+    if let Some(new_dests) = new_dests {
+        add_dests(&mut base, &new_dests)?;
+    }
+    layers::merge_layers(&mut base)?;
+
+    Ok(base)
+}
+
+/// Adds `dests` to the `/Dests` of `doc`, keeping the existing destination
+/// where both define a name.
+fn add_dests(doc: &mut Document, dests: &Dictionary) -> Result<()> {
+    let mut merged = links::input_dests(doc).unwrap_or_default();
+    for (name, dest) in dests.iter() {
+        if !merged.has(name) {
+            merged.set(name.clone(), dest.clone());
+        }
+    }
+    doc.catalog_mut()?.set("Dests", merged);
+
+    Ok(())
+}
+
+/// Returns the direct children of an outline node, in order.
+pub(crate) fn outline_children(doc: &Document, node_id: ObjectId) -> Vec<ObjectId> {
+    let first = doc
+        .get_dictionary(node_id)
+        .and_then(|node| node.get(b"First"))
+        .and_then(Object::as_reference)
+        .ok();
+
+    outline_siblings(doc, first)
+}
+
+/// Returns `first` and the outline items following it through `/Next`.
+pub(crate) fn outline_siblings(doc: &Document, first: Option<ObjectId>) -> Vec<ObjectId> {
+    let mut children = Vec::new();
+    let mut next = first;
+
+    while let Some(id) = next {
+        if children.len() >= MAX_CHAIN_LENGTH || children.contains(&id) {
+            break;
+        }
+
+        children.push(id);
+        next = doc
+            .get_dictionary(id)
+            .and_then(|item| item.get(b"Next"))
+            .and_then(Object::as_reference)
+            .ok();
+    }
+
+    children
+}
+
+/// Resolves the page an outline item jumps to, if it uses an explicit
+/// destination.
+fn outline_item_page(doc: &Document, item: &Dictionary) -> Option<ObjectId> {
+    let destination = match item.get(b"Dest") {
+        Ok(dest) => dest,
+        Err(_) => {
+            let (_, action) = doc.dereference(item.get(b"A").ok()?).ok()?;
+            action.as_dict().ok()?.get(b"D").ok()?
+        }
+    };
+
+    let (_, destination) = doc.dereference(destination).ok()?;
+    destination.as_array().ok()?.first()?.as_reference().ok()
+}
+
+/// Hangs the outline `items`, siblings in order, under the root of the
+/// outline of `doc`, before the first item pointing at the page at `index`
+/// of `base_pages` or later.
+pub(crate) fn graft_outline_items(
+    doc: &mut Document,
+    base_pages: &[ObjectId],
+    index: usize,
+    items: &[ObjectId],
+) -> Result<()> {
+    let (first, last) = match (items.first(), items.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Ok(()),
+    };
+
+    let outlines_id = match doc
+        .catalog()?
+        .get(b"Outlines")
+        .and_then(Object::as_reference)
+    {
+        Ok(id) => id,
+        Err(_) => {
+            let id = doc.add_object(dictionary! { "Type" => "Outlines" });
+            doc.catalog_mut()?.set("Outlines", id);
+            id
+        }
+    };
+
+    // Insert before the first existing item pointing at or after the
+    // insertion point, so the outline keeps following the page order.
+    let existing = outline_children(doc, outlines_id);
+    let position = existing
+        .iter()
+        .position(|id| {
+            doc.get_dictionary(*id)
+                .ok()
+                .and_then(|item| outline_item_page(doc, item))
+                .and_then(|page| base_pages.iter().position(|p| *p == page))
+                .is_some_and(|page_index| page_index >= index)
+        })
+        .unwrap_or(existing.len());
+
+    let prev = position.checked_sub(1).map(|i| existing[i]);
+    let next = existing.get(position).copied();
+
+    for item in items {
+        doc.get_dictionary_mut(*item)?.set("Parent", outlines_id);
+    }
+
+    doc.get_dictionary_mut(first)?.remove(b"Prev");
+    doc.get_dictionary_mut(last)?.remove(b"Next");
+
+    match prev {
+        Some(prev) => {
+            doc.get_dictionary_mut(prev)?.set("Next", first);
+            doc.get_dictionary_mut(first)?.set("Prev", prev);
+        }
+        None => doc.get_dictionary_mut(outlines_id)?.set("First", first),
+    }
+
+    match next {
+        Some(next) => {
+            doc.get_dictionary_mut(next)?.set("Prev", last);
+            doc.get_dictionary_mut(last)?.set("Next", next);
+        }
+        None => doc.get_dictionary_mut(outlines_id)?.set("Last", last),
+    }
+
+    let outlines = doc.get_dictionary_mut(outlines_id)?;
+    let count = outlines.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+    outlines.set("Count", count.abs() + items.len() as i64);
+
+    Ok(())
+}
+
+/// Moves every `/PageLabels` range starting at or after `index` back by
+/// `amount` pages, so the inserted pages continue the range they land in.
+pub(crate) fn shift_page_labels(doc: &mut Document, index: usize, amount: i64) -> Result<()> {
+    let labels = match doc.catalog()?.get(b"PageLabels") {
+        Ok(labels) => labels.clone(),
+        Err(_) => return Ok(()),
+    };
+
+    let (labels_id, labels) = doc.dereference(&labels)?;
+    let mut labels = labels.as_dict()?.clone();
+
+    let nums = match labels.get(b"Nums") {
+        Ok(nums) => doc.dereference(nums)?.1.as_array()?.clone(),
+        Err(_) => {
+            warn!("Page labels are stored in a nested number tree, leaving them unchanged.");
+            return Ok(());
+        }
+    };
+
+    let nums = nums
+        .chunks(2)
+        .flat_map(|pair| match pair {
+            [Object::Integer(start), label] if *start > 0 && *start >= index as i64 => {
+                vec![Object::Integer(start + amount), label.clone()]
+            }
+            _ => pair.to_vec(),
+        })
+        .collect::<Vec<_>>();
+
+    labels.set("Nums", nums);
+
+    match labels_id {
+        Some(id) => doc.set_object(id, labels),
+        None => doc.catalog_mut()?.set("PageLabels", labels),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{anyhow, Result};
+use lopdf::{dictionary, Document, Object, ObjectId};
+use xmltree::{Element, XMLNode};
+
+use crate::continuous::page_to_xobject;
+use crate::metadata::text_string;
+use crate::page::{add_page_resources, media_box, wrap_page_contents};
+use crate::{tree_from_element, tree_to_pdf_with, RenderOptions};
+
+/// Elements that draw nothing themselves, kept in every layer so what the
+/// layers refer to, such as gradients and styles, is always there.
+const NON_RENDERING_ELEMENTS: [&str; 7] = [
+    "defs",
+    "style",
+    "title",
+    "desc",
+    "metadata",
+    "script",
+    "namedview",
+];
+
+/// A run of top-level elements of an SVG rendered on its own: either an
+/// Inkscape layer, or the content between layers that belongs to none.
+struct Segment {
+    /// Label of the layer and whether it is hidden, `None` for content
+    /// outside of layers.
+    layer: Option<(String, bool)>,
+    /// Indices of the elements in the root's children.
+    children: Vec<usize>,
+}
+
+/// Renders `root` with every top-level Inkscape layer
+/// (`<g inkscape:groupmode="layer">`) as an optional content group named
+/// after its `inkscape:label`, which viewers let users toggle.
+///
+/// Each layer, and the content between layers, is rendered on its own and
+/// composited onto the page as a Form XObject, the layers wrapped in `/OC`
+/// marked content, so they keep their stacking order. Layers hidden in
+/// Inkscape are drawn but start out hidden. Sublayers are part of the
+/// top-level layer they are in. Returns `None` if the SVG has no layers, so
+/// it can be rendered as usual.
+pub(crate) fn render_layers(
+    root: &Element,
+    render_options: &RenderOptions,
+) -> Result<Option<Vec<u8>>> {
+    let segments = segments(root);
+    if !segments.iter().any(|segment| segment.layer.is_some()) {
+        return Ok(None);
+    }
+
+    let mut rendered = Vec::with_capacity(segments.len());
+    for segment in &segments {
+        let mut segment_root = root.clone();
+        for (index, child) in segment_root.children.iter_mut().enumerate() {
+            if let XMLNode::Element(element) = child {
+                if segment.children.contains(&index) {
+                    show(element);
+                } else if !NON_RENDERING_ELEMENTS.contains(&element.name.as_str()) {
+                    element
+                        .attributes
+                        .insert("display".to_string(), "none".to_string());
+                }
+            }
+        }
+
+        let tree = tree_from_element(&segment_root, render_options)?;
+        rendered.push(Document::load_mem(&tree_to_pdf_with(
+            &tree,
+            render_options,
+        )?)?);
+    }
+
+    let mut rendered = rendered.into_iter();
+    let mut doc = rendered
+        .next()
+        .ok_or_else(|| anyhow!("The SVG has nothing to render"))?;
+    let page_id = doc
+        .page_iter()
+        .next()
+        .ok_or_else(|| anyhow!("The rendered PDF has no pages"))?;
+    let visible_box = media_box(&doc, page_id)?;
+
+    let mut resources = Vec::new();
+    let mut prefix = b"q\n".to_vec();
+    let mut suffix = b"Q\n".to_vec();
+    let mut ocgs = Vec::new();
+    let mut hidden = Vec::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+        let layer_name = format!("Layer{}", index + 1);
+        let (begin, end) = match &segment.layer {
+            Some((label, is_hidden)) => {
+                let mut ocg = dictionary! {
+                    "Type" => "OCG",
+                    "Name" => text_string(label),
+                };
+                if *is_hidden {
+                    // Remembers the state on the group itself, so it survives
+                    // merging, see `merge_layers`.
+                    ocg.set(
+                        "Usage",
+                        dictionary! { "View" => dictionary! { "ViewState" => "OFF" } },
+                    );
+                }
+                let ocg_id = doc.add_object(ocg);
+                ocgs.push(Object::Reference(ocg_id));
+                if *is_hidden {
+                    hidden.push(Object::Reference(ocg_id));
+                }
+                resources.push((b"Properties".as_slice(), layer_name.clone(), ocg_id));
+
+                (format!("/OC /{} BDC\n", layer_name), "EMC\n")
+            }
+            None => (String::new(), ""),
+        };
+
+        if index == 0 {
+            prefix.splice(0..0, begin.into_bytes());
+            suffix.extend_from_slice(end.as_bytes());
+            continue;
+        }
+
+        let mut segment_doc = match rendered.next() {
+            Some(segment_doc) => segment_doc,
+            None => break,
+        };
+        segment_doc.renumber_objects_with(doc.max_id + 1);
+        let segment_page_id = segment_doc
+            .page_iter()
+            .next()
+            .ok_or_else(|| anyhow!("The rendered PDF has no pages"))?;
+        let xobject_id = page_to_xobject(&mut segment_doc, segment_page_id, visible_box)?;
+
+        for (id, object) in segment_doc.objects {
+            let type_name = object.type_name().unwrap_or("");
+            if !matches!(type_name, "Catalog" | "Pages" | "Page") {
+                doc.objects.insert(id, object);
+            }
+        }
+        doc.max_id = doc.max_id.max(segment_doc.max_id);
+
+        let form_name = format!("LayerForm{}", index + 1);
+        suffix.extend(format!("{}/{} Do\n{}", begin, form_name, end).into_bytes());
+        resources.push((b"XObject".as_slice(), form_name, xobject_id));
+    }
+
+    let resources: Vec<(&[u8], &str, ObjectId)> = resources
+        .iter()
+        .map(|(category, name, id)| (*category, name.as_str(), *id))
+        .collect();
+    add_page_resources(&mut doc, page_id, &resources)?;
+    wrap_page_contents(&mut doc, page_id, prefix, suffix)?;
+
+    doc.catalog_mut()?.set(
+        "OCProperties",
+        dictionary! {
+            "OCGs" => ocgs.clone(),
+            "D" => dictionary! { "Order" => ocgs, "OFF" => hidden },
+        },
+    );
+
+    let mut output = Vec::new();
+    doc.save_to(&mut output)?;
+
+    Ok(Some(output))
+}
+
+/// Splits the top-level elements of `root` into layers and the runs of
+/// content between them, in document order, leaving out elements that draw
+/// nothing.
+fn segments(root: &Element) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for (index, child) in root.children.iter().enumerate() {
+        let element = match child {
+            XMLNode::Element(element)
+                if !NON_RENDERING_ELEMENTS.contains(&element.name.as_str()) =>
+            {
+                element
+            }
+            _ => continue,
+        };
+
+        if element.name == "g"
+            && element.attributes.get("groupmode").map(String::as_str) == Some("layer")
+        {
+            let label = element
+                .attributes
+                .get("label")
+                .or_else(|| element.attributes.get("id"))
+                .cloned()
+                .unwrap_or_else(|| format!("Layer {}", segments.len() + 1));
+            segments.push(Segment {
+                layer: Some((label, is_hidden(element))),
+                children: vec![index],
+            });
+            continue;
+        }
+
+        match segments.last_mut() {
+            Some(segment) if segment.layer.is_none() => segment.children.push(index),
+            _ => segments.push(Segment {
+                layer: None,
+                children: vec![index],
+            }),
+        }
+    }
+
+    segments
+}
+
+/// Whether `element` is hidden with `display: none`, the way Inkscape hides
+/// layers.
+fn is_hidden(element: &Element) -> bool {
+    element.attributes.get("display").map(|value| value.trim()) == Some("none")
+        || style_declarations(element)
+            .any(|(property, value)| property == "display" && value == "none")
+}
+
+/// Makes a hidden layer visible again, so it can be drawn into its group.
+fn show(element: &mut Element) {
+    element.attributes.remove("display");
+    let style: Vec<String> = style_declarations(element)
+        .filter(|(property, _)| *property != "display")
+        .map(|(property, value)| format!("{}:{}", property, value))
+        .collect();
+    if !style.is_empty() || element.attributes.contains_key("style") {
+        element
+            .attributes
+            .insert("style".to_string(), style.join(";"));
+    }
+}
+
+/// The `property: value` pairs of the element's `style` attribute.
+fn style_declarations(element: &Element) -> impl Iterator<Item = (&str, &str)> {
+    element
+        .attributes
+        .get("style")
+        .map_or("", String::as_str)
+        .split(';')
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            Some((property.trim(), value.trim()))
+        })
+}
+
+/// Joins the optional content groups of `doc` sharing a name into one, so a
+/// layer that is on several pages is toggled on all of them at once, and
+/// lists them in the catalog's `/OCProperties` in order of appearance.
+///
+/// A group starts out hidden if it was hidden in the catalog before or
+/// recommends so in its `/Usage` dictionary. Does nothing if `doc` has no
+/// optional content groups.
+pub fn merge_layers(doc: &mut Document) -> Result<()> {
+    let previously_hidden: BTreeSet<ObjectId> = doc
+        .catalog()?
+        .get(b"OCProperties")
+        .and_then(|properties| doc.dereference(properties))
+        .and_then(|(_, properties)| properties.as_dict())
+        .and_then(|properties| properties.get(b"D"))
+        .and_then(|config| doc.dereference(config))
+        .and_then(|(_, config)| config.as_dict())
+        .and_then(|config| config.get(b"OFF"))
+        .and_then(Object::as_array)
+        .map(|off| off.iter().filter_map(|id| id.as_reference().ok()).collect())
+        .unwrap_or_default();
+
+    let mut groups: Vec<(Vec<u8>, ObjectId, bool)> = Vec::new();
+    let mut replacements: HashMap<ObjectId, ObjectId> = HashMap::new();
+
+    for (id, object) in &doc.objects {
+        let ocg = match object.as_dict() {
+            Ok(dict) if object.type_name().is_ok_and(|name| name == "OCG") => dict,
+            _ => continue,
+        };
+        let name = ocg
+            .get(b"Name")
+            .and_then(Object::as_str)
+            .unwrap_or_default()
+            .to_vec();
+        let recommends_hidden = ocg
+            .get(b"Usage")
+            .and_then(Object::as_dict)
+            .and_then(|usage| usage.get(b"View"))
+            .and_then(Object::as_dict)
+            .and_then(|view| view.get(b"ViewState"))
+            .and_then(Object::as_name)
+            .is_ok_and(|state| state == b"OFF");
+        let hidden = recommends_hidden || previously_hidden.contains(id);
+
+        match groups
+            .iter_mut()
+            .find(|(group_name, _, _)| *group_name == name)
+        {
+            Some((_, original, group_hidden)) => {
+                *group_hidden |= hidden;
+                replacements.insert(*id, *original);
+            }
+            None => groups.push((name, *id, hidden)),
+        }
+    }
+
+    if groups.is_empty() {
+        return Ok(());
+    }
+
+    for object in doc.objects.values_mut() {
+        replace_references(object, &replacements);
+    }
+    for duplicate in replacements.keys() {
+        doc.objects.remove(duplicate);
+    }
+
+    let ocgs: Vec<Object> = groups
+        .iter()
+        .map(|(_, id, _)| Object::Reference(*id))
+        .collect();
+    let hidden: Vec<Object> = groups
+        .iter()
+        .filter(|(_, _, hidden)| *hidden)
+        .map(|(_, id, _)| Object::Reference(*id))
+        .collect();
+    doc.catalog_mut()?.set(
+        "OCProperties",
+        dictionary! {
+            "OCGs" => ocgs.clone(),
+            "D" => dictionary! { "Order" => ocgs, "OFF" => hidden },
+        },
+    );
+
+    Ok(())
+}
+
+fn replace_references(object: &mut Object, replacements: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(replacement) = replacements.get(id) {
+                *id = *replacement;
+            }
+        }
+        Object::Array(array) => {
+            for item in array {
+                replace_references(item, replacements);
+            }
+        }
+        Object::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                replace_references(value, replacements);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                replace_references(value, replacements);
+            }
+        }
+        _ => {}
+    }
+}
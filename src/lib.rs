@@ -0,0 +1,2478 @@
+pub mod acroform;
+pub mod attachments;
+pub mod bleed;
+pub mod cache;
+pub mod color_profile;
+pub mod compression;
+pub mod continuous;
+pub mod deadline;
+pub mod draft;
+pub mod encryption;
+pub mod fonts;
+pub mod images;
+pub mod insert;
+pub mod layers;
+pub mod linearize;
+pub mod links;
+pub mod metadata;
+pub mod page;
+pub mod page_checks;
+pub mod page_labels;
+pub mod page_numbers;
+pub mod page_ranges;
+pub mod page_size;
+pub mod pipeline;
+pub mod precision;
+mod raster;
+pub mod rotation;
+pub mod sort;
+pub mod thumbnail;
+pub mod toc;
+pub mod user_unit;
+pub mod watermark;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufWriter, Cursor, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, str};
+
+use anyhow::{anyhow, Context, Result};
+use base64::alphabet;
+use base64::engine::general_purpose::GeneralPurposeConfig;
+use base64::engine::{DecodePaddingMode, GeneralPurpose};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use clap::ValueEnum;
+use log::warn;
+use lopdf::xref::XrefType;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use rayon::prelude::*;
+
+use crate::acroform::FieldClash;
+use crate::fonts::FontFamily;
+use crate::page_size::PageSize;
+use serde::Deserialize;
+use svg2pdf::usvg::{ImageHrefResolver, Tree};
+use svg2pdf::{ConversionOptions, PageOptions};
+use xmltree::Element;
+use xmltree::EmitterConfig;
+use xmltree::XMLNode;
+
+/// Root `<svg>` attribute overriding [`RenderOptions::background`] for a
+/// single page, e.g. `data-background="white"`.
+const BACKGROUND_ATTRIBUTE: &str = "data-background";
+
+/// Attributes an element can link to its data under. xmltree drops namespace
+/// prefixes, so `xlink:href` from Inkscape and older exporters usually shows
+/// up as plain `href`, but both spellings are checked.
+const HREF_ATTRIBUTES: [&str; 2] = ["href", "xlink:href"];
+
+/// Attribute marking an element's text as a heading of the given level.
+const HEADING_LEVEL_ATTRIBUTE: &str = "data-level";
+
+/// Largest SVG document parsed, in bytes, including SVGs embedded in it as
+/// base64 images, which are checked on their own once decoded.
+pub const MAX_SVG_SIZE: usize = 256 << 20;
+
+/// Largest PNG or JPEG rendered unless [`RenderOptions::max_image_pixels`]
+/// says otherwise, in pixels, e.g. 10000x10000, which take 400 MB decoded.
+pub const DEFAULT_MAX_IMAGE_PIXELS: u64 = 100_000_000;
+
+/// What ends up behind the SVG content on the rendered page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Background {
+    /// Keep the SVG's transparency, suitable for overlaying
+    #[default]
+    Transparent,
+    /// Composite the page onto an opaque white background, suitable for printing
+    White,
+}
+
+/// Options controlling how a single SVG page is rendered.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Background used for pages that don't set `data-background` themselves.
+    pub background: Background,
+    /// Trim the page to the bounding box of its content plus this many points.
+    pub autocrop: Option<f32>,
+    /// Which page box [`RenderOptions::autocrop`] adjusts.
+    pub autocrop_box: PageBox,
+    /// Embed a thumbnail this many pixels along its longer side.
+    pub thumbnail_size: Option<u32>,
+    /// Down-sample embedded base64 PNGs and JPEGs to at most this many
+    /// pixels along their longer side.
+    pub max_image_size: Option<u32>,
+    /// Fail to render pages with a PNG or JPEG image, embedded or linked, of
+    /// more than this many pixels, [`DEFAULT_MAX_IMAGE_PIXELS`] if not set.
+    /// Images are measured by their header, before anything allocates
+    /// memory for their pixels.
+    pub max_image_pixels: Option<u64>,
+    /// Resolution, in pixels per inch, of the images svg2pdf rasterizes
+    /// filters into. svg2pdf's default of 108 if not set.
+    pub raster_dpi: Option<u32>,
+    /// Draw text as path outlines instead of embedding fonts, so pages look
+    /// the same everywhere, but their text can't be selected or searched.
+    pub outline_text: bool,
+    /// Turn top-level Inkscape layers into optional content groups viewers
+    /// can toggle, see [`layers::render_layers`].
+    pub preserve_layers: bool,
+    /// Directories whose fonts are loaded, in addition to the system fonts
+    /// unless [`RenderOptions::skip_system_fonts`] is set. Directories that
+    /// don't exist are ignored.
+    pub font_dirs: Vec<PathBuf>,
+    /// Only use the fonts from [`RenderOptions::font_dirs`], so rendering
+    /// doesn't depend on what is installed on the host.
+    pub skip_system_fonts: bool,
+    /// Fonts used for the generic families, later ones winning for the same
+    /// family. usvg's choice among the loaded fonts for those not listed.
+    pub font_families: Vec<FontFamily>,
+    /// Languages, e.g. `ar` or `en-US`, `systemLanguage` attributes of
+    /// `<switch>` children are matched against. usvg's `en` if empty.
+    ///
+    /// Text shaping doesn't depend on them: usvg shapes every run of text
+    /// with rustybuzz, which picks the script and direction from the text
+    /// itself.
+    pub languages: Vec<String>,
+    /// Scale and center every page onto this size, see
+    /// [`page_size::fit_to_page_size`].
+    pub page_size: Option<PageSize>,
+    /// Size of pages whose SVG has no `viewBox` and lacks a `width` or
+    /// `height`. If not set, usvg sizes them to fit the content they draw.
+    pub default_size: Option<PageSize>,
+    /// Parse every rendered PDF again before returning it, so output lopdf
+    /// can't read is reported for the SVG it came from instead of failing
+    /// the merge.
+    pub validate: bool,
+    /// Directory relative file paths of `<image>` elements are resolved
+    /// against, usually the SVG's own. The working directory if not set.
+    /// Paths inside a ZIP archive, see [`pipeline::read_source`], work too.
+    pub resources_dir: Option<PathBuf>,
+}
+
+/// Decodes the base64 data of a data URI. The padding may be left out and
+/// whitespace, e.g. from tools wrapping long lines, is skipped.
+fn decode_base64(data: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    const FORGIVING: GeneralPurpose = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
+
+    BASE64_STANDARD.decode(data).or_else(|e| {
+        let data: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+        FORGIVING.decode(data).map_err(|_| e)
+    })
+}
+
+pub fn expand_base64_svgs(svg_content: &str) -> Result<String> {
+    // Parse the SVG content as an XML element
+    let mut root = parse_xml(svg_content)?;
+
+    // Recursively process the XML tree to decode base64 SVG images
+    process_element(&mut root, None, DEFAULT_MAX_IMAGE_PIXELS).map_err(|e| anyhow::anyhow!(e))?;
+
+    write_svg(&root)
+}
+
+/// Parses an XML document that may come from an untrusted source.
+///
+/// Documents larger than [`MAX_SVG_SIZE`] and documents whose DTD declares
+/// entities are rejected up front, so neither a huge input nor entity
+/// expansion (a "billion laughs" document) can exhaust memory. SVGs never
+/// need entities, only the predefined ones like `&amp;` are supported.
+fn parse_xml(content: &str) -> Result<Element> {
+    if content.len() > MAX_SVG_SIZE {
+        return Err(anyhow!(
+            "The SVG is {} bytes large, more than the {} bytes allowed",
+            content.len(),
+            MAX_SVG_SIZE
+        ));
+    }
+
+    if let Some(start) = content.find("<!DOCTYPE") {
+        let doctype = &content[start..];
+        let has_internal_subset = doctype
+            .find(['[', '>'])
+            .is_some_and(|index| doctype.as_bytes()[index] == b'[');
+        let subset_end = doctype.find("]>").unwrap_or(doctype.len());
+        if has_internal_subset && doctype[..subset_end].contains("<!ENTITY") {
+            return Err(anyhow!(
+                "The SVG declares XML entities in its DOCTYPE, which are not supported"
+            ));
+        }
+    }
+
+    Ok(Element::parse(Cursor::new(content))?)
+}
+
+/// Returns the name and value of the attribute `element` links through,
+/// either `href` or `xlink:href`.
+fn href_attribute(element: &Element) -> Option<(&'static str, &str)> {
+    HREF_ATTRIBUTES.into_iter().find_map(|key| {
+        let value = element.attributes.get(key)?;
+        Some((key, value.as_str()))
+    })
+}
+
+fn write_svg(root: &Element) -> Result<String> {
+    // Convert the modified XML tree back to a string
+    let mut output = Vec::new();
+    root.write_with_config(&mut output, EmitterConfig::default())?;
+    let result = String::from_utf8(output)?;
+
+    Ok(result)
+}
+
+/// Resolves the background of a page, honoring its `data-background` marker.
+fn page_background(root: &Element, default: Background) -> Background {
+    match root.attributes.get(BACKGROUND_ATTRIBUTE) {
+        Some(value) => Background::from_str(value, true).unwrap_or_else(|_| {
+            warn!(
+                "Ignoring unknown {} value {:?}, expected \"transparent\" or \"white\"",
+                BACKGROUND_ATTRIBUTE, value
+            );
+            default
+        }),
+        None => default,
+    }
+}
+
+/// Paints an opaque white rectangle behind everything else in the document.
+fn flatten_background(root: &mut Element) {
+    let mut rect = Element::new("rect");
+
+    // Cover the viewBox when there is one, since percentages would be
+    // resolved against it anyway but its origin may not be at zero.
+    let view_box: Option<Vec<&str>> = root
+        .attributes
+        .get("viewBox")
+        .map(|view_box| {
+            view_box
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|part| !part.is_empty())
+                .collect()
+        })
+        .filter(|parts: &Vec<&str>| parts.len() == 4);
+
+    let (x, y, width, height) = match view_box {
+        Some(parts) => (parts[0], parts[1], parts[2], parts[3]),
+        None => ("0", "0", "100%", "100%"),
+    };
+
+    for (key, value) in [("x", x), ("y", y), ("width", width), ("height", height)] {
+        rect.attributes.insert(key.to_string(), value.to_string());
+    }
+    rect.attributes
+        .insert("fill".to_string(), "white".to_string());
+
+    root.children.insert(0, XMLNode::Element(rect));
+}
+
+fn process_element(
+    element: &mut Element,
+    max_image_size: Option<u32>,
+    max_image_pixels: u64,
+) -> Result<()> {
+    // Process all child elements
+    for child in &mut element.children {
+        if let XMLNode::Element(ref mut child_element) = child {
+            process_element(child_element, max_image_size, max_image_pixels)?;
+        }
+    }
+
+    // Drop base64 PNGs and JPEGs that don't decode, down-sampling the rest if
+    // asked to, and stop at the first one that is too large
+    let mut too_large = Ok(());
+    element.children.retain_mut(|child| match child {
+        XMLNode::Element(child_element) if too_large.is_ok() => {
+            raster::check_raster_image(child_element, max_image_size, max_image_pixels)
+                .unwrap_or_else(|e| {
+                    too_large = Err(e);
+                    true
+                })
+        }
+        _ => true,
+    });
+    too_large?;
+
+    // Check if the element is an <image> element with a base64-encoded SVG in its href or xlink:href attribute
+    if element.name == "image" {
+        if let Some((_, href)) = href_attribute(element) {
+            if let Some(base64_data) = href.strip_prefix("data:image/svg+xml;base64,") {
+                match decode_base64(base64_data) {
+                    Ok(decoded_bytes) => match str::from_utf8(&decoded_bytes) {
+                        Ok(decoded_svg) => {
+                            // Parse the decoded SVG content as an XML element
+                            let decoded_element = parse_xml(decoded_svg)?;
+
+                            let mut group_element = wrap_decoded_svg(element, decoded_element);
+
+                            // The decoded SVG may embed base64 SVGs of its own
+                            process_element(&mut group_element, max_image_size, max_image_pixels)?;
+
+                            // Replace the <image> element with the group_element SVG content
+                            *element = group_element;
+                        }
+                        Err(_) => {
+                            warn!("Keeping an embedded SVG image that isn't UTF-8 as is");
+                        }
+                    },
+                    Err(e) => {
+                        warn!(
+                            "Keeping an embedded SVG image as is, its base64 data is invalid: {}",
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes `<image>` elements embedding a whole PDF, returning the embedded
+/// documents in document order.
+///
+/// The renderer can't draw PDFs, so instead of inlining them they are merged
+/// as pages following the page embedding them. Images whose data doesn't
+/// decode to a PDF are left alone.
+fn take_embedded_pdfs(element: &mut Element, pdfs: &mut Vec<Vec<u8>>) {
+    element.children.retain_mut(|child| {
+        let child_element = match child {
+            XMLNode::Element(child_element) => child_element,
+            _ => return true,
+        };
+
+        let base64_data = match href_attribute(child_element) {
+            Some((_, href)) if child_element.name == "image" => {
+                match href.strip_prefix("data:application/pdf;base64,") {
+                    Some(base64_data) => base64_data,
+                    None => return true,
+                }
+            }
+            _ => {
+                take_embedded_pdfs(child_element, pdfs);
+                return true;
+            }
+        };
+
+        let decoded = decode_base64(base64_data)
+            .ok()
+            .and_then(|pdf| Some((Document::load_mem(&pdf).ok()?.get_pages().len(), pdf)));
+        match decoded {
+            Some((0, _)) => {
+                warn!("Ignoring an embedded application/pdf image without pages");
+                true
+            }
+            Some((_, pdf)) => {
+                pdfs.push(pdf);
+                false
+            }
+            None => {
+                warn!("Ignoring an embedded application/pdf image that isn't a valid PDF");
+                true
+            }
+        }
+    });
+}
+
+/// Page box set when autocropping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageBox {
+    /// Replace the MediaBox, discarding everything outside of the content
+    #[default]
+    Media,
+    /// Set a CropBox, keeping the original MediaBox intact
+    Crop,
+}
+
+impl PageBox {
+    /// Name of the box in the page dictionary.
+    pub fn key(self) -> &'static str {
+        match self {
+            PageBox::Media => "MediaBox",
+            PageBox::Crop => "CropBox",
+        }
+    }
+}
+
+/// Tight bounding box of a page's visible content.
+///
+/// Coordinates are in PDF points with the origin at the bottom-left corner of
+/// the page, i.e. the same space as the page's MediaBox.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    pub top: f32,
+}
+
+impl BoundingBox {
+    pub fn grow(self, margin: f32) -> BoundingBox {
+        BoundingBox {
+            left: self.left - margin,
+            bottom: self.bottom - margin,
+            right: self.right + margin,
+            top: self.top + margin,
+        }
+    }
+
+    pub fn intersect(self, other: BoundingBox) -> BoundingBox {
+        BoundingBox {
+            left: self.left.max(other.left),
+            bottom: self.bottom.max(other.bottom),
+            right: self.right.min(other.right),
+            top: self.top.min(other.top),
+        }
+    }
+}
+
+impl From<BoundingBox> for Object {
+    fn from(bbox: BoundingBox) -> Self {
+        Object::Array(vec![
+            bbox.left.into(),
+            bbox.bottom.into(),
+            bbox.right.into(),
+            bbox.top.into(),
+        ])
+    }
+}
+
+pub fn parse_svg(svg_content: &str) -> Result<Tree> {
+    parse_svg_with(svg_content, &RenderOptions::default())
+}
+
+pub fn parse_svg_with(svg_content: &str, render_options: &RenderOptions) -> Result<Tree> {
+    let (root, _) = load_svg(svg_content, render_options)?;
+
+    tree_from_element(&root, render_options)
+}
+
+/// Attributes of an `<image>` that say where its content goes, which
+/// [`wrap_decoded_svg`] moves to the `<svg>` placing an embedded SVG.
+const IMAGE_PLACEMENT_ATTRIBUTES: [&str; 5] = ["x", "y", "width", "height", "preserveAspectRatio"];
+
+/// Builds the `<g>` replacing `image`, an `<image>` embedding the SVG
+/// document `decoded`, placed the way usvg places SVG images: the document
+/// keeps its own size, which its `viewBox` is fitted into, and that size is
+/// fitted into the image's `x`, `y`, `width` and `height` following the
+/// image's `preserveAspectRatio`. An image without a `width` or `height`
+/// takes it from the document, keeping its aspect ratio.
+///
+/// The `<g>` carries the image's other attributes, e.g. `transform` or
+/// `opacity`, which usvg doesn't apply the same way to a nested `<svg>`. In
+/// it, an `<svg>` with the image's placement holds the decoded root, which
+/// keeps its `viewBox` and other attributes.
+fn wrap_decoded_svg(image: &Element, decoded: Element) -> Element {
+    let (width, height) = intrinsic_size(&decoded);
+
+    // Create a new <g> element to wrap the decoded SVG content
+    let mut group_element = Element::new("g");
+    let mut placement_element = Element::new("svg");
+
+    // Transfer the attributes from the <image> element to the wrappers
+    for (key, value) in &image.attributes {
+        if IMAGE_PLACEMENT_ATTRIBUTES.contains(&key.as_str()) {
+            placement_element
+                .attributes
+                .insert(key.clone(), value.clone());
+        } else if !HREF_ATTRIBUTES.contains(&key.as_str()) {
+            // Exclude the xlink:href, href attribute
+            group_element.attributes.insert(key.clone(), value.clone());
+        }
+    }
+    let image_width = image.attributes.get("width").and_then(|w| parse_length(w));
+    let image_height = image.attributes.get("height").and_then(|h| parse_length(h));
+    match (image_width, image_height) {
+        (Some(image_width), None) if !image.attributes.contains_key("height") => {
+            placement_element.attributes.insert(
+                "height".to_string(),
+                (image_width * height / width).to_string(),
+            );
+        }
+        (None, Some(image_height)) if !image.attributes.contains_key("width") => {
+            placement_element.attributes.insert(
+                "width".to_string(),
+                (image_height * width / height).to_string(),
+            );
+        }
+        _ => {
+            for (key, side) in [("width", width), ("height", height)] {
+                placement_element
+                    .attributes
+                    .entry(key.to_string())
+                    .or_insert_with(|| side.to_string());
+            }
+        }
+    }
+    placement_element
+        .attributes
+        .insert("viewBox".to_string(), format!("0 0 {} {}", width, height));
+
+    let mut document_element = Element::new("svg");
+    for (key, value) in &decoded.attributes {
+        if !matches!(key.as_str(), "xmlns" | "x" | "y" | "width" | "height") {
+            // Exclude the xmlns attribute, namespaces are copied below, and
+            // the placement, which is set to the size it was resolved to
+            document_element
+                .attributes
+                .insert(key.clone(), value.clone());
+        }
+    }
+    document_element
+        .attributes
+        .insert("width".to_string(), width.to_string());
+    document_element
+        .attributes
+        .insert("height".to_string(), height.to_string());
+
+    // Keep the decoded root's namespace, otherwise the wrappers only end up
+    // in the SVG namespace if the outer document happens to make it the
+    // default one
+    for wrapper in [
+        &mut group_element,
+        &mut placement_element,
+        &mut document_element,
+    ] {
+        wrapper.prefix = decoded.prefix.clone();
+        wrapper.namespace = decoded.namespace.clone();
+        wrapper.namespaces = decoded.namespaces.clone();
+    }
+
+    // Add the decoded SVG content as children of the inner <svg> element
+    document_element.children = decoded.children;
+    placement_element
+        .children
+        .push(XMLNode::Element(document_element));
+    group_element
+        .children
+        .push(XMLNode::Element(placement_element));
+
+    group_element
+}
+
+/// Size of the SVG document `root` in user units, as usvg resolves it when
+/// loading the document as an image: `width` and `height`, percentages of
+/// the `viewBox` size, 100% if they're missing. Without a `viewBox`,
+/// percentages are of 100 by 100.
+fn intrinsic_size(root: &Element) -> (f64, f64) {
+    let view_box = root.attributes.get("viewBox").and_then(|view_box| {
+        let parts = view_box
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match parts[..] {
+            [_, _, width, height] if width > 0.0 && height > 0.0 => Some((width, height)),
+            _ => None,
+        }
+    });
+
+    let side = |key: &str, view_box_side: Option<f64>| {
+        let reference = view_box_side.unwrap_or(100.0);
+        let value = root
+            .attributes
+            .get(key)
+            .map_or("100%", |value| value.trim());
+        match value.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse::<f64>()
+                .map_or(reference, |percent| reference * percent / 100.0),
+            None => parse_length(value).unwrap_or(reference),
+        }
+    };
+
+    (
+        side("width", view_box.map(|(width, _)| width)),
+        side("height", view_box.map(|(_, height)| height)),
+    )
+}
+
+/// Parses an absolute SVG length, e.g. `12`, `12px` or `1in`, into user
+/// units at 96 per inch, the resolution usvg uses.
+fn parse_length(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit.trim() {
+        "" | "px" => 1.0,
+        "in" => 96.0,
+        "cm" => 96.0 / 2.54,
+        "mm" => 96.0 / 25.4,
+        "pt" => 4.0 / 3.0,
+        "pc" => 16.0,
+        _ => return None,
+    };
+
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number > 0.0)
+        .map(|number| number * scale)
+}
+
+/// Parses an SVG document and applies all XML level preprocessing to it,
+/// returning it with whether it was given [`RenderOptions::default_size`].
+fn load_svg(svg_content: &str, render_options: &RenderOptions) -> Result<(Element, bool)> {
+    let mut root = parse_xml(svg_content)?;
+
+    let sized = match render_options.default_size {
+        Some(size) => apply_default_size(&mut root, size),
+        None => false,
+    };
+
+    // Expand base64 encoded SVGs
+    process_element(
+        &mut root,
+        render_options.max_image_size,
+        render_options
+            .max_image_pixels
+            .unwrap_or(DEFAULT_MAX_IMAGE_PIXELS),
+    )?;
+
+    if page_background(&root, render_options.background) == Background::White {
+        flatten_background(&mut root);
+    }
+
+    Ok((root, sized))
+}
+
+/// Sets the missing `width` and `height` of an SVG without a `viewBox` to
+/// `size`, returning whether it had to. Percentages count as missing, there
+/// is nothing for them to be relative to.
+fn apply_default_size(root: &mut Element, size: PageSize) -> bool {
+    if root.attributes.contains_key("viewBox") {
+        return false;
+    }
+
+    let mut sized = false;
+    for (key, value) in [("width", size.width), ("height", size.height)] {
+        let missing = root
+            .attributes
+            .get(key)
+            .is_none_or(|length| length.trim().is_empty() || length.contains('%'));
+        if missing {
+            root.attributes.insert(key.to_string(), value.to_string());
+            sized = true;
+        }
+    }
+
+    sized
+}
+
+fn tree_from_element(root: &Element, render_options: &RenderOptions) -> Result<Tree> {
+    let expanded_svg_content = write_svg(root)?;
+
+    let mut options = svg2pdf::usvg::Options {
+        resources_dir: render_options.resources_dir.clone(),
+        ..Default::default()
+    };
+    // Images usvg loads itself, linked ones and those in linked or embedded
+    // SVGs, are checked as they are loaded. The resolvers can only skip an
+    // image, so the first one too large is kept to fail with afterwards.
+    let max_image_pixels = render_options
+        .max_image_pixels
+        .unwrap_or(DEFAULT_MAX_IMAGE_PIXELS);
+    let too_large: Arc<Mutex<Option<anyhow::Error>>> = Arc::default();
+    let accept_image = {
+        let too_large = too_large.clone();
+        move |data: &[u8], label: &str| match raster::check_image_pixels(
+            data,
+            None,
+            max_image_pixels,
+            label,
+        ) {
+            Ok(()) => true,
+            Err(e) => {
+                too_large.lock().unwrap().get_or_insert(e);
+                false
+            }
+        }
+    };
+    let resolve_data = ImageHrefResolver::default_data_resolver();
+    options.image_href_resolver.resolve_data = Box::new({
+        let accept_image = accept_image.clone();
+        move |mime, data, options| {
+            if !accept_image(&data, "Embedded image") {
+                return None;
+            }
+            resolve_data(mime, data, options)
+        }
+    });
+    options.image_href_resolver.resolve_string = Box::new(move |href, options| {
+        let path = options.get_abs_path(Path::new(href));
+        match pipeline::read_source(&path) {
+            Ok(data) if !accept_image(&data, &format!("Image {:?}", path)) => None,
+            // Sniffs the format from the content, as for data URIs without
+            // a known type.
+            Ok(data) => {
+                (ImageHrefResolver::default_data_resolver())("text/plain", Arc::new(data), options)
+            }
+            Err(e) => {
+                warn!("Cannot read image {:?}: {}, skipping it", path, e);
+                None
+            }
+        }
+    });
+    let fontdb = options.fontdb_mut();
+    if !render_options.skip_system_fonts {
+        fontdb.load_system_fonts();
+    }
+    for font_dir in &render_options.font_dirs {
+        fontdb.load_fonts_dir(font_dir);
+    }
+    for family in &render_options.font_families {
+        family.apply(&mut options);
+    }
+    if !render_options.languages.is_empty() {
+        options.languages = render_options.languages.clone();
+    }
+    let tree = catch_render_panic(|| Ok(Tree::from_str(&expanded_svg_content, &options)?))?;
+    if let Some(e) = too_large.lock().unwrap().take() {
+        return Err(e);
+    }
+
+    Ok(tree)
+}
+
+/// Returned when usvg or svg2pdf panic on an input instead of reporting an
+/// error, which they occasionally do for malformed-but-parseable SVGs.
+#[derive(Debug)]
+pub struct RenderPanic {
+    pub message: String,
+}
+
+impl fmt::Display for RenderPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "renderer panicked: {}", self.message)
+    }
+}
+
+impl std::error::Error for RenderPanic {}
+
+/// Runs `render`, turning a panic into a [`RenderPanic`] error so a single
+/// pathological page doesn't take down the whole batch.
+fn catch_render_panic<T>(render: impl FnOnce() -> Result<T>) -> Result<T> {
+    // Nothing observes the renderer's state after a panic, it's dropped
+    // together with the failed page.
+    panic::catch_unwind(AssertUnwindSafe(render)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_string());
+
+        Err(RenderPanic { message }.into())
+    })
+}
+
+/// A heading marked up in an SVG page, see [`extract_headings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u32,
+    pub title: String,
+}
+
+/// Collects the headings of an SVG page in document order.
+///
+/// Headings are elements carrying a `data-level` attribute with a positive
+/// level, e.g. `<text data-level="1">Introduction</text>`. Their title is the
+/// whitespace-normalized text of all descendants.
+pub fn extract_headings(svg_content: &str) -> Result<Vec<Heading>> {
+    let root = parse_xml(svg_content)?;
+
+    let mut headings = Vec::new();
+    collect_headings(&root, &mut headings);
+
+    Ok(headings)
+}
+
+fn collect_headings(element: &Element, headings: &mut Vec<Heading>) {
+    let level = element
+        .attributes
+        .get(HEADING_LEVEL_ATTRIBUTE)
+        .and_then(|level| level.trim().parse::<u32>().ok())
+        .filter(|level| *level > 0);
+
+    if let Some(level) = level {
+        let mut text = String::new();
+        collect_text(element, &mut text);
+
+        let title = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !title.is_empty() {
+            headings.push(Heading { level, title });
+        }
+
+        return;
+    }
+
+    for child in &element.children {
+        if let XMLNode::Element(child_element) = child {
+            collect_headings(child_element, headings);
+        }
+    }
+}
+
+/// The text of the `<title>` child of the root `<svg>`, with whitespace
+/// collapsed. Titles of nested elements only describe those elements.
+fn document_title(root: &Element) -> Option<String> {
+    let mut text = String::new();
+    collect_text(root.get_child("title")?, &mut text);
+
+    let title = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        return None;
+    }
+
+    Some(title)
+}
+
+fn collect_text(element: &Element, text: &mut String) {
+    for child in &element.children {
+        match child {
+            XMLNode::Text(content) | XMLNode::CData(content) => text.push_str(content),
+            XMLNode::Element(child_element) => collect_text(child_element, text),
+            _ => {}
+        }
+    }
+}
+
+pub fn tree_to_pdf(tree: &Tree) -> Result<Vec<u8>> {
+    tree_to_pdf_with(tree, &RenderOptions::default())
+}
+
+/// Like [`tree_to_pdf`], but rasterizing filters at
+/// [`RenderOptions::raster_dpi`] and with [`RenderOptions::outline_text`].
+pub fn tree_to_pdf_with(tree: &Tree, render_options: &RenderOptions) -> Result<Vec<u8>> {
+    let mut conversion_options = ConversionOptions {
+        embed_text: !render_options.outline_text,
+        ..ConversionOptions::default()
+    };
+    if let Some(dpi) = render_options.raster_dpi {
+        // svg2pdf scales relative to the 72 points of an inch.
+        conversion_options.raster_scale = dpi as f32 / 72.0;
+    }
+
+    catch_render_panic(|| {
+        Ok(svg2pdf::to_pdf(
+            tree,
+            conversion_options,
+            PageOptions::default(),
+        ))
+    })
+}
+
+/// Renders a single SVG document to a standalone one-page PDF.
+///
+/// Rendering is CPU bound and never yields. Async callers should run it on a
+/// blocking thread pool, e.g.
+/// `tokio::task::spawn_blocking(move || render_svg_to_pdf(&svg)).await?`.
+pub fn render_svg_to_pdf(svg_content: &str) -> Result<Vec<u8>> {
+    render_svg_to_pdf_with(svg_content, &RenderOptions::default())
+}
+
+/// Like [`render_svg_to_pdf`], but with control over how the page is rendered.
+///
+/// PDFs embedded as `data:application/pdf` images are not part of the
+/// result, use [`render_page`] to get them as well.
+pub fn render_svg_to_pdf_with(
+    svg_content: &str,
+    render_options: &RenderOptions,
+) -> Result<Vec<u8>> {
+    Ok(render_page(svg_content, render_options)?.pdf)
+}
+
+/// How long each phase of rendering a single SVG took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderTimings {
+    /// Parsing the XML and expanding embedded base64 SVGs.
+    pub expansion: Duration,
+    /// Building the usvg tree, including font loading and text layout.
+    pub parsing: Duration,
+    /// Converting the tree to PDF with svg2pdf.
+    pub conversion: Duration,
+    /// Rasterizing the thumbnail [`RenderOptions::thumbnail_size`] asks
+    /// for, zero without one.
+    pub thumbnail: Duration,
+}
+
+/// Like [`render_svg_to_pdf_with`], but also reports how long each phase took.
+pub fn render_svg_to_pdf_timed(
+    svg_content: &str,
+    render_options: &RenderOptions,
+) -> Result<(Vec<u8>, RenderTimings)> {
+    let page = render_page(svg_content, render_options)?;
+
+    Ok((page.pdf, page.timings))
+}
+
+/// Returns the bounding box of everything drawn on the page, or `None` when
+/// the page has no visible content.
+///
+/// The box includes strokes and filter regions and is clipped to the canvas,
+/// since anything outside of it is cut off by the page anyway.
+pub fn content_bounding_box(tree: &Tree) -> Option<BoundingBox> {
+    let root = tree.root();
+    if !root.has_children() {
+        return None;
+    }
+
+    let rect = root.abs_layer_bounding_box();
+    let size = tree.size();
+
+    let left = rect.left().max(0.0);
+    let right = rect.right().min(size.width());
+    let top = rect.top().max(0.0);
+    let bottom = rect.bottom().min(size.height());
+
+    if left >= right || top >= bottom {
+        return None;
+    }
+
+    // usvg uses a y-down coordinate system, PDF a y-up one.
+    Some(BoundingBox {
+        left,
+        bottom: size.height() - bottom,
+        right,
+        top: size.height() - top,
+    })
+}
+
+/// Computes the box a page should be trimmed to so that `margin` points of
+/// whitespace remain around its content.
+///
+/// Returns `None` when the page is already tight, i.e. trimming it would not
+/// remove anything.
+pub fn autocrop_box(tree: &Tree, margin: f32) -> Option<BoundingBox> {
+    let size = tree.size();
+    let page = BoundingBox {
+        left: 0.0,
+        bottom: 0.0,
+        right: size.width(),
+        top: size.height(),
+    };
+
+    let cropped = content_bounding_box(tree)?.grow(margin).intersect(page);
+    if cropped == page {
+        return None;
+    }
+
+    Some(cropped)
+}
+
+/// Sets the page box named `key` (e.g. `MediaBox` or `CropBox`) of every page
+/// in `pdf` to `bbox`.
+pub fn set_page_box(pdf: &[u8], key: &str, bbox: BoundingBox) -> Result<Vec<u8>> {
+    let mut doc = Document::load_mem(pdf)?;
+
+    for page_id in doc.page_iter().collect::<Vec<_>>() {
+        doc.get_dictionary_mut(page_id)?.set(key, bbox);
+    }
+
+    let mut output = Vec::new();
+    doc.save_to(&mut output)?;
+
+    Ok(output)
+}
+
+/// Everything [`render_page`] produces for a single SVG.
+#[derive(Debug, Clone)]
+pub struct RenderedPage {
+    pub pdf: Vec<u8>,
+    /// Headings marked up in the SVG, see [`extract_headings`].
+    pub headings: Vec<Heading>,
+    /// Text of the SVG's top-level `<title>`, if it has a non-empty one.
+    pub title: Option<String>,
+    /// PDFs embedded as images, merged right after `pdf`.
+    pub embedded_pdfs: Vec<Vec<u8>>,
+    /// Whether the SVG had no size of its own and got
+    /// [`RenderOptions::default_size`].
+    pub default_size: bool,
+    pub timings: RenderTimings,
+    /// Time spent on the page as a whole, including autocropping and
+    /// thumbnails.
+    pub total_time: Duration,
+}
+
+impl RenderedPage {
+    /// Wraps an existing PDF, e.g. a fixed cover page, so it can be merged
+    /// along with rendered pages. Fails if it has no pages, since it would
+    /// add nothing to the merge.
+    pub fn from_pdf(pdf: Vec<u8>) -> Result<RenderedPage> {
+        let start = Instant::now();
+        if Document::load_mem(&pdf)?.get_pages().is_empty() {
+            return Err(anyhow!("The PDF has no pages"));
+        }
+
+        Ok(RenderedPage {
+            pdf,
+            headings: Vec::new(),
+            title: None,
+            embedded_pdfs: Vec::new(),
+            default_size: false,
+            timings: RenderTimings::default(),
+            total_time: start.elapsed(),
+        })
+    }
+
+    /// The documents this page contributes to the merge, in order.
+    pub fn inputs(&self) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(self.pdf.as_slice()).chain(self.embedded_pdfs.iter().map(Vec::as_slice))
+    }
+}
+
+/// Renders a single SVG document, keeping everything needed to merge it
+/// into a report: its headings and the PDFs it embeds.
+pub fn render_page(svg_content: &str, render_options: &RenderOptions) -> Result<RenderedPage> {
+    let start = Instant::now();
+    let (mut root, default_size) = load_svg(svg_content, render_options)?;
+
+    let mut embedded_pdfs = Vec::new();
+    take_embedded_pdfs(&mut root, &mut embedded_pdfs);
+
+    let mut headings = Vec::new();
+    collect_headings(&root, &mut headings);
+    let title = document_title(&root);
+
+    let page_links = links::collect_links(&mut root);
+
+    let expanded = Instant::now();
+    let tree = tree_from_element(&root, render_options)?;
+    let parsed = Instant::now();
+    let layered = if render_options.preserve_layers {
+        layers::render_layers(&root, render_options)?
+    } else {
+        None
+    };
+    let mut pdf = match layered {
+        Some(pdf) => pdf,
+        None => tree_to_pdf_with(&tree, render_options)?,
+    };
+    // Otherwise the page would silently be missing from the merge.
+    if Document::load_mem(&pdf)?.get_pages().is_empty() {
+        return Err(anyhow!("The rendered PDF has no pages"));
+    }
+    let mut timings = RenderTimings {
+        expansion: expanded - start,
+        parsing: parsed - expanded,
+        conversion: parsed.elapsed(),
+        thumbnail: Duration::ZERO,
+    };
+
+    pdf = links::add_links(&pdf, &tree, &page_links)?;
+
+    let crop = render_options
+        .autocrop
+        .and_then(|margin| autocrop_box(&tree, margin));
+    if let Some(bbox) = crop {
+        pdf = set_page_box(&pdf, render_options.autocrop_box.key(), bbox)?;
+    }
+
+    if let Some(size) = render_options.page_size {
+        pdf = page_size::fit_to_page_size(&pdf, size)?;
+    }
+
+    if let Some(max_side) = render_options.thumbnail_size {
+        let thumbnail_start = Instant::now();
+        let thumbnail = thumbnail::render_thumbnail(&tree, crop, max_side)?;
+        pdf = thumbnail::set_page_thumbnail(&pdf, &thumbnail)?;
+        timings.thumbnail = thumbnail_start.elapsed();
+    }
+
+    if render_options.validate {
+        Document::load_mem(&pdf).context("The rendered PDF is not well-formed")?;
+    }
+
+    Ok(RenderedPage {
+        pdf,
+        headings,
+        title,
+        embedded_pdfs,
+        default_size,
+        timings,
+        total_time: start.elapsed(),
+    })
+}
+
+/// A single bookmark of the merged document's outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineEntry {
+    /// Nesting depth, starting at 1 for top-level bookmarks.
+    pub level: u32,
+    pub title: String,
+    /// Index of the input PDF whose first page the bookmark points at.
+    pub input: usize,
+}
+
+/// Options controlling how [`merge_pdfs_with`] combines its inputs.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Bookmarks to create instead of one per input.
+    ///
+    /// `OutlineEntry::input` refers to the position of a PDF in the merged
+    /// inputs; entries pointing at inputs without pages are skipped.
+    pub outline: Option<Vec<OutlineEntry>>,
+    /// How to resolve form fields of different inputs sharing a name.
+    pub field_clash: FieldClash,
+    /// Maximum number of kids per page tree node. When set, pages are spread
+    /// over a balanced tree of intermediate `Pages` nodes instead of being
+    /// direct kids of the root, which helps viewers with very large reports.
+    pub page_tree_fanout: Option<usize>,
+    /// How to tell apart bookmarks sharing a title.
+    pub duplicate_titles: DuplicateTitles,
+    /// Keep the page tree of every input as its own subtree under the root
+    /// `Pages` node instead of listing all pages directly under the root.
+    pub preserve_source_trees: bool,
+    /// Keep the bookmarks inputs come with, e.g. PDFs merged as they are,
+    /// adding their top-level items to the merged outline after the
+    /// bookmarks of the pages before them. Otherwise only the bookmarks
+    /// [`MergeOptions::outline`] asks for are created.
+    pub preserve_outlines: bool,
+    /// Leave out pages that draw nothing, see [`page_checks::is_blank_page`].
+    /// Inputs left without pages get no bookmark.
+    pub drop_blank_pages: bool,
+    /// Store images drawn on several pages only once, see
+    /// [`images::dedup_images`].
+    pub dedup_images: bool,
+    /// Leave the merged document uncompressed instead of running
+    /// [`Document::compress`] on it. Streams the inputs already compressed
+    /// stay compressed.
+    pub skip_compression: bool,
+    /// Version of the PDF specification the merged document declares.
+    pub pdf_version: PdfVersion,
+}
+
+/// PDF specification version written to the header of the merged document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+pub enum PdfVersion {
+    #[value(name = "1.3")]
+    #[serde(rename = "1.3")]
+    V1_3,
+    #[value(name = "1.4")]
+    #[serde(rename = "1.4")]
+    V1_4,
+    #[default]
+    #[value(name = "1.5")]
+    #[serde(rename = "1.5")]
+    V1_5,
+    #[value(name = "1.6")]
+    #[serde(rename = "1.6")]
+    V1_6,
+    #[value(name = "1.7")]
+    #[serde(rename = "1.7")]
+    V1_7,
+    #[value(name = "2.0")]
+    #[serde(rename = "2.0")]
+    V2_0,
+}
+
+impl PdfVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PdfVersion::V1_3 => "1.3",
+            PdfVersion::V1_4 => "1.4",
+            PdfVersion::V1_5 => "1.5",
+            PdfVersion::V1_6 => "1.6",
+            PdfVersion::V1_7 => "1.7",
+            PdfVersion::V2_0 => "2.0",
+        }
+    }
+
+    /// Makes `doc` declare this version when saved.
+    ///
+    /// Cross-reference streams only exist since PDF 1.5, older versions get a
+    /// classic cross-reference table instead.
+    pub fn apply(self, doc: &mut Document) {
+        doc.version = self.as_str().to_string();
+        doc.reference_table.cross_reference_type = if self >= PdfVersion::V1_5 {
+            XrefType::CrossReferenceStream
+        } else {
+            XrefType::CrossReferenceTable
+        };
+    }
+}
+
+/// What to do with bookmarks whose title was already used by an earlier one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateTitles {
+    /// Keep the titles as they are
+    #[default]
+    Keep,
+    /// Append a counter to repeated titles, e.g. "Results (2)"
+    Counter,
+    /// Append the page number to repeated titles, e.g. "Results (p. 7)"
+    Page,
+    /// Drop bookmarks repeating the title of the bookmark right before them
+    Collapse,
+}
+
+/// Catalog key temporarily listing the index of every input kept by
+/// [`MergeOptions::preserve_outlines`] along with its first top-level outline
+/// item. Renumbering only updates references in objects reachable from the
+/// trailer, which the items no longer are once the inputs' `/Outlines` are
+/// dropped.
+const SOURCE_OUTLINES_KEY: &str = "PdfPostprocessSourceOutlines";
+
+/// Adds the outlines of the inputs listed under [`SOURCE_OUTLINES_KEY`] to
+/// the outline of `document`, each after the bookmarks pointing at pages
+/// before the page following the input's first page.
+fn graft_source_outlines(
+    document: &mut Document,
+    first_pages: &[Option<(ObjectId, usize)>],
+) -> Result<()> {
+    let listed = match document
+        .catalog_mut()?
+        .remove(SOURCE_OUTLINES_KEY.as_bytes())
+    {
+        Some(Object::Array(listed)) => listed,
+        _ => return Ok(()),
+    };
+
+    let pages: Vec<ObjectId> = document.page_iter().collect();
+    for pair in listed.chunks_exact(2) {
+        let (input, first) = match (pair[0].as_i64(), pair[1].as_reference()) {
+            (Ok(input), Ok(first)) => (input as usize, first),
+            _ => continue,
+        };
+        let page_number = match first_pages.get(input).copied().flatten() {
+            Some((_, page_number)) => page_number,
+            None => continue,
+        };
+
+        let items = insert::outline_siblings(document, Some(first));
+        insert::graft_outline_items(document, &pages, page_number, &items)?;
+    }
+
+    Ok(())
+}
+
+/// Adds `entries` as bookmarks, nesting each one under the closest preceding
+/// entry with a lower level.
+///
+/// `first_pages` holds the first page of every input along with its 1-based
+/// page number in the merged document.
+fn add_outline(
+    document: &mut Document,
+    entries: &[OutlineEntry],
+    first_pages: &[Option<(ObjectId, usize)>],
+    duplicate_titles: DuplicateTitles,
+) {
+    let mut parents: Vec<(u32, u32)> = Vec::new();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    let mut previous: Option<&OutlineEntry> = None;
+
+    for entry in entries {
+        let (page, page_number) = match first_pages.get(entry.input).copied().flatten() {
+            Some(page) => page,
+            None => continue,
+        };
+
+        if duplicate_titles == DuplicateTitles::Collapse
+            && previous.is_some_and(|previous| {
+                previous.level == entry.level && previous.title == entry.title
+            })
+        {
+            continue;
+        }
+        previous = Some(entry);
+
+        let occurrences = seen.entry(entry.title.as_str()).or_insert(0);
+        *occurrences += 1;
+        let title = match duplicate_titles {
+            DuplicateTitles::Counter if *occurrences > 1 => {
+                format!("{} ({})", entry.title, occurrences)
+            }
+            DuplicateTitles::Page if *occurrences > 1 => {
+                format!("{} (p. {})", entry.title, page_number)
+            }
+            _ => entry.title.clone(),
+        };
+
+        while parents
+            .last()
+            .is_some_and(|(level, _)| *level >= entry.level)
+        {
+            parents.pop();
+        }
+
+        let bookmark = lopdf::Bookmark::new(title, [0.0, 0.0, 1.0], 0, page);
+        let id = document.add_bookmark(bookmark, parents.last().map(|(_, id)| *id));
+        parents.push((entry.level, id));
+    }
+}
+
+// Offloading rendering and merging to a blocking thread pool moves their
+// inputs and outputs across threads, so keep all of them `Send + 'static`.
+const _: fn() = || {
+    fn assert_send<T: Send + 'static>() {}
+
+    assert_send::<String>();
+    assert_send::<Vec<u8>>();
+    assert_send::<Tree>();
+    assert_send::<BoundingBox>();
+    assert_send::<RenderOptions>();
+    assert_send::<Heading>();
+    assert_send::<OutlineEntry>();
+    assert_send::<MergeOptions>();
+    assert_send::<DuplicateTitles>();
+    assert_send::<RenderPanic>();
+    assert_send::<RenderTimings>();
+    assert_send::<RenderedPage>();
+    assert_send::<thumbnail::Thumbnail>();
+    assert_send::<Document>();
+    assert_send::<PdfMerger>();
+    assert_send::<anyhow::Error>();
+};
+
+/// Merges PDFs into one document, adding a bookmark at the first page of each input.
+///
+/// The bookmarks are titled `Page_1`, `Page_2`, ... Use [`merge_pages`] to
+/// give them titles of their own, e.g. the names of the source files.
+///
+/// Like [`render_svg_to_pdf`] this is blocking work; see its documentation
+/// for how to call it from async code. The returned [`Document`] is `Send`
+/// and can be handed back to the async side once merging is done.
+pub fn merge_pdfs(output_files: Vec<&[u8]>) -> Result<Document> {
+    merge_pdfs_with(output_files, &MergeOptions::default())
+}
+
+/// Writes `doc` as a PDF file to `writer`, e.g. a `File` or a `Vec<u8>`,
+/// buffering the many small writes lopdf makes.
+///
+/// This is the save step on its own, so the [`Document`] [`merge_pdfs`] and
+/// the other merge functions return can be changed first, e.g. given
+/// metadata of its own or signed, and then written anywhere.
+pub fn save_document<W: Write>(doc: &mut Document, writer: W) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    doc.save_to(&mut writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Merges rendered pages given as `(title, pdf)` pairs, in the order given,
+/// adding a bookmark with the page's title at its first page.
+///
+/// This is [`merge_pdfs`] without the filesystem: no sorting or title
+/// derivation happens, the caller decides both.
+pub fn merge_pages(pages: Vec<(String, Vec<u8>)>) -> Result<Document> {
+    let outline = pages
+        .iter()
+        .enumerate()
+        .map(|(input, (title, _))| OutlineEntry {
+            level: 1,
+            title: title.clone(),
+            input,
+        })
+        .collect();
+
+    let merge_options = MergeOptions {
+        outline: Some(outline),
+        ..MergeOptions::default()
+    };
+
+    merge_pdfs_with(
+        pages.iter().map(|(_, pdf)| pdf.as_slice()).collect(),
+        &merge_options,
+    )
+}
+
+/// Checks that every page of `merged` has exactly the MediaBox of the
+/// corresponding page of `inputs`, taken in order.
+///
+/// Merging never resizes pages, this turns that into a guarantee for
+/// workflows where even a fraction of a point matters.
+pub fn verify_page_sizes(inputs: &[&[u8]], merged: &Document) -> Result<()> {
+    let mut source_boxes = Vec::new();
+    for input in inputs {
+        let doc = Document::load_mem(input)?;
+        for page_id in doc.page_iter() {
+            source_boxes.push(page::media_box(&doc, page_id)?);
+        }
+    }
+
+    let merged_pages: Vec<ObjectId> = merged.page_iter().collect();
+    if merged_pages.len() != source_boxes.len() {
+        return Err(anyhow!(
+            "The merged document has {} pages, but its inputs have {}.",
+            merged_pages.len(),
+            source_boxes.len()
+        ));
+    }
+
+    for (index, (page_id, source_box)) in merged_pages.iter().zip(source_boxes).enumerate() {
+        let merged_box = page::media_box(merged, *page_id)?;
+        if merged_box != source_box {
+            return Err(anyhow!(
+                "Page {} has MediaBox {:?} after merging, but {:?} in its input.",
+                index + 1,
+                merged_box,
+                source_box
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Like [`merge_pdfs`], but with control over how the inputs are combined.
+pub fn merge_pdfs_with(output_files: Vec<&[u8]>, merge_options: &MergeOptions) -> Result<Document> {
+    let mut merger = PdfMerger::new(merge_options.clone());
+    merger.add_pages(&output_files)?;
+
+    merger.finish()
+}
+
+/// Splits `doc` into one standalone PDF per page, in page order, the inverse
+/// of [`merge_pdfs`].
+///
+/// Each output holds the page, with the attributes it inherits from the page
+/// tree copied onto it, everything the page refers to directly or
+/// indirectly, and a minimal catalog and page tree. References to other
+/// pages, e.g. from link destinations, become `null`. Document-level data
+/// such as the outline, named destinations and forms is not carried over.
+pub fn split_pdf(doc: &Document) -> Result<Vec<Vec<u8>>> {
+    doc.page_iter()
+        .map(|page_id| extract_page(doc, page_id))
+        .collect()
+}
+
+fn extract_page(doc: &Document, page_id: ObjectId) -> Result<Vec<u8>> {
+    let mut page = doc.get_dictionary(page_id)?.clone();
+    for key in page::INHERITABLE_PAGE_KEYS {
+        if let Some(value) = page::inherited_page_attribute(doc, page_id, key) {
+            page.set(key, value);
+        }
+    }
+    page.remove(b"Parent");
+
+    let mut output = Document::with_version(doc.version.clone());
+    output.reference_table.cross_reference_type = doc.reference_table.cross_reference_type;
+    let pages_id = output.new_object_id();
+    let new_page_id = output.new_object_id();
+
+    // Objects copied so far by their id in `doc`, and those still to copy.
+    let mut copied = HashMap::from([(page_id, new_page_id)]);
+    let mut pending = vec![(new_page_id, Object::Dictionary(page))];
+    while let Some((id, mut object)) = pending.pop() {
+        copy_references(doc, &mut object, &mut output, &mut copied, &mut pending);
+        output.objects.insert(id, object);
+    }
+
+    output
+        .get_dictionary_mut(new_page_id)?
+        .set("Parent", pages_id);
+    output.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(new_page_id)],
+            "Count" => 1,
+        }),
+    );
+    let catalog_id = output.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    output.trailer.set("Root", catalog_id);
+
+    let mut bytes = Vec::new();
+    output.save_to(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+/// Points the references of `object` at copies in `output` of the objects
+/// of `doc` they refer to, queueing objects not copied yet in `pending`.
+/// References to pages and page tree nodes, and dangling ones, become
+/// `null`, so no other page is pulled in.
+fn copy_references(
+    doc: &Document,
+    object: &mut Object,
+    output: &mut Document,
+    copied: &mut HashMap<ObjectId, ObjectId>,
+    pending: &mut Vec<(ObjectId, Object)>,
+) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(new_id) = copied.get(id) {
+                *id = *new_id;
+                return;
+            }
+            let target = match doc.get_object(*id) {
+                Ok(target) if !matches!(target.type_name(), Ok("Page" | "Pages")) => target,
+                _ => {
+                    *object = Object::Null;
+                    return;
+                }
+            };
+            let new_id = output.new_object_id();
+            copied.insert(*id, new_id);
+            pending.push((new_id, target.clone()));
+            *id = new_id;
+        }
+        Object::Array(array) => {
+            for item in array {
+                copy_references(doc, item, output, copied, pending);
+            }
+        }
+        Object::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                copy_references(doc, value, output, copied, pending);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                copy_references(doc, value, output, copied, pending);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses a PDF to merge. The merged document gets an information
+/// dictionary of its own, the input's would only be left behind
+/// unreferenced.
+fn load_input(pdf: &[u8]) -> Result<Document> {
+    let mut doc = Document::load_mem(pdf)?;
+    if let Ok(info_id) = doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        doc.objects.remove(&info_id);
+    }
+
+    Ok(doc)
+}
+
+/// What [`PdfMerger`] keeps of an input, renumbered to ids from
+/// `starting_id` on, see [`prepare_input`].
+struct PreparedInput {
+    /// One past the highest id of the input.
+    next_id: u32,
+    /// In page tree order, object ids don't necessarily follow it.
+    pages: Vec<(ObjectId, Object)>,
+    /// Number of pages left out by [`MergeOptions::drop_blank_pages`].
+    dropped_blank_pages: usize,
+    form: Option<Dictionary>,
+    dests: Option<Dictionary>,
+    /// For [`MergeOptions::preserve_outlines`].
+    first_outline_item: Option<ObjectId>,
+    last_media_box: Option<[f32; 4]>,
+    /// For [`MergeOptions::preserve_source_trees`].
+    source_root: Option<ObjectId>,
+    objects: BTreeMap<ObjectId, Object>,
+}
+
+/// Renumbers `doc` to ids from `starting_id` on, one for each of its
+/// objects, and collects what [`PdfMerger`] needs of it. Needs nothing from
+/// other inputs, so inputs can be prepared in parallel.
+fn prepare_input(
+    mut doc: Document,
+    starting_id: u32,
+    merge_options: &MergeOptions,
+) -> Result<PreparedInput> {
+    doc.renumber_objects_with(starting_id);
+
+    let mut pages: Vec<ObjectId> = doc.page_iter().collect();
+    let mut dropped_blank_pages = 0;
+    if merge_options.drop_blank_pages {
+        let page_count = pages.len();
+        let mut blank_pages = Vec::new();
+        for page_id in &pages {
+            if page_checks::is_blank_page(&doc, *page_id)? {
+                blank_pages.push(*page_id);
+            }
+        }
+        if !blank_pages.is_empty() {
+            for page_id in blank_pages {
+                remove_page(&mut doc, page_id)?;
+            }
+            doc.prune_objects();
+        }
+        pages = doc.page_iter().collect();
+        dropped_blank_pages = page_count - pages.len();
+    }
+    let form = acroform::input_form(&doc);
+    let dests = links::input_dests(&doc);
+    let mut first_outline_item = None;
+    if merge_options.preserve_outlines {
+        if let Ok(outlines_id) = doc
+            .catalog()?
+            .get(b"Outlines")
+            .and_then(Object::as_reference)
+        {
+            first_outline_item = insert::outline_children(&doc, outlines_id).first().copied();
+        }
+    }
+    let last_media_box = pages
+        .last()
+        .and_then(|page_id| page::media_box(&doc, *page_id).ok());
+
+    let mut source_root = None;
+    if merge_options.preserve_source_trees {
+        if !pages.is_empty() {
+            source_root = Some(doc.catalog()?.get(b"Pages")?.as_reference()?);
+        }
+    } else {
+        // Pages are re-parented to a single root when merging, so values
+        // they inherit from intermediate nodes have to move onto the pages.
+        page::flatten_page_attributes(&mut doc, &pages);
+    }
+
+    let pages = pages
+        .into_iter()
+        .filter_map(|object_id| match doc.get_object(object_id) {
+            Ok(object) => Some((object_id, object.to_owned())),
+            Err(_) => None,
+        })
+        .collect();
+
+    Ok(PreparedInput {
+        next_id: doc.max_id + 1,
+        pages,
+        dropped_blank_pages,
+        form,
+        dests,
+        first_outline_item,
+        last_media_box,
+        source_root,
+        objects: doc.objects,
+    })
+}
+
+/// Takes the page at `page_id` out of the page tree of `doc`, leaving
+/// whatever only it used unreferenced.
+fn remove_page(doc: &mut Document, page_id: ObjectId) -> Result<()> {
+    let mut parent = doc
+        .get_dictionary(page_id)?
+        .get(b"Parent")
+        .and_then(Object::as_reference)
+        .ok();
+    if let Some(parent_id) = parent {
+        if let Ok(Object::Array(kids)) = doc.get_dictionary_mut(parent_id)?.get_mut(b"Kids") {
+            kids.retain(|kid| kid.as_reference().ok() != Some(page_id));
+        }
+    }
+    while let Some(node_id) = parent {
+        let node = doc.get_dictionary_mut(node_id)?;
+        if let Ok(count) = node.get(b"Count").and_then(Object::as_i64) {
+            node.set("Count", count - 1);
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    doc.objects.remove(&page_id);
+
+    Ok(())
+}
+
+/// Inputs parsed at once per rayon thread by [`PdfMerger::add_pages`], enough
+/// to keep every thread busy while bounding the memory of a large batch.
+pub const MERGE_BATCH_PER_THREAD: usize = 4;
+
+/// Merges PDFs added one at a time, the incremental form of
+/// [`merge_pdfs_with`].
+///
+/// Every input is parsed, renumbered past the objects added before it and
+/// moved into the merger right away, so the caller can drop its bytes after
+/// adding it instead of keeping all inputs alive until the merge is done.
+pub struct PdfMerger {
+    merge_options: MergeOptions,
+    max_id: u32,
+    first_pages: Vec<Option<(ObjectId, usize)>>,
+    forms: Vec<Dictionary>,
+    dests: Vec<Dictionary>,
+    /// The first top-level outline item of every input that has one, along
+    /// with the index of the input, for [`MergeOptions::preserve_outlines`].
+    source_outlines: Vec<(usize, ObjectId)>,
+    documents_pages: Vec<(ObjectId, Object)>,
+    /// The input every page of `documents_pages` came from, `None` for
+    /// blank pages.
+    page_inputs: Vec<Option<usize>>,
+    /// Inputs pages were left out of by [`MergeOptions::drop_blank_pages`],
+    /// once per page.
+    dropped_blank_pages: Vec<usize>,
+    documents_objects: BTreeMap<ObjectId, Object>,
+    source_roots: Vec<ObjectId>,
+    last_media_box: Option<[f32; 4]>,
+}
+
+impl PdfMerger {
+    pub fn new(merge_options: MergeOptions) -> PdfMerger {
+        PdfMerger {
+            merge_options,
+            max_id: 1,
+            first_pages: Vec::new(),
+            forms: Vec::new(),
+            dests: Vec::new(),
+            source_outlines: Vec::new(),
+            documents_pages: Vec::new(),
+            page_inputs: Vec::new(),
+            dropped_blank_pages: Vec::new(),
+            documents_objects: BTreeMap::new(),
+            source_roots: Vec::new(),
+            last_media_box: None,
+        }
+    }
+
+    /// Number of pages added so far, including blank ones.
+    pub fn page_count(&self) -> usize {
+        self.documents_pages.len()
+    }
+
+    /// Index of the input every page added so far came from, counting the
+    /// inputs in the order they were added, or `None` for pages added by
+    /// [`PdfMerger::add_blank_page`].
+    pub fn page_inputs(&self) -> &[Option<usize>] {
+        &self.page_inputs
+    }
+
+    /// Index of the input of every page [`MergeOptions::drop_blank_pages`]
+    /// left out, like [`PdfMerger::page_inputs`].
+    pub fn dropped_blank_pages(&self) -> &[usize] {
+        &self.dropped_blank_pages
+    }
+
+    /// Appends the pages of `pdf`, usually a single rendered page, after
+    /// those added so far. It counts as one input for
+    /// [`MergeOptions::outline`].
+    pub fn add_page(&mut self, pdf: &[u8]) -> Result<()> {
+        let doc = load_input(pdf)?;
+        let input = prepare_input(doc, self.max_id, &self.merge_options)?;
+        self.append(input);
+
+        Ok(())
+    }
+
+    /// Appends the pages of every PDF in `pdfs`, in order, as if each was
+    /// given to [`PdfMerger::add_page`], parsing and renumbering them in
+    /// parallel.
+    ///
+    /// Inputs are handled in batches of [`MERGE_BATCH_PER_THREAD`] per rayon
+    /// thread, so only one batch of parsed documents is held in memory on
+    /// top of the merged one. Every input of a batch gets a range of ids of
+    /// its own up front, from the number of objects it holds, so they can be
+    /// renumbered independently and the result is the same as adding them
+    /// one at a time.
+    pub fn add_pages(&mut self, pdfs: &[&[u8]]) -> Result<()> {
+        let batch_size = rayon::current_num_threads() * MERGE_BATCH_PER_THREAD;
+        for batch in pdfs.chunks(batch_size) {
+            let docs = batch
+                .par_iter()
+                .map(|pdf| load_input(pdf))
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut starting_ids = Vec::with_capacity(docs.len());
+            let mut next_id = self.max_id;
+            for doc in &docs {
+                starting_ids.push(next_id);
+                next_id += doc.objects.len() as u32;
+            }
+
+            let inputs = docs
+                .into_par_iter()
+                .zip(starting_ids)
+                .map(|(doc, starting_id)| prepare_input(doc, starting_id, &self.merge_options))
+                .collect::<Result<Vec<_>>>()?;
+            for input in inputs {
+                self.append(input);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves an input renumbered to start at [`PdfMerger::max_id`] into the
+    /// merger.
+    fn append(&mut self, input: PreparedInput) {
+        if input.pages.is_empty() && input.dropped_blank_pages == 0 {
+            warn!(
+                "Input {} has no pages and adds nothing to the merged document",
+                self.first_pages.len() + 1
+            );
+        }
+        self.max_id = input.next_id;
+        self.first_pages.push(
+            input
+                .pages
+                .first()
+                .map(|(page_id, _)| (*page_id, self.documents_pages.len() + 1)),
+        );
+        self.forms.extend(input.form);
+        self.dests.extend(input.dests);
+        if let Some(first) = input.first_outline_item {
+            self.source_outlines
+                .push((self.first_pages.len() - 1, first));
+        }
+        if input.last_media_box.is_some() {
+            self.last_media_box = input.last_media_box;
+        }
+        self.source_roots.extend(input.source_root);
+        let input_index = self.first_pages.len() - 1;
+        self.page_inputs
+            .extend(input.pages.iter().map(|_| Some(input_index)));
+        self.dropped_blank_pages
+            .extend(std::iter::repeat_n(input_index, input.dropped_blank_pages));
+        self.documents_pages.extend(input.pages);
+        self.documents_objects.extend(input.objects);
+    }
+
+    /// Appends an empty page of the same size as the last page added, or US
+    /// Letter if there is none yet.
+    ///
+    /// The blank page doesn't count as an input for
+    /// [`MergeOptions::outline`], so bookmarks keep pointing at the pages
+    /// they were meant for.
+    pub fn add_blank_page(&mut self) {
+        let [left, bottom, right, top] = self.last_media_box.unwrap_or([0.0, 0.0, 612.0, 792.0]);
+        let contents_id = (self.max_id, 0);
+        let page_id = (self.max_id + 1, 0);
+        self.max_id += 2;
+
+        self.documents_objects.insert(
+            contents_id,
+            Object::Stream(Stream::new(Dictionary::new(), Vec::new())),
+        );
+        let page = Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "MediaBox" => vec![left.into(), bottom.into(), right.into(), top.into()],
+            "Resources" => Dictionary::new(),
+            "Contents" => contents_id,
+        });
+        if self.merge_options.preserve_source_trees {
+            // Hung directly under the root, between the trees of the inputs
+            // around it.
+            self.source_roots.push(page_id);
+        }
+        self.documents_pages.push((page_id, page));
+        self.page_inputs.push(None);
+    }
+
+    /// Replaces [`MergeOptions::outline`], e.g. with bookmarks that are only
+    /// known once every input was added. Only [`PdfMerger::finish`] reads it.
+    pub fn set_outline(&mut self, outline: Option<Vec<OutlineEntry>>) {
+        self.merge_options.outline = outline;
+    }
+
+    /// Builds the merged document from everything added so far.
+    pub fn finish(self) -> Result<Document> {
+        let PdfMerger {
+            merge_options,
+            max_id,
+            first_pages,
+            forms,
+            dests,
+            source_outlines,
+            documents_pages,
+            page_inputs: _,
+            dropped_blank_pages: _,
+            documents_objects,
+            source_roots,
+            last_media_box: _,
+        } = self;
+        let mut document = Document::new();
+        merge_options.pdf_version.apply(&mut document);
+        let preserve_trees = merge_options.preserve_source_trees;
+
+        match &merge_options.outline {
+            Some(entries) => add_outline(
+                &mut document,
+                entries,
+                &first_pages,
+                merge_options.duplicate_titles,
+            ),
+            None => {
+                for (index, (object_id, _)) in first_pages.iter().flatten().enumerate() {
+                    let bookmark = lopdf::Bookmark::new(
+                        format!("Page_{}", index + 1),
+                        [0.0, 0.0, 1.0],
+                        0,
+                        *object_id,
+                    );
+                    document.add_bookmark(bookmark, None);
+                }
+            }
+        }
+
+        let preserve_outlines = merge_options.preserve_outlines;
+
+        let mut catalog_object: Option<(ObjectId, Object)> = None;
+        let mut pages_object: Option<(ObjectId, Object)> = None;
+
+        for (object_id, object) in documents_objects {
+            match object.type_name().unwrap_or("") {
+                "Catalog" => {
+                    catalog_object = Some((catalog_object.map_or(object_id, |(id, _)| id), object));
+                }
+                "Pages" if preserve_trees => {
+                    document.objects.insert(object_id, object);
+                }
+                "Pages" => {
+                    if let Ok(dictionary) = object.as_dict() {
+                        let mut dictionary = dictionary.clone();
+                        if let Some((_, ref object)) = pages_object {
+                            if let Ok(old_dictionary) = object.as_dict() {
+                                dictionary.extend(old_dictionary);
+                            }
+                        }
+
+                        pages_object = Some((
+                            pages_object.map_or(object_id, |(id, _)| id),
+                            Object::Dictionary(dictionary),
+                        ));
+                    }
+                }
+                "Outline" if preserve_outlines => {
+                    document.objects.insert(object_id, object);
+                }
+                "Page" | "Outlines" | "Outline" => {}
+                _ => {
+                    document.objects.insert(object_id, object);
+                }
+            }
+        }
+
+        if preserve_trees && !source_roots.is_empty() {
+            pages_object = Some((
+                (max_id, 0),
+                Object::Dictionary(lopdf::dictionary! { "Type" => "Pages" }),
+            ));
+        }
+
+        let pages_object = match pages_object {
+            Some(pages_object) => pages_object,
+            None => {
+                return Err(anyhow!("Pages root not found."));
+            }
+        };
+
+        for (object_id, object) in documents_pages.iter() {
+            if let Ok(dictionary) = object.as_dict() {
+                let mut dictionary = dictionary.clone();
+                if !preserve_trees {
+                    dictionary.set("Parent", pages_object.0);
+                }
+
+                document
+                    .objects
+                    .insert(*object_id, Object::Dictionary(dictionary));
+            }
+        }
+
+        let catalog_object = match catalog_object {
+            Some(catalog_object) => catalog_object,
+            None => {
+                return Err(anyhow!("Catalog root not found."));
+            }
+        };
+
+        if let Ok(dictionary) = pages_object.1.as_dict() {
+            let mut dictionary = dictionary.clone();
+            dictionary.set("Count", documents_pages.len() as u32);
+
+            let kids = if preserve_trees {
+                // Each input keeps its own page tree, hung under the new root.
+                for root_id in &source_roots {
+                    document
+                        .get_dictionary_mut(*root_id)?
+                        .set("Parent", pages_object.0);
+                }
+                source_roots
+            } else {
+                documents_pages
+                    .into_iter()
+                    .map(|(object_id, _)| object_id)
+                    .collect()
+            };
+            dictionary.set(
+                "Kids",
+                kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+            );
+
+            document
+                .objects
+                .insert(pages_object.0, Object::Dictionary(dictionary));
+        }
+
+        // Parent fields created while resolving name clashes need fresh ids.
+        document.max_id = document
+            .objects
+            .keys()
+            .map(|(id, _)| *id)
+            .max()
+            .unwrap_or(0);
+        let acroform = acroform::merge_forms(&mut document, forms, merge_options.field_clash);
+
+        if let Ok(dictionary) = catalog_object.1.as_dict() {
+            let mut dictionary = dictionary.clone();
+            dictionary.set("Pages", pages_object.0);
+            dictionary.remove(b"Outlines");
+            if !source_outlines.is_empty() {
+                let listed: Vec<Object> = source_outlines
+                    .iter()
+                    .flat_map(|(input, first)| {
+                        [Object::Integer(*input as i64), Object::Reference(*first)]
+                    })
+                    .collect();
+                dictionary.set(SOURCE_OUTLINES_KEY, listed);
+            }
+
+            match acroform {
+                Some(acroform) => dictionary.set("AcroForm", acroform),
+                None => {
+                    dictionary.remove(b"AcroForm");
+                }
+            }
+
+            match links::merge_dests(&document, dests) {
+                Some(dests) => dictionary.set("Dests", dests),
+                None => {
+                    dictionary.remove(b"Dests");
+                }
+            }
+
+            document
+                .objects
+                .insert(catalog_object.0, Object::Dictionary(dictionary));
+        }
+
+        document.trailer.set("Root", catalog_object.0);
+        fonts::dedup_font_files(&mut document);
+        if merge_options.dedup_images {
+            images::dedup_images(&mut document);
+        }
+        layers::merge_layers(&mut document)?;
+        document.max_id = document.objects.len() as u32;
+        document.renumber_objects();
+        document.adjust_zero_pages();
+
+        // Renumbering moves the catalog whenever objects of the inputs were
+        // dropped before it, e.g. their outlines, so look it up again.
+        let catalog_id = document.trailer.get(b"Root")?.as_reference()?;
+        if let Some(n) = document.build_outline() {
+            if let Ok(Object::Dictionary(ref mut dict)) = document.get_object_mut(catalog_id) {
+                dict.set("Outlines", Object::Reference(n));
+            }
+        }
+        if preserve_outlines {
+            graft_source_outlines(&mut document, &first_pages)?;
+        }
+
+        if let Some(fanout) = merge_options.page_tree_fanout {
+            page::balance_page_tree(&mut document, fanout)?;
+        }
+
+        if !merge_options.skip_compression {
+            document.compress();
+        }
+
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a PDF with one empty 100 x 100 page for every label, in order,
+    /// each with its label in a comment of its content. The pages are added
+    /// last to first, so their object ids run against the page order.
+    fn document_with_pages(labels: &[&str]) -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+
+        let mut kids = Vec::new();
+        for label in labels.iter().rev() {
+            let content_id = doc.add_object(Stream::new(
+                dictionary! {},
+                format!("% {}\n", label).into_bytes(),
+            ));
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+                "Contents" => content_id,
+            });
+            kids.insert(0, Object::Reference(page_id));
+        }
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => labels.len() as i64,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        doc
+    }
+
+    fn to_pdf(mut doc: Document) -> Vec<u8> {
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).unwrap();
+        pdf
+    }
+
+    fn pdf_with_pages(labels: &[&str]) -> Vec<u8> {
+        to_pdf(document_with_pages(labels))
+    }
+
+    /// The labels of [`document_with_pages`] in the page order of `doc`.
+    fn page_labels(doc: &Document) -> Vec<String> {
+        doc.page_iter()
+            .map(|page_id| {
+                let content = doc.get_page_content(page_id).unwrap();
+                String::from_utf8(content).unwrap()[2..].trim().to_string()
+            })
+            .collect()
+    }
+
+    /// Titles of the top-level bookmarks of `doc`, in order.
+    fn bookmark_titles(doc: &Document) -> Vec<String> {
+        let outlines = doc
+            .catalog()
+            .unwrap()
+            .get_deref(b"Outlines", doc)
+            .and_then(Object::as_dict)
+            .unwrap();
+
+        let mut titles = Vec::new();
+        let mut item = outlines.get(b"First").and_then(Object::as_reference).ok();
+        while let Some(item_id) = item {
+            let dict = doc.get_dictionary(item_id).unwrap();
+            let title = dict.get(b"Title").and_then(Object::as_str).unwrap();
+            titles.push(String::from_utf8_lossy(title).into_owned());
+            item = dict.get(b"Next").and_then(Object::as_reference).ok();
+        }
+
+        titles
+    }
+
+    /// Checks that `/Root` of `doc` resolves to a catalog whose page tree
+    /// counts `page_count` pages, also after saving and loading it again.
+    fn assert_valid_catalog(doc: &mut Document, page_count: usize) {
+        for doc in [
+            doc.clone(),
+            Document::load_mem(&to_pdf(doc.clone())).unwrap(),
+        ] {
+            let catalog = doc.catalog().unwrap();
+            assert_eq!(catalog.get(b"Type").unwrap().as_name().unwrap(), b"Catalog");
+
+            let pages = catalog
+                .get_deref(b"Pages", &doc)
+                .and_then(Object::as_dict)
+                .unwrap();
+            assert_eq!(pages.get(b"Type").unwrap().as_name().unwrap(), b"Pages");
+            assert_eq!(
+                pages.get(b"Count").unwrap().as_i64().unwrap(),
+                page_count as i64
+            );
+            assert_eq!(doc.get_pages().len(), page_count);
+        }
+    }
+
+    #[test]
+    fn merges_a_single_page() {
+        let mut merged = merge_pdfs(vec![&pdf_with_pages(&["a"])]).unwrap();
+
+        assert_valid_catalog(&mut merged, 1);
+        assert_eq!(page_labels(&merged), ["a"]);
+        assert_eq!(bookmark_titles(&merged), ["Page_1"]);
+    }
+
+    #[test]
+    fn replaces_the_outlines_of_inputs() {
+        let with_outline = |label| {
+            let mut doc = document_with_pages(&[label]);
+            let page_id = doc.page_iter().next().unwrap();
+            let outlines_id = doc.new_object_id();
+            let item_id = doc.add_object(dictionary! {
+                "Title" => Object::string_literal("Old bookmark"),
+                "Parent" => outlines_id,
+                "Dest" => vec![page_id.into(), "Fit".into()],
+            });
+            doc.objects.insert(
+                outlines_id,
+                Object::Dictionary(dictionary! {
+                    "Type" => "Outlines",
+                    "First" => item_id,
+                    "Last" => item_id,
+                    "Count" => 1,
+                }),
+            );
+            // Move the catalog past the outline, so that dropping the
+            // outline moves the catalog when the merged document is
+            // renumbered.
+            let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+            let mut catalog = doc.objects.remove(&catalog_id).unwrap();
+            catalog.as_dict_mut().unwrap().set("Outlines", outlines_id);
+            let catalog_id = doc.add_object(catalog);
+            doc.trailer.set("Root", catalog_id);
+            to_pdf(doc)
+        };
+
+        let mut merged = merge_pdfs(vec![&with_outline("a"), &with_outline("b")]).unwrap();
+
+        assert_valid_catalog(&mut merged, 2);
+        assert_eq!(page_labels(&merged), ["a", "b"]);
+        assert_eq!(bookmark_titles(&merged), ["Page_1", "Page_2"]);
+    }
+
+    #[test]
+    fn recounts_pages_of_inputs_with_a_wrong_count() {
+        let mut doc = document_with_pages(&["a", "b"]);
+        let pages_id = doc
+            .catalog()
+            .unwrap()
+            .get(b"Pages")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        doc.get_dictionary_mut(pages_id).unwrap().set("Count", 5);
+
+        let mut merged = merge_pdfs(vec![&to_pdf(doc), &pdf_with_pages(&["c"])]).unwrap();
+
+        assert_valid_catalog(&mut merged, 3);
+        assert_eq!(page_labels(&merged), ["a", "b", "c"]);
+        assert_eq!(bookmark_titles(&merged), ["Page_1", "Page_2"]);
+    }
+
+    #[test]
+    fn drops_the_info_of_a_single_page_input() {
+        let mut doc = document_with_pages(&["a"]);
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::string_literal("Stale title"),
+        });
+        doc.trailer.set("Info", info_id);
+
+        let mut merged = merge_pdfs(vec![&to_pdf(doc)]).unwrap();
+
+        assert_valid_catalog(&mut merged, 1);
+        assert_eq!(page_labels(&merged), ["a"]);
+        assert!(merged.trailer.get(b"Info").is_err());
+        assert!(!merged.objects.values().any(|object| {
+            object.as_dict().is_ok_and(|dict| {
+                dict.get(b"Title").and_then(Object::as_str).ok() == Some(b"Stale title")
+            })
+        }));
+        let pdf = to_pdf(merged);
+        assert!(!pdf.windows(11).any(|bytes| bytes == b"Stale title"));
+    }
+
+    #[test]
+    fn keeps_the_page_order_of_multi_page_inputs() {
+        // Nest the last two pages under a node of their own, which makes
+        // their object ids come before those of the first page.
+        let mut doc = document_with_pages(&["b", "c"]);
+        let root_id = doc
+            .catalog()
+            .unwrap()
+            .get(b"Pages")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let node_id = doc.new_object_id();
+        let mut node = doc.objects.remove(&root_id).unwrap();
+        node.as_dict_mut().unwrap().set("Parent", root_id);
+        doc.objects.insert(node_id, node);
+        for page_id in doc.page_iter().collect::<Vec<_>>() {
+            doc.get_dictionary_mut(page_id)
+                .unwrap()
+                .set("Parent", node_id);
+        }
+        let content_id = doc.add_object(Stream::new(dictionary! {}, b"% a\n".to_vec()));
+        let first_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => root_id,
+            "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "Contents" => content_id,
+        });
+        doc.objects.insert(
+            root_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![first_id.into(), node_id.into()],
+                "Count" => 3,
+            }),
+        );
+        assert_eq!(page_labels(&doc), ["a", "b", "c"]);
+
+        let mut merged = merge_pdfs(vec![&to_pdf(doc), &pdf_with_pages(&["d", "e"])]).unwrap();
+
+        assert_valid_catalog(&mut merged, 5);
+        assert_eq!(page_labels(&merged), ["a", "b", "c", "d", "e"]);
+        assert_eq!(bookmark_titles(&merged), ["Page_1", "Page_2"]);
+    }
+
+    /// `svg` as a base64 `data:` URI.
+    fn svg_data_uri(svg: &str) -> String {
+        format!("data:image/svg+xml;base64,{}", BASE64_STANDARD.encode(svg))
+    }
+
+    #[test]
+    fn expands_svgs_nested_two_levels_deep() {
+        let inner = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect id="innermost" width="10" height="10"/></svg>"#;
+        let middle = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="20"><image href="{}" width="10" height="10"/></svg>"#,
+            svg_data_uri(inner)
+        );
+        let outer = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="40"><image href="{}" width="20" height="20"/></svg>"#,
+            svg_data_uri(&middle)
+        );
+
+        let expanded = expand_base64_svgs(&outer).unwrap();
+
+        assert!(!expanded.contains("data:image/svg+xml"), "{}", expanded);
+        assert!(expanded.contains(r#"id="innermost""#), "{}", expanded);
+    }
+
+    #[test]
+    fn expanded_svgs_parse_with_usvg() {
+        let embedded = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="0 0 10 10"><defs><rect id="square" width="10" height="10"/></defs><use xlink:href="#square" fill="red"/></svg>"##;
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="40" height="40"><image xlink:href="{}" width="20" height="20"/></svg>"#,
+            svg_data_uri(embedded)
+        );
+
+        let expanded = expand_base64_svgs(&svg).unwrap();
+        assert!(
+            expanded.contains(r#"xmlns="http://www.w3.org/2000/svg""#),
+            "{}",
+            expanded
+        );
+
+        let tree = Tree::from_str(&expanded, &svg2pdf::usvg::Options::default()).unwrap();
+        assert!(tree.root().has_children());
+        assert!(content_bounding_box(&tree).is_some());
+    }
+
+    #[test]
+    fn decodes_base64_wrapped_over_lines() {
+        let encoded = BASE64_STANDARD.encode("a line long enough to be wrapped");
+        let wrapped = format!(
+            "{}\n{}\r\n  {}",
+            &encoded[..10],
+            &encoded[10..30],
+            &encoded[30..]
+        );
+
+        assert_eq!(
+            decode_base64(&wrapped).unwrap(),
+            b"a line long enough to be wrapped"
+        );
+    }
+
+    #[test]
+    fn decodes_base64_without_padding() {
+        assert_eq!(decode_base64("aGVsbG8").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGk").unwrap(), b"hi");
+    }
+
+    #[test]
+    fn falls_back_to_forgiving_base64_only_when_needed() {
+        // Strict decoding rejects both, the fallback accepts them.
+        assert!(BASE64_STANDARD.decode("aGVs\nbG8").is_err());
+        assert_eq!(decode_base64("aGVs\nbG8").unwrap(), b"hello");
+
+        // Data the fallback can't decode either reports the strict error.
+        let strict_error = BASE64_STANDARD.decode("aGVs*bG8=").unwrap_err();
+        assert_eq!(decode_base64("aGVs*bG8=").unwrap_err(), strict_error);
+    }
+
+    #[test]
+    fn expands_svgs_with_wrapped_unpadded_base64() {
+        let mut embedded = r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1"><g id="wrapped"/></svg>"#.to_string();
+        // Long enough to wrap, and of a length base64 pads.
+        while embedded.len() < 100 || embedded.len().is_multiple_of(3) {
+            embedded.push(' ');
+        }
+        let encoded = BASE64_STANDARD.encode(&embedded);
+        assert!(encoded.ends_with('='));
+        let wrapped: Vec<&str> = encoded
+            .trim_end_matches('=')
+            .as_bytes()
+            .chunks(76)
+            .map(|line| str::from_utf8(line).unwrap())
+            .collect();
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"><image href="data:image/svg+xml;base64,{}" width="1" height="1"/></svg>"#,
+            wrapped.join("\n")
+        );
+
+        let expanded = expand_base64_svgs(&svg).unwrap();
+
+        assert!(!expanded.contains("data:image/svg+xml"), "{}", expanded);
+        assert!(expanded.contains(r#"id="wrapped""#), "{}", expanded);
+    }
+
+    /// Bounding box of the pixels `svg` paints, as `[left, top, right,
+    /// bottom]`, rasterized at its own size.
+    fn painted_box(svg: &str) -> [u32; 4] {
+        let tree = Tree::from_str(svg, &svg2pdf::usvg::Options::default()).unwrap();
+        let size = tree.size().to_int_size();
+        let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height()).unwrap();
+        resvg::render(&tree, Default::default(), &mut pixmap.as_mut());
+
+        let mut painted = [u32::MAX, u32::MAX, 0, 0];
+        for (index, pixel) in pixmap.pixels().iter().enumerate() {
+            if pixel.alpha() > 127 {
+                let (x, y) = (index as u32 % size.width(), index as u32 / size.width());
+                painted = [
+                    painted[0].min(x),
+                    painted[1].min(y),
+                    painted[2].max(x + 1),
+                    painted[3].max(y + 1),
+                ];
+            }
+        }
+
+        painted
+    }
+
+    #[test]
+    fn expanded_svgs_keep_the_placement_of_the_image() {
+        // A document half as wide as it is tall, filling its viewBox.
+        let embedded = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20" viewBox="0 0 5 10"><rect width="5" height="10"/></svg>"#;
+        let data_uri = svg_data_uri(embedded);
+
+        // Every case puts the edges on whole pixels, where antialiasing can't
+        // tip the comparison.
+        for image in [
+            r#"x="10" y="10" width="40" height="40""#,
+            r#"x="10" y="10" width="40" height="40" preserveAspectRatio="xMinYMax meet""#,
+            r#"x="10" y="10" width="40" height="40" preserveAspectRatio="xMidYMid slice""#,
+            r#"x="10" y="10" width="40" height="40" preserveAspectRatio="none""#,
+            r#"x="6" y="6" width="40" height="40" transform="translate(20 10) scale(0.5)""#,
+            r#"x="10" y="10" width="30""#,
+            r#"x="10" y="10""#,
+        ] {
+            let svg = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100"><image href="{}" {}/></svg>"#,
+                data_uri, image
+            );
+            let expanded = expand_base64_svgs(&svg).unwrap();
+            assert!(!expanded.contains("data:image/svg+xml"), "{}", expanded);
+
+            assert_eq!(
+                painted_box(&expanded),
+                painted_box(&svg),
+                "<image {}>",
+                image
+            );
+        }
+    }
+}
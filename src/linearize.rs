@@ -0,0 +1,1034 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::page::{flatten_page_attributes, INHERITABLE_PAGE_KEYS};
+
+/// Digits reserved for the offsets and lengths in the linearization
+/// dictionary and the first-page trailer, which are only known once
+/// everything after them is laid out.
+const OFFSET_WIDTH: usize = 10;
+
+/// Catalog entries a viewer needs before showing the first page, placed
+/// right after the catalog.
+const OPEN_DOCUMENT_KEYS: [&[u8]; 5] = [
+    b"ViewerPreferences",
+    b"PageMode",
+    b"Threads",
+    b"OpenAction",
+    b"AcroForm",
+];
+
+/// Where an object goes in the linearized file, by what refers to it. When
+/// several things do, the one listed first wins, except that objects used
+/// by several pages become shared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Category {
+    Root,
+    OpenDocument,
+    FirstPageShared,
+    FirstPagePrivate,
+    OtherPageShared,
+    /// Used by the page at this index only.
+    OtherPagePrivate(usize),
+    ThumbnailShared,
+    ThumbnailPrivate(usize),
+    PageTree,
+    /// The `/Outlines` dictionary itself.
+    OutlinesRoot,
+    Outlines,
+    Other,
+}
+
+impl Category {
+    fn page(index: usize) -> Category {
+        if index == 0 {
+            Category::FirstPagePrivate
+        } else {
+            Category::OtherPagePrivate(index)
+        }
+    }
+
+    fn is_page(self) -> bool {
+        matches!(
+            self,
+            Category::FirstPagePrivate | Category::OtherPagePrivate(_)
+        )
+    }
+
+    fn is_shared(self) -> bool {
+        matches!(self, Category::FirstPageShared | Category::OtherPageShared)
+    }
+
+    /// The category of an object already in `self` that is also used as
+    /// `other`.
+    fn combine(self, other: Category) -> Category {
+        let (first, last) = (self.min(other), self.max(other));
+        match (first, last) {
+            (
+                Category::FirstPagePrivate,
+                Category::OtherPageShared | Category::OtherPagePrivate(_),
+            ) => Category::FirstPageShared,
+            (Category::OtherPagePrivate(page), Category::OtherPagePrivate(other))
+                if page != other =>
+            {
+                Category::OtherPageShared
+            }
+            (Category::ThumbnailPrivate(page), Category::ThumbnailPrivate(other))
+                if page != other =>
+            {
+                Category::ThumbnailShared
+            }
+            _ => first,
+        }
+    }
+}
+
+/// Writes `doc` linearized, also known as "fast web view", so viewers
+/// loading it over HTTP can show the first page before the rest has
+/// arrived. lopdf can't do this itself.
+///
+/// Objects are placed in two passes. The first one sorts every object into
+/// the parts of a linearized file (ISO 32000-1, Annex F) by what uses it:
+/// the catalog and what is needed to open the document, everything the
+/// first page needs, the objects of each other page, those shared between
+/// them, and the rest, such as the page tree and the outline. Objects are
+/// renumbered so each part is consecutive, as the hint tables require.
+/// Objects nothing refers to are left out. The second pass lays out the
+/// file, computing the hint tables from the length of every object with the
+/// hint stream left out, as the specification has them, so the
+/// linearization dictionary at the start can then be filled in with the
+/// final offsets.
+///
+/// `doc` is renumbered in the process and inherited page attributes are
+/// moved onto the pages. Fails for encrypted documents, whose encryption
+/// depends on the object numbers, and documents without pages.
+pub fn save_linearized<W: Write>(doc: &mut Document, target: &mut W) -> Result<()> {
+    if doc.trailer.has(b"Encrypt") {
+        return Err(anyhow!("Encrypted documents can't be linearized"));
+    }
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+    if page_ids.is_empty() {
+        return Err(anyhow!("A document without pages can't be linearized"));
+    }
+
+    flatten_page_attributes(doc, &page_ids);
+    for object in doc.objects.values_mut() {
+        if object.type_name().is_ok_and(|name| name == "Pages") {
+            if let Object::Dictionary(node) = object {
+                for key in INHERITABLE_PAGE_KEYS {
+                    node.remove(key);
+                }
+            }
+        }
+    }
+
+    let layout = Layout::new(doc, &page_ids)?;
+    layout.renumber(doc);
+    let pdf = layout.write(doc)?;
+    target.write_all(&pdf)?;
+
+    Ok(())
+}
+
+/// Object ids of every part of the linearized file, in file order, see
+/// [`save_linearized`].
+struct Layout {
+    /// The catalog followed by what is needed to open the document.
+    open_document: Vec<ObjectId>,
+    /// The first page, then the objects only it uses, then those it
+    /// shares with other pages.
+    first_page: Vec<ObjectId>,
+    /// Every other page followed by the objects only it uses.
+    other_pages: Vec<Vec<ObjectId>>,
+    /// Objects used by several pages but not the first.
+    shared: Vec<ObjectId>,
+    rest: Vec<ObjectId>,
+    /// Outline objects, the `/Outlines` dictionary first, as they are placed
+    /// either at the end of `first_page` or within `rest`.
+    outlines: Vec<ObjectId>,
+    categories: BTreeMap<ObjectId, Category>,
+    /// The objects each page uses, in no particular order.
+    page_objects: Vec<BTreeSet<ObjectId>>,
+    /// Old ids to the new ones, see [`Layout::renumber`].
+    new_ids: BTreeMap<ObjectId, u32>,
+    /// Number of objects in the main cross-reference section, which come
+    /// after the first page in the file but are numbered first.
+    main_count: u32,
+}
+
+impl Layout {
+    fn new(doc: &Document, page_ids: &[ObjectId]) -> Result<Layout> {
+        let mut categories = BTreeMap::new();
+        let mut page_objects = Vec::with_capacity(page_ids.len());
+
+        for (index, page_id) in page_ids.iter().enumerate() {
+            let mut objects = BTreeSet::new();
+            categorize(
+                doc,
+                &Object::Reference(*page_id),
+                Category::page(index),
+                &mut categories,
+                &mut objects,
+            );
+            page_objects.push(objects);
+        }
+
+        for (key, value) in doc.trailer.iter() {
+            let user = match key.as_slice() {
+                b"Root" => continue,
+                b"Encrypt" => Category::OpenDocument,
+                _ => Category::Other,
+            };
+            categorize(doc, value, user, &mut categories, &mut BTreeSet::new());
+        }
+
+        let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+        let catalog = doc.get_dictionary(catalog_id)?;
+        for (key, value) in catalog.iter() {
+            let user = match key.as_slice() {
+                key if OPEN_DOCUMENT_KEYS.contains(&key) => Category::OpenDocument,
+                b"Outlines" => Category::Outlines,
+                b"Pages" => Category::PageTree,
+                _ => Category::Other,
+            };
+            categorize(doc, value, user, &mut categories, &mut BTreeSet::new());
+        }
+        let catalog_category = categories
+            .get(&catalog_id)
+            .map_or(Category::Root, |category| category.combine(Category::Root));
+        categories.insert(catalog_id, catalog_category);
+
+        let in_category = |wanted: Category| -> Vec<ObjectId> {
+            categories
+                .iter()
+                .filter(|(_, category)| **category == wanted)
+                .map(|(id, _)| *id)
+                .collect()
+        };
+
+        let mut open_document = in_category(Category::Root);
+        if open_document != [catalog_id] {
+            return Err(anyhow!("The catalog is part of a page"));
+        }
+        open_document.extend(in_category(Category::OpenDocument));
+
+        let mut first_page = vec![page_ids[0]];
+        first_page.extend(
+            in_category(Category::FirstPagePrivate)
+                .into_iter()
+                .filter(|id| *id != page_ids[0]),
+        );
+        first_page.extend(in_category(Category::FirstPageShared));
+
+        let mut other_pages = Vec::with_capacity(page_ids.len() - 1);
+        for (index, page_id) in page_ids.iter().enumerate().skip(1) {
+            let mut objects = vec![*page_id];
+            objects.extend(
+                in_category(Category::OtherPagePrivate(index))
+                    .into_iter()
+                    .filter(|id| id != page_id),
+            );
+            other_pages.push(objects);
+        }
+
+        let shared = in_category(Category::OtherPageShared);
+
+        let mut outlines = in_category(Category::OutlinesRoot);
+        outlines.extend(in_category(Category::Outlines));
+        let outlines_on_first_page = catalog
+            .get(b"PageMode")
+            .and_then(Object::as_name)
+            .is_ok_and(|mode| mode == b"UseOutlines");
+
+        let mut rest = in_category(Category::PageTree);
+        let mut thumbnails: Vec<(usize, ObjectId)> = categories
+            .iter()
+            .filter_map(|(id, category)| match category {
+                Category::ThumbnailPrivate(page) => Some((*page, *id)),
+                _ => None,
+            })
+            .collect();
+        thumbnails.sort();
+        rest.extend(thumbnails.into_iter().map(|(_, id)| id));
+        rest.extend(in_category(Category::ThumbnailShared));
+        if outlines_on_first_page {
+            first_page.extend(&outlines);
+        } else {
+            rest.extend(&outlines);
+        }
+        rest.extend(in_category(Category::Other));
+
+        let mut layout = Layout {
+            open_document,
+            first_page,
+            other_pages,
+            shared,
+            rest,
+            outlines,
+            categories,
+            page_objects,
+            new_ids: BTreeMap::new(),
+            main_count: 0,
+        };
+        layout.number();
+
+        Ok(layout)
+    }
+
+    /// Numbers the main section from 1 in file order, followed by the
+    /// linearization dictionary, the objects to open the document with, the
+    /// hint stream and the first page.
+    fn number(&mut self) {
+        let main = self
+            .other_pages
+            .iter()
+            .flatten()
+            .chain(&self.shared)
+            .chain(&self.rest);
+        let mut next_id = 1;
+        for id in main {
+            self.new_ids.insert(*id, next_id);
+            next_id += 1;
+        }
+        self.main_count = next_id - 1;
+
+        // Skips the linearization dictionary.
+        next_id += 1;
+        for id in &self.open_document {
+            self.new_ids.insert(*id, next_id);
+            next_id += 1;
+        }
+        // Skips the hint stream.
+        next_id += 1;
+        for id in &self.first_page {
+            self.new_ids.insert(*id, next_id);
+            next_id += 1;
+        }
+    }
+
+    fn linearization_id(&self) -> u32 {
+        self.main_count + 1
+    }
+
+    fn hint_stream_id(&self) -> u32 {
+        self.linearization_id() + self.open_document.len() as u32 + 1
+    }
+
+    fn first_page_id(&self) -> u32 {
+        self.hint_stream_id() + 1
+    }
+
+    /// One past the highest object number.
+    fn size(&self) -> u32 {
+        self.first_page_id() + self.first_page.len() as u32
+    }
+
+    /// Gives every placed object of `doc` its new number, dropping the rest
+    /// and turning references to them into `null`.
+    fn renumber(&self, doc: &mut Document) {
+        let objects = std::mem::take(&mut doc.objects);
+        for (id, mut object) in objects {
+            if let Some(new_id) = self.new_ids.get(&id) {
+                renumber_references(&mut object, &self.new_ids);
+                doc.objects.insert((*new_id, 0), object);
+            }
+        }
+        for (_, value) in doc.trailer.iter_mut() {
+            renumber_references(value, &self.new_ids);
+        }
+        doc.max_id = self.size() - 1;
+    }
+
+    /// Lays out the renumbered `doc`, see [`save_linearized`].
+    fn write(&self, doc: &Document) -> Result<Vec<u8>> {
+        let serialize_part = |part: &[ObjectId]| -> Result<Vec<Vec<u8>>> {
+            part.iter()
+                .map(|id| {
+                    let id = self.new_ids[id];
+                    let object = doc
+                        .objects
+                        .get(&(id, 0))
+                        .ok_or_else(|| anyhow!("Object {} went missing", id))?;
+                    indirect_object(id, object)
+                })
+                .collect()
+        };
+        let open_document = serialize_part(&self.open_document)?;
+        let first_page = serialize_part(&self.first_page)?;
+        let other_pages: Vec<Vec<Vec<u8>>> = self
+            .other_pages
+            .iter()
+            .map(|page| serialize_part(page))
+            .collect::<Result<_>>()?;
+        let shared = serialize_part(&self.shared)?;
+        let rest = serialize_part(&self.rest)?;
+
+        let header = format!("%PDF-{}\n%\u{BF}\u{F7}\u{A2}\u{FE}\n", doc.version);
+        // Latin-1 bytes of the binary comment, not UTF-8.
+        let header: Vec<u8> = header.chars().map(|c| c as u8).collect();
+
+        let first_page_count = self.size() - self.linearization_id();
+        let first_trailer = self.first_page_trailer(doc)?;
+        let linearization_length = linearization_dictionary(self.linearization_id(), [0; 7]).len();
+        let first_xref_length = first_page_xref(
+            self.linearization_id(),
+            &vec![0; first_page_count as usize],
+            &first_trailer,
+            0,
+        )
+        .len();
+        let open_document_length: usize = open_document.iter().map(Vec::len).sum();
+        let hint_offset =
+            header.len() + linearization_length + first_xref_length + open_document_length;
+
+        // Offsets of everything after the hint stream, as if it wasn't
+        // there, the way the hint tables count them.
+        let mut offsets: BTreeMap<u32, usize> = BTreeMap::new();
+        let mut lengths: BTreeMap<u32, usize> = BTreeMap::new();
+        let mut offset = hint_offset;
+        let after_hints = self
+            .first_page
+            .iter()
+            .zip(&first_page)
+            .chain(
+                self.other_pages
+                    .iter()
+                    .flatten()
+                    .zip(other_pages.iter().flatten()),
+            )
+            .chain(self.shared.iter().zip(&shared))
+            .chain(self.rest.iter().zip(&rest));
+        for (id, bytes) in after_hints {
+            offsets.insert(self.new_ids[id], offset);
+            lengths.insert(self.new_ids[id], bytes.len());
+            offset += bytes.len();
+        }
+
+        let hint_stream = indirect_object(
+            self.hint_stream_id(),
+            &Object::Stream(self.hint_stream(&offsets, &lengths)),
+        )?;
+        let shift = hint_stream.len();
+        let offset_of = |id: u32| offsets[&id] + shift;
+
+        let last_first_page_id = self.size() - 1;
+        let first_page_end = offset_of(last_first_page_id) + lengths[&last_first_page_id];
+        let main_xref_offset = offset + shift;
+        let main_xref_head = format!("xref\n0 {}\n", self.main_count + 1);
+        let first_xref_offset = header.len() + linearization_length;
+        let main_trailer = self.main_trailer(doc, first_xref_offset)?;
+        let file_length = main_xref_offset
+            + main_xref_head.len()
+            + 20 * (self.main_count as usize + 1)
+            + main_trailer.len();
+
+        let mut first_xref_offsets = vec![header.len()];
+        let mut open_offset = first_xref_offset + first_xref_length;
+        for bytes in &open_document {
+            first_xref_offsets.push(open_offset);
+            open_offset += bytes.len();
+        }
+        first_xref_offsets.push(hint_offset);
+        first_xref_offsets.extend((self.first_page_id()..self.size()).map(offset_of));
+
+        let mut pdf = Vec::with_capacity(file_length);
+        pdf.extend_from_slice(&header);
+        pdf.extend(linearization_dictionary(
+            self.linearization_id(),
+            [
+                file_length,
+                hint_offset,
+                shift,
+                self.first_page_id() as usize,
+                first_page_end,
+                self.other_pages.len() + 1,
+                // The line break right before the entry of object 0.
+                main_xref_offset + main_xref_head.len() - 1,
+            ],
+        ));
+        pdf.extend(first_page_xref(
+            self.linearization_id(),
+            &first_xref_offsets,
+            &first_trailer,
+            main_xref_offset,
+        ));
+        for bytes in open_document {
+            pdf.extend(bytes);
+        }
+        pdf.extend(hint_stream);
+        for bytes in first_page
+            .into_iter()
+            .chain(other_pages.into_iter().flatten())
+            .chain(shared)
+            .chain(rest)
+        {
+            pdf.extend(bytes);
+        }
+
+        if pdf.len() != main_xref_offset {
+            return Err(anyhow!("The linearized layout doesn't add up"));
+        }
+        pdf.extend(main_xref_head.into_bytes());
+        pdf.extend(b"0000000000 65535 f \n");
+        for id in 1..=self.main_count {
+            pdf.extend(format!("{:010} 00000 n \n", offset_of(id)).into_bytes());
+        }
+        pdf.extend(main_trailer);
+        if pdf.len() != file_length {
+            return Err(anyhow!("The linearized layout doesn't add up"));
+        }
+
+        Ok(pdf)
+    }
+
+    /// The trailer of the first-page cross-reference section, which holds
+    /// what the document's trailer did, ending with a `/Prev` entry to be
+    /// filled in.
+    fn first_page_trailer(&self, doc: &Document) -> Result<Vec<u8>> {
+        let mut trailer = doc.trailer.clone();
+        for key in [
+            b"Prev".as_slice(),
+            b"XRefStm",
+            b"Size",
+            b"Type",
+            b"W",
+            b"Index",
+        ] {
+            trailer.remove(key);
+        }
+        trailer.set("Size", self.size() as i64);
+
+        let mut bytes = serialize(&Object::Dictionary(trailer))?;
+        // Reopens the dictionary for the `/Prev` entry.
+        bytes.truncate(bytes.len() - 2);
+        bytes.extend(b"/Prev ");
+
+        Ok(bytes)
+    }
+
+    /// The trailer of the main cross-reference section, whose `startxref`
+    /// leads to the first-page section.
+    fn main_trailer(&self, doc: &Document, first_xref_offset: usize) -> Result<Vec<u8>> {
+        let mut trailer = dictionary! { "Size" => self.main_count as i64 + 1 };
+        if let Ok(id) = doc.trailer.get(b"ID") {
+            trailer.set("ID", id.clone());
+        }
+
+        let mut bytes = b"trailer\n".to_vec();
+        bytes.extend(serialize(&Object::Dictionary(trailer))?);
+        bytes.extend(format!("\nstartxref\n{}\n%%EOF\n", first_xref_offset).into_bytes());
+
+        Ok(bytes)
+    }
+
+    /// The page offset, shared object and outline hint tables, from the
+    /// offsets and lengths of the objects after the hint stream.
+    fn hint_stream(
+        &self,
+        offsets: &BTreeMap<u32, usize>,
+        lengths: &BTreeMap<u32, usize>,
+    ) -> Stream {
+        let length_of =
+            |ids: &[ObjectId]| -> usize { ids.iter().map(|id| lengths[&self.new_ids[id]]).sum() };
+        let pages: Vec<&[ObjectId]> = std::iter::once(self.first_page.as_slice())
+            .chain(self.other_pages.iter().map(Vec::as_slice))
+            .collect();
+
+        // Shared objects are referred to by their position among the
+        // objects of the first page followed by those shared between the
+        // other pages.
+        let shared_index: BTreeMap<ObjectId, usize> = self
+            .first_page
+            .iter()
+            .chain(&self.shared)
+            .enumerate()
+            .map(|(index, id)| (*id, index))
+            .collect();
+        let shared_references: Vec<Vec<usize>> = self
+            .page_objects
+            .iter()
+            .enumerate()
+            .map(|(index, objects)| {
+                if index == 0 {
+                    return Vec::new();
+                }
+                objects
+                    .iter()
+                    .filter(|id| {
+                        self.categories
+                            .get(id)
+                            .is_some_and(|category| category.is_shared())
+                    })
+                    .map(|id| shared_index[id])
+                    .collect()
+            })
+            .collect();
+
+        let object_counts: Vec<usize> = pages.iter().map(|page| page.len()).collect();
+        let page_lengths: Vec<usize> = pages.iter().map(|page| length_of(page)).collect();
+        let (min_objects, object_bits) = range(&object_counts);
+        let (min_length, length_bits) = range(&page_lengths);
+        let (_, reference_count_bits) =
+            range(&shared_references.iter().map(Vec::len).collect::<Vec<_>>());
+        let shared_total = self.first_page.len() + self.shared.len();
+        let identifier_bits = bits(shared_total.saturating_sub(1));
+
+        let mut hints = BitWriter::default();
+        hints.write(min_objects, 32);
+        hints.write(offsets[&self.first_page_id()], 32);
+        hints.write(object_bits, 16);
+        hints.write(min_length, 32);
+        hints.write(length_bits, 16);
+        // Content stream offsets and lengths, which viewers ignore, set the
+        // way Acrobat does: the content starting right at the page and
+        // running through all of it.
+        hints.write(0, 32);
+        hints.write(0, 16);
+        hints.write(min_length, 32);
+        hints.write(length_bits, 16);
+        hints.write(reference_count_bits, 16);
+        hints.write(identifier_bits, 16);
+        // No fractional positions of shared objects within the pages.
+        hints.write(0, 16);
+        hints.write(1, 16);
+
+        hints.write_all(
+            object_counts.iter().map(|count| count - min_objects),
+            object_bits,
+        );
+        hints.write_all(
+            page_lengths.iter().map(|length| length - min_length),
+            length_bits,
+        );
+        hints.write_all(shared_references.iter().map(Vec::len), reference_count_bits);
+        hints.write_all(shared_references.iter().flatten().copied(), identifier_bits);
+        hints.write_all(std::iter::empty(), 0);
+        hints.write_all(page_lengths.iter().map(|_| 0), 0);
+        hints.write_all(
+            page_lengths.iter().map(|length| length - min_length),
+            length_bits,
+        );
+
+        let shared_table_offset = hints.bytes.len();
+        let group_lengths: Vec<usize> = self
+            .first_page
+            .iter()
+            .chain(&self.shared)
+            .map(|id| lengths[&self.new_ids[id]])
+            .collect();
+        let (min_group_length, group_length_bits) = range(&group_lengths);
+        match self.shared.first() {
+            Some(id) => {
+                hints.write(self.new_ids[id] as usize, 32);
+                hints.write(offsets[&self.new_ids[id]], 32);
+            }
+            None => {
+                hints.write(0, 32);
+                hints.write(0, 32);
+            }
+        }
+        hints.write(self.first_page.len(), 32);
+        hints.write(shared_total, 32);
+        // Every group holds a single object.
+        hints.write(0, 16);
+        hints.write(min_group_length, 32);
+        hints.write(group_length_bits, 16);
+        hints.write_all(
+            group_lengths.iter().map(|length| length - min_group_length),
+            group_length_bits,
+        );
+        // No MD5 signatures.
+        hints.write_all(group_lengths.iter().map(|_| 0), 1);
+        hints.write_all(group_lengths.iter().map(|_| 0), 0);
+
+        let mut dict = dictionary! { "S" => shared_table_offset as i64 };
+        if let Some(root) = self.outlines.first() {
+            let first = self.new_ids[root];
+            let end = self
+                .outlines
+                .iter()
+                .map(|id| offsets[&self.new_ids[id]] + lengths[&self.new_ids[id]])
+                .max()
+                .unwrap_or(0);
+            dict.set("O", hints.bytes.len() as i64);
+            hints.write(first as usize, 32);
+            hints.write(offsets[&first], 32);
+            hints.write(self.outlines.len(), 32);
+            hints.write(end - offsets[&first], 32);
+        }
+
+        let mut stream = Stream::new(dict, hints.bytes);
+        // Failing to compress only makes the hint stream larger.
+        let _ = stream.compress();
+        stream
+    }
+}
+
+/// Sorts everything reachable from `start` into a category as used by
+/// `user`, without crossing into other pages or up the page tree. Objects
+/// reached by a page user are also added to `page_objects`.
+fn categorize(
+    doc: &Document,
+    start: &Object,
+    user: Category,
+    categories: &mut BTreeMap<ObjectId, Category>,
+    page_objects: &mut BTreeSet<ObjectId>,
+) {
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![(start, user, true)];
+
+    while let Some((object, user, top)) = pending.pop() {
+        let mut is_page = false;
+        let object = match object {
+            Object::Reference(id) => {
+                if !visited.insert(*id) {
+                    continue;
+                }
+                let resolved = match doc.objects.get(id) {
+                    Some(resolved) => resolved,
+                    None => continue,
+                };
+                let type_name = match resolved {
+                    Object::Dictionary(dict) => dict.get(b"Type").and_then(Object::as_name).ok(),
+                    _ => None,
+                };
+                match type_name {
+                    Some(b"Page") if !top => continue,
+                    Some(b"Page") => is_page = true,
+                    Some(b"Pages") if user.is_page() => continue,
+                    _ => {}
+                }
+
+                let category = match user {
+                    Category::Outlines if top => Category::OutlinesRoot,
+                    _ => user,
+                };
+                let combined = categories
+                    .get(id)
+                    .map_or(category, |previous| previous.combine(category));
+                categories.insert(*id, combined);
+                if user.is_page() {
+                    page_objects.insert(*id);
+                }
+                resolved
+            }
+            object => object,
+        };
+
+        let dict = match object {
+            Object::Array(items) => {
+                pending.extend(items.iter().map(|item| (item, user, false)));
+                continue;
+            }
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &stream.dict,
+            _ => continue,
+        };
+        for (key, value) in dict.iter() {
+            match key.as_slice() {
+                b"Parent" if is_page => {}
+                b"Thumb" if is_page => {
+                    let page = match user {
+                        Category::OtherPagePrivate(page) => page,
+                        _ => 0,
+                    };
+                    pending.push((value, Category::ThumbnailPrivate(page), false));
+                }
+                _ => pending.push((value, user, false)),
+            }
+        }
+    }
+}
+
+fn renumber_references(object: &mut Object, new_ids: &BTreeMap<ObjectId, u32>) {
+    match object {
+        Object::Reference(id) => match new_ids.get(id) {
+            Some(new_id) => *id = (*new_id, 0),
+            None => *object = Object::Null,
+        },
+        Object::Array(items) => {
+            for item in items {
+                renumber_references(item, new_ids);
+            }
+        }
+        Object::Dictionary(dict) => renumber_dictionary(dict, new_ids),
+        Object::Stream(stream) => renumber_dictionary(&mut stream.dict, new_ids),
+        _ => {}
+    }
+}
+
+fn renumber_dictionary(dict: &mut Dictionary, new_ids: &BTreeMap<ObjectId, u32>) {
+    for (_, value) in dict.iter_mut() {
+        renumber_references(value, new_ids);
+    }
+}
+
+/// The linearization dictionary with `values` for `/L`, `/H` (offset and
+/// length), `/O`, `/E`, `/N` and `/T`, padded to the same length whatever
+/// they are.
+fn linearization_dictionary(id: u32, values: [usize; 7]) -> Vec<u8> {
+    let [length, hint_offset, hint_length, first_page, first_page_end, pages, xref] =
+        values.map(|value| format!("{:<width$}", value, width = OFFSET_WIDTH));
+
+    format!(
+        "{} 0 obj\n<</Linearized 1/L {}/H [{} {}]/O {}/E {}/N {}/T {}>>\nendobj\n",
+        id, length, hint_offset, hint_length, first_page, first_page_end, pages, xref
+    )
+    .into_bytes()
+}
+
+/// The cross-reference section of the first page, starting at `first_id`
+/// with an entry for every offset, and its trailer pointing back at the main
+/// section at `main_xref_offset`.
+fn first_page_xref(
+    first_id: u32,
+    offsets: &[usize],
+    trailer: &[u8],
+    main_xref_offset: usize,
+) -> Vec<u8> {
+    let mut bytes = format!("xref\n{} {}\n", first_id, offsets.len()).into_bytes();
+    for offset in offsets {
+        bytes.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+    }
+    bytes.extend(b"trailer\n");
+    bytes.extend_from_slice(trailer);
+    bytes.extend(
+        format!(
+            "{:<width$}>>\nstartxref\n0\n%%EOF\n",
+            main_xref_offset,
+            width = OFFSET_WIDTH
+        )
+        .into_bytes(),
+    );
+
+    bytes
+}
+
+fn indirect_object(id: u32, object: &Object) -> Result<Vec<u8>> {
+    let mut bytes = format!("{} 0 obj\n", id).into_bytes();
+    bytes.extend(serialize(object)?);
+    bytes.extend(b"\nendobj\n");
+
+    Ok(bytes)
+}
+
+/// Writes `object` the way lopdf does, by encoding it as the only operand of
+/// a content stream operation without an operator.
+fn serialize(object: &Object) -> Result<Vec<u8>> {
+    let mut bytes = Content {
+        operations: vec![Operation::new("", vec![object.clone()])],
+    }
+    .encode()?;
+    // The space separating the operand from the missing operator.
+    bytes.pop();
+
+    Ok(bytes)
+}
+
+/// The smallest of `values` and the number of bits needed for how much the
+/// others exceed it.
+fn range(values: &[usize]) -> (usize, usize) {
+    let min = values.iter().copied().min().unwrap_or(0);
+    let max = values.iter().copied().max().unwrap_or(0);
+
+    (min, bits(max - min))
+}
+
+fn bits(value: usize) -> usize {
+    (usize::BITS - value.leading_zeros()) as usize
+}
+
+/// Packs hint table items most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    used: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: usize, bits: usize) {
+        for bit in (0..bits).rev() {
+            self.current = (self.current << 1) | ((value >> bit) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.bytes.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    /// Writes an item for every page or shared object, padding to the next
+    /// byte afterwards, as each item starts on a byte boundary.
+    fn write_all(&mut self, values: impl Iterator<Item = usize>, bits: usize) {
+        for value in values {
+            self.write(value, bits);
+        }
+        if self.used > 0 {
+            self.bytes.push(self.current << (8 - self.used));
+            self.current = 0;
+            self.used = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_COUNT: usize = 3;
+
+    /// A document of [`PAGE_COUNT`] pages drawing their own content with a
+    /// font they share, and an outline.
+    fn document() -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+
+        let mut kids = Vec::new();
+        for index in 0..PAGE_COUNT {
+            let content = format!("BT /F1 12 Tf 10 10 Td (Page {}) Tj ET", index + 1);
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            });
+            kids.push(Object::Reference(page_id));
+        }
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => PAGE_COUNT as i64,
+                "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+            }),
+        );
+
+        let outlines_id = doc.new_object_id();
+        let item_id = doc.add_object(dictionary! {
+            "Title" => Object::string_literal("Page 2"),
+            "Parent" => outlines_id,
+        });
+        doc.objects.insert(
+            outlines_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Outlines",
+                "First" => item_id,
+                "Last" => item_id,
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "Outlines" => outlines_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        doc
+    }
+
+    fn linearized() -> Vec<u8> {
+        let mut pdf = Vec::new();
+        save_linearized(&mut document(), &mut pdf).unwrap();
+        pdf
+    }
+
+    /// The numbers of the linearization dictionary at the start of `pdf`, by
+    /// key, `/H` giving two.
+    fn linearization_values(pdf: &[u8]) -> BTreeMap<String, Vec<usize>> {
+        let text = String::from_utf8_lossy(pdf);
+        let start = text.find("<</Linearized").unwrap() + 2;
+        let end = start + text[start..].find(">>").unwrap();
+
+        text[start..end]
+            .split('/')
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (key, value) = entry.split_once(' ').unwrap();
+                let numbers = value
+                    .split(|c: char| !c.is_ascii_digit())
+                    .filter(|number| !number.is_empty())
+                    .map(|number| number.parse().unwrap())
+                    .collect();
+                (key.to_string(), numbers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_every_page_and_its_content() {
+        let pdf = linearized();
+        let doc = Document::load_mem(&pdf).unwrap();
+        let pages = doc.get_pages();
+
+        assert_eq!(pages.len(), PAGE_COUNT);
+        for (number, page_id) in pages {
+            let content = String::from_utf8(doc.get_page_content(page_id).unwrap()).unwrap();
+            assert!(
+                content.contains(&format!("(Page {})", number)),
+                "page {} draws {:?}",
+                number,
+                content
+            );
+            let fonts = doc.get_page_fonts(page_id);
+            assert_eq!(
+                fonts[b"F1".as_slice()]
+                    .get(b"BaseFont")
+                    .unwrap()
+                    .as_name()
+                    .unwrap(),
+                b"Helvetica"
+            );
+        }
+        assert!(doc.catalog().unwrap().has(b"Outlines"));
+    }
+
+    #[test]
+    fn linearization_dictionary_matches_the_written_bytes() {
+        let pdf = linearized();
+        let values = linearization_values(&pdf);
+        let doc = Document::load_mem(&pdf).unwrap();
+        let first_page_id = doc.page_iter().next().unwrap();
+        let starts_with =
+            |offset: usize, prefix: &str| pdf[offset..].starts_with(prefix.as_bytes());
+
+        assert_eq!(values["L"], [pdf.len()]);
+        assert_eq!(values["N"], [PAGE_COUNT]);
+        assert_eq!(values["O"], [first_page_id.0 as usize]);
+
+        // The hint stream, followed right away by the first page.
+        let [hint_offset, hint_length] = values["H"][..] else {
+            panic!("/H is {:?}", values["H"]);
+        };
+        let hint_id = first_page_id.0 - 1;
+        assert!(starts_with(hint_offset, &format!("{} 0 obj", hint_id)));
+        assert!(pdf[..hint_offset + hint_length].ends_with(b"endobj\n"));
+        assert!(starts_with(
+            hint_offset + hint_length,
+            &format!("{} 0 obj", first_page_id.0)
+        ));
+
+        // The end of the first page's objects, where the other pages start,
+        // numbered from 1.
+        let end = values["E"][0];
+        assert!(pdf[..end].ends_with(b"endobj\n"));
+        assert!(starts_with(end, "1 0 obj"));
+
+        // The main cross-reference table.
+        let xref = values["T"][0];
+        assert!(starts_with(xref, "\n0000000000 65535 f "));
+    }
+}
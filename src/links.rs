@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use log::warn;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, StringFormat};
+use svg2pdf::usvg::{Group, Node, Tree};
+use xmltree::{Element, XMLNode};
+
+/// Prefix of the ids given to `<a>` elements without one, so they can be
+/// found in the usvg tree.
+const LINK_ID_PREFIX: &str = "pdf-postprocess-link-";
+
+/// A clickable `<a>` element of an SVG page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// Id of the `<a>` element, generated if it had none.
+    pub id: String,
+    pub target: LinkTarget,
+}
+
+/// Where a [`Link`] leads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// An external address such as `https://example.com` or `mailto:...`.
+    Uri(String),
+    /// The element with this id, e.g. `intro` for `href="#intro"`, on this or
+    /// any other page of the merged document.
+    Fragment(String),
+}
+
+/// Collects the `<a>` elements of an SVG page that have an `href`, giving
+/// those without an `id` a generated one.
+///
+/// usvg treats an `<a>` as a plain group and keeps it, along with its id, in
+/// the tree, which is how [`add_links`] finds its geometry afterwards. Links
+/// inside `<text>` become text spans and lose their id, so they are reported
+/// and skipped.
+pub fn collect_links(root: &mut Element) -> Vec<Link> {
+    let mut links = Vec::new();
+    collect_element_links(root, false, &mut links);
+
+    links
+}
+
+fn collect_element_links(element: &mut Element, in_text: bool, links: &mut Vec<Link>) {
+    let in_text = in_text || element.name == "text";
+
+    if element.name == "a" {
+        if let Some((_, href)) = crate::href_attribute(element) {
+            let target = match href.strip_prefix('#') {
+                Some(fragment) => LinkTarget::Fragment(fragment.to_string()),
+                None => LinkTarget::Uri(href.to_string()),
+            };
+
+            if in_text {
+                warn!("Skipping link to {:?} inside <text>", href);
+            } else {
+                let next_id = format!("{}{}", LINK_ID_PREFIX, links.len() + 1);
+                let id = element
+                    .attributes
+                    .entry("id".to_string())
+                    .or_insert(next_id)
+                    .clone();
+                links.push(Link { id, target });
+            }
+        }
+    }
+
+    for child in &mut element.children {
+        if let XMLNode::Element(child_element) = child {
+            collect_element_links(child_element, in_text, links);
+        }
+    }
+}
+
+/// Adds a `/Link` annotation for every link in `links` to the single page of
+/// `pdf`, rendered from `tree` by svg2pdf, and a named destination for every
+/// element with an id to the catalog's `/Dests`.
+///
+/// usvg reports bounding boxes in canvas coordinates: the origin at the
+/// top-left corner of the SVG viewport, y pointing down, and the viewBox
+/// transform already applied. svg2pdf draws that canvas onto a page of the
+/// same size, scaled by the ratio between the MediaBox and the canvas size
+/// (1 at the default 72 dpi) and flipped so y points up. A canvas point
+/// `(x, y)` thus ends up at `(left + x * sx, top - y * sy)` in PDF user
+/// space, where `left` and `top` are MediaBox edges.
+///
+/// External links get a `/URI` action, `#id` links a `/GoTo` action to the
+/// destination named `id`, which [`crate::merge_pdfs_with`] resolves across
+/// pages. Links whose element draws nothing are skipped.
+pub fn add_links(pdf: &[u8], tree: &Tree, links: &[Link]) -> Result<Vec<u8>> {
+    let mut anchors = Vec::new();
+    collect_anchors(tree.root(), &mut anchors);
+    if links.is_empty() && anchors.is_empty() {
+        return Ok(pdf.to_vec());
+    }
+
+    let mut doc = Document::load_mem(pdf)?;
+    let page_id = match doc.page_iter().next() {
+        Some(page_id) => page_id,
+        None => return Ok(pdf.to_vec()),
+    };
+
+    let [left, bottom, right, top] = crate::page::media_box(&doc, page_id)?;
+    let size = tree.size();
+    let (sx, sy) = (
+        (right - left) / size.width(),
+        (top - bottom) / size.height(),
+    );
+    let to_pdf = |x: f32, y: f32| (left + x * sx, top - y * sy);
+
+    let mut annotations = Vec::new();
+    for link in links {
+        let rect = match tree.node_by_id(&link.id) {
+            Some(node) => node.abs_bounding_box(),
+            None => continue,
+        };
+        if rect.width() <= 0.0 || rect.height() <= 0.0 {
+            continue;
+        }
+
+        let (x1, y1) = to_pdf(rect.left(), rect.bottom());
+        let (x2, y2) = to_pdf(rect.right(), rect.top());
+        let action = match &link.target {
+            LinkTarget::Uri(uri) => dictionary! {
+                "S" => "URI",
+                "URI" => Object::String(uri.as_bytes().to_vec(), StringFormat::Literal),
+            },
+            LinkTarget::Fragment(fragment) => dictionary! {
+                "S" => "GoTo",
+                "D" => Object::Name(fragment.as_bytes().to_vec()),
+            },
+        };
+
+        annotations.push(Object::Reference(doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => vec![x1.into(), y1.into(), x2.into(), y2.into()],
+            "Border" => vec![0.into(), 0.into(), 0.into()],
+            "P" => page_id,
+            "A" => action,
+        })));
+    }
+
+    if !annotations.is_empty() {
+        doc.get_dictionary_mut(page_id)?.set("Annots", annotations);
+    }
+
+    let mut dests = Dictionary::new();
+    for (id, x, y) in anchors {
+        let (x, y) = to_pdf(x, y);
+        dests.set(
+            id,
+            vec![
+                Object::Reference(page_id),
+                "XYZ".into(),
+                x.into(),
+                y.into(),
+                Object::Null,
+            ],
+        );
+    }
+    if !dests.is_empty() {
+        doc.catalog_mut()?.set("Dests", dests);
+    }
+
+    let mut output = Vec::new();
+    doc.save_to(&mut output)?;
+
+    Ok(output)
+}
+
+/// Collects every element id usvg kept, along with the top-left corner of
+/// the element in canvas coordinates.
+fn collect_anchors(group: &Group, anchors: &mut Vec<(String, f32, f32)>) {
+    for node in group.children() {
+        let id = node.id();
+        if !id.is_empty() && !id.starts_with(LINK_ID_PREFIX) {
+            let rect = node.abs_bounding_box();
+            anchors.push((id.to_string(), rect.left(), rect.top()));
+        }
+
+        if let Node::Group(child) = node {
+            collect_anchors(child, anchors);
+        }
+    }
+}
+
+/// Returns the `/Dests` dictionary of a document's catalog, if any.
+pub fn input_dests(doc: &Document) -> Option<Dictionary> {
+    let dests = doc.catalog().ok()?.get(b"Dests").ok()?;
+
+    Some(doc.dereference(dests).ok()?.1.as_dict().ok()?.clone())
+}
+
+/// Combines the named destinations of the merged inputs into one `/Dests`
+/// dictionary, keeping only those a `/GoTo` action of `doc` refers to.
+///
+/// When several inputs define the same name, the first one wins. Returns
+/// `None` if no destination is left.
+pub fn merge_dests(doc: &Document, inputs: Vec<Dictionary>) -> Option<Dictionary> {
+    let referenced = referenced_dest_names(doc);
+    let mut merged = Dictionary::new();
+
+    for dests in inputs {
+        for (name, dest) in dests.iter() {
+            if referenced.contains(name) && !merged.has(name) {
+                merged.set(name.clone(), dest.clone());
+            }
+        }
+    }
+
+    if merged.is_empty() {
+        return None;
+    }
+
+    Some(merged)
+}
+
+/// Names used as the destination of a `/GoTo` link action anywhere in `doc`.
+fn referenced_dest_names(doc: &Document) -> HashSet<Vec<u8>> {
+    doc.objects
+        .values()
+        .filter_map(|object| {
+            let action = object.as_dict().ok()?.get(b"A").ok()?.as_dict().ok()?;
+            if action.get(b"S").and_then(Object::as_name).ok()? != b"GoTo" {
+                return None;
+            }
+
+            Some(action.get(b"D").and_then(Object::as_name).ok()?.to_vec())
+        })
+        .collect()
+}
+
+/// Moves the link annotations of a page, and the named destinations on it
+/// in `/Dests`, along with content scaled by `scale` and then translated by
+/// `(x, y)`.
+pub fn transform_links(
+    doc: &mut Document,
+    page_id: ObjectId,
+    scale: f32,
+    x: f32,
+    y: f32,
+) -> Result<()> {
+    // Leaves anything but numbers alone, e.g. the `null` of a destination
+    // that keeps the current zoom.
+    let transform = |value: &mut Object, offset: f32| {
+        if let Ok(number) = value
+            .as_float()
+            .or_else(|_| value.as_i64().map(|v| v as f32))
+        {
+            *value = Object::Real(number * scale + offset);
+        }
+    };
+
+    let annotation_ids: Vec<ObjectId> = match doc.get_dictionary(page_id)?.get(b"Annots") {
+        Ok(Object::Array(annotations)) => annotations
+            .iter()
+            .filter_map(|annotation| annotation.as_reference().ok())
+            .collect(),
+        _ => Vec::new(),
+    };
+    for annotation_id in annotation_ids {
+        let annotation = doc.get_dictionary_mut(annotation_id)?;
+        if let Ok(Object::Array(rect)) = annotation.get_mut(b"Rect") {
+            for (index, value) in rect.iter_mut().enumerate() {
+                let offset = if index % 2 == 0 { x } else { y };
+                transform(value, offset);
+            }
+        }
+    }
+
+    if let Ok(Object::Dictionary(dests)) = doc.catalog_mut()?.get_mut(b"Dests") {
+        for (_, dest) in dests.iter_mut() {
+            if let Object::Array(dest) = dest {
+                if dest.first().and_then(|page| page.as_reference().ok()) != Some(page_id) {
+                    continue;
+                }
+                if let [_, _, left, top, ..] = &mut dest[..] {
+                    transform(left, x);
+                    transform(top, y);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,316 +1,1132 @@
-use std::collections::BTreeMap;
-use std::fs::read_to_string;
-use std::io::Cursor;
-use std::path::PathBuf;
-use std::{process, str};
+mod config;
+mod manifest;
+mod progress;
+mod report;
+
+use std::fs::write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use base64::Engine;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use globset::Glob;
 use log::{error, info};
-use lopdf::{Document, Object, ObjectId};
-use rayon::prelude::*;
-use svg2pdf::{ConversionOptions, PageOptions};
+
+use crate::config::Config;
+use crate::report::Report;
+use pdf_postprocess::acroform::FieldClash;
+use pdf_postprocess::color_profile::ColorProfile;
+use pdf_postprocess::deadline;
+use pdf_postprocess::encryption::{Encryption, Permission};
+use pdf_postprocess::fonts::FontFamily;
+use pdf_postprocess::metadata::Metadata;
+use pdf_postprocess::page_labels::PageLabels;
+use pdf_postprocess::page_numbers::{self, PageNumberPosition};
+use pdf_postprocess::page_ranges::PageRanges;
+use pdf_postprocess::page_size::{Orientation, PageSize, SizeMatch};
+use pdf_postprocess::pipeline::{self, read_svg, Options, SplitBy, DEBUG_SVG_SUFFIX, STDOUT_PATH};
+use pdf_postprocess::rotation::PageRotation;
+use pdf_postprocess::sort::SortOrder;
+use pdf_postprocess::watermark::{Color, Watermark};
+use pdf_postprocess::{
+    expand_base64_svgs, render_page, Background, DuplicateTitles, MergeOptions, PageBox,
+    PdfVersion, RenderOptions, DEFAULT_MAX_IMAGE_PIXELS,
+};
 use tracing_subscriber::filter::EnvFilter;
-use walkdir::WalkDir;
-use xmltree::Element;
-use xmltree::EmitterConfig;
-use xmltree::XMLNode;
 
-fn expand_base64_svgs(svg_content: &str) -> Result<String> {
-    // Parse the SVG content as an XML element
-    let mut root: Element = Element::parse(Cursor::new(svg_content))?;
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    after_help = "Exit status: 0 on success, 1 if the run failed, 2 for invalid arguments, \
+                  3 if --skip-errors left pages out, 4 if --deadline cut the run short."
+)]
+struct Cli {
+    /// Directories containing the SVG pages, or ZIP archives of them, merged
+    /// in the order given
+    #[arg(value_name = "SVG_DIR")]
+    svg_dirs: Vec<PathBuf>,
 
-    // Recursively process the XML tree to decode base64 SVG images
-    process_element(&mut root).map_err(|e| anyhow::anyhow!(e))?;
+    /// File listing the SVGs to merge, in order, instead of scanning SVG_DIR;
+    /// one path per line, or a JSON array if it ends in `.json`
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
 
-    // Convert the modified XML tree back to a string
-    let mut output = Vec::new();
-    root.write_with_config(&mut output, EmitterConfig::default())?;
-    let result = String::from_utf8(output)?;
+    /// Where to write the merged PDF, `-` for stdout; `merged.pdf` next to the
+    /// pages by default
+    #[arg(short, long, value_name = "PDF")]
+    output: Option<PathBuf>,
 
-    Ok(result)
-}
+    /// Write one PDF per top-level subdirectory of the pages instead of
+    /// merging them all, as `<subdirectory>.pdf` in the --output directory
+    #[arg(long, value_name = "MODE")]
+    split_by: Option<SplitBy>,
 
-fn process_element(element: &mut Element) -> Result<()> {
-    // Process all child elements
-    for child in &mut element.children {
-        if let XMLNode::Element(ref mut child_element) = child {
-            process_element(child_element)?;
-        }
-    }
+    /// Config file with default options, `pdf-postprocess.toml` in the working
+    /// directory if present
+    #[arg(long, value_name = "FILE", env = "PDF_POSTPROCESS_CONFIG")]
+    config: Option<PathBuf>,
 
-    // Check if the element is an <image> element with a base64-encoded SVG in the xlink:href attribute
-    if element.name == "image" {
-        if let Some(href) = element.attributes.get("href") {
-            if let Some(base64_data) = href.strip_prefix("data:image/svg+xml;base64,") {
-                match base64::prelude::BASE64_STANDARD.decode(base64_data) {
-                    Ok(decoded_bytes) => match str::from_utf8(&decoded_bytes) {
-                        Ok(decoded_svg) => {
-                            // Parse the decoded SVG content as an XML element
-                            let decoded_element: Element =
-                                Element::parse(Cursor::new(decoded_svg))?;
-
-                            // Create a new <svg> element to wrap the decoded SVG content
-                            let mut group_element = Element::new("svg");
-
-                            // Transfer the attributes from the <image> element to the <svg> element
-                            for (key, value) in &element.attributes {
-                                if key != "xlink:href" && key != "href" {
-                                    // Exclude the xlink:href, href attribute
-                                    group_element.attributes.insert(key.clone(), value.clone());
-                                }
-                            }
-
-                            for (key, value) in &decoded_element.attributes {
-                                if key != "xmlns" {
-                                    // Exclude the xmlns attribute
-                                    group_element.attributes.insert(key.clone(), value.clone());
-                                }
-                            }
-
-                            // Add the decoded SVG content as children of the <svg> element
-                            for child in decoded_element.children {
-                                group_element.children.push(child);
-                            }
-
-                            // Replace the <image> element with the group_element SVG content
-                            *element = group_element;
-                        }
-                        Err(_) => {
-                            // Handle UTF-8 error, keep the original
-                        }
-                    },
-                    Err(_) => {
-                        // Handle base64 decode error, keep the original
-                    }
-                }
-            }
-        }
-    }
+    /// Expand and render only this SVG file, writing `<name>.expanded.svg` and
+    /// `<name>.pdf` next to it instead of merging a directory
+    #[arg(long, value_name = "SVG")]
+    debug_file: Option<PathBuf>,
 
-    Ok(())
-}
+    /// List the files that would be merged, in order, and the output path
+    /// without rendering or writing anything
+    #[arg(long, conflicts_with = "debug_file")]
+    dry_run: bool,
+
+    /// Also write the PDF rendered from every page, before merging, to this
+    /// directory as `<name>.pdf`
+    #[arg(long, value_name = "DIR")]
+    keep_intermediate: Option<PathBuf>,
+
+    /// Keep rendered pages in this directory and reuse them on later runs
+    /// for SVGs that didn't change, as long as the rendering options didn't
+    /// either
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Also merge the PDF files found in SVG_DIR as they are, ordered among
+    /// the SVGs by their file name
+    #[arg(long)]
+    include_pdfs: bool,
+
+    /// Fail if any entry of SVG_DIR can't be read instead of skipping it
+    #[arg(long)]
+    strict: bool,
+
+    /// Only merge the files of SVG_DIR whose path relative to it matches this
+    /// glob, e.g. `chapter*/*.svg`, can be given multiple times
+    #[arg(long, value_name = "GLOB", value_parser = Glob::new)]
+    include: Vec<Glob>,
+
+    /// Skip the files of SVG_DIR whose path relative to it matches this glob,
+    /// e.g. `**/_draft-*.svg`, can be given multiple times
+    #[arg(long, value_name = "GLOB", value_parser = Glob::new)]
+    exclude: Vec<Glob>,
+
+    /// Order in which the pages are merged
+    #[arg(long, value_enum, default_value = "natural")]
+    sort: SortOrder,
+
+    /// Only merge the pages at these positions in sort order, counted from
+    /// 1, e.g. `1-5,8,10-`
+    #[arg(long, value_name = "RANGES", value_parser = PageRanges::from_str)]
+    pages: Option<PageRanges>,
+
+    /// Trim every page to the bounding box of its content plus MARGIN points
+    #[arg(
+        long,
+        value_name = "MARGIN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "8"
+    )]
+    autocrop: Option<f32>,
+
+    /// Which page box `--autocrop` adjusts
+    #[arg(long, value_enum, default_value = "media")]
+    autocrop_box: PageBox,
+
+    /// Give every page the size of the first page's MediaBox once merged, by
+    /// scaling its content to fit or only resizing the box
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "scale",
+        conflicts_with = "preserve_sizes"
+    )]
+    page_size_from_first: Option<SizeMatch>,
+
+    /// Scale and center every page onto this size, e.g. `a4`, `letter` or
+    /// `500x700` in points
+    #[arg(long, value_name = "SIZE", value_parser = PageSize::from_str)]
+    page_size: Option<PageSize>,
+
+    /// Size of pages whose SVG has no viewBox and no width or height, same
+    /// format as `--page-size`
+    #[arg(long, value_name = "SIZE", value_parser = PageSize::from_str)]
+    default_size: Option<PageSize>,
+
+    /// Turn `--page-size` to portrait or landscape
+    #[arg(long, value_enum)]
+    orientation: Option<Orientation>,
+
+    /// Turn every page, or with `PAGES:DEGREES` e.g. `3:90` or `1-5:270`
+    /// only some, clockwise for viewing, can be given multiple times
+    #[arg(long, value_name = "DEGREES", value_parser = PageRotation::from_str)]
+    rotate: Vec<PageRotation>,
+
+    /// Labels the page navigator of viewers shows, e.g. `roman:1-3,decimal:4-`
+    /// for front matter numbered i to iii followed by pages numbered from 1
+    #[arg(long, value_name = "SPEC", value_parser = PageLabels::from_str)]
+    page_labels: Option<PageLabels>,
+
+    /// Background of pages whose root <svg> doesn't set `data-background`
+    #[arg(long, value_enum, default_value = "transparent")]
+    background: Background,
+
+    /// Build the outline from `data-level` headings in the SVGs instead of one bookmark per page
+    #[arg(long)]
+    heading_bookmarks: bool,
+
+    /// How to resolve form fields of different inputs sharing a name
+    #[arg(long, value_enum, default_value = "rename")]
+    form_field_clash: FieldClash,
+
+    /// Stack all pages vertically onto a single tall page, as wide as the widest page
+    #[arg(long)]
+    continuous: bool,
+
+    /// Attach every source SVG to the merged PDF, so the exact inputs can be
+    /// extracted again
+    #[arg(long)]
+    embed_sources: bool,
+
+    /// Insert blank pages so the pages of every directory start on an odd page, for duplex printing
+    #[arg(long, conflicts_with_all = ["continuous", "preserve_sizes"])]
+    duplex_align: bool,
+
+    /// Fail unless every merged page kept exactly the MediaBox it was rendered with
+    #[arg(long, conflicts_with = "continuous")]
+    preserve_sizes: bool,
+
+    /// Warn about pages that are identical to another page, e.g. an SVG
+    /// exported twice
+    #[arg(long)]
+    warn_duplicates: bool,
+
+    /// Warn about pages that draw nothing
+    #[arg(long)]
+    warn_blank: bool,
+
+    /// Leave out pages that draw nothing
+    #[arg(long, conflicts_with = "preserve_sizes")]
+    drop_blank: bool,
+
+    /// Existing PDF to insert the rendered pages into instead of starting a new document
+    #[arg(long, value_name = "PDF")]
+    base: Option<PathBuf>,
+
+    /// 1-based page of the base document at which the rendered pages are inserted
+    /// (appended when omitted)
+    #[arg(long, value_name = "PAGE", requires = "base", value_parser = clap::value_parser!(u32).range(1..))]
+    insert_at: Option<u32>,
+
+    /// Render at most N pages at the same time, all cores by default; `1`
+    /// renders one page after the other
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Stop starting new renders after this long (e.g. `90s`, `5m`) and merge
+    /// the pages finished so far, exiting with status 4
+    #[arg(long, value_name = "DURATION", value_parser = deadline::parse_duration)]
+    deadline: Option<Duration>,
+
+    /// Give up on a page that takes longer than this to render (e.g. `30`,
+    /// `2m`), failing it like any other broken page
+    #[arg(long, value_name = "DURATION", value_parser = deadline::parse_duration)]
+    timeout: Option<Duration>,
 
-pub fn render_svg_to_pdf(svg_content: &str) -> Result<Vec<u8>> {
-    // Expand base64 encoded SVGs
-    let expanded_svg_content = expand_base64_svgs(svg_content)?;
+    /// Build a balanced page tree with at most this many kids per node instead
+    /// of a single flat node, for reports with thousands of pages
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(2..))]
+    page_tree_fanout: Option<u16>,
 
-    let mut options = svg2pdf::usvg::Options::default();
-    options.fontdb_mut().load_system_fonts();
-    let tree = svg2pdf::usvg::Tree::from_str(&expanded_svg_content, &options)?;
+    /// Keep every input's page tree as a subtree of the merged one instead of
+    /// listing all pages directly under its root
+    #[arg(long, conflicts_with = "page_tree_fanout")]
+    preserve_source_trees: bool,
 
-    let pdf = svg2pdf::to_pdf(&tree, ConversionOptions::default(), PageOptions::default());
+    /// Keep the bookmarks of merged PDFs, listed after the bookmarks of the
+    /// pages before them
+    #[arg(long)]
+    preserve_outlines: bool,
 
-    Ok(pdf)
+    /// Round coordinates and other numbers in page content to this many decimal places
+    #[arg(long, value_name = "DIGITS", value_parser = clap::value_parser!(u8).range(0..=6))]
+    precision: Option<u8>,
+
+    /// Embed a thumbnail of every page, SIZE pixels along its longer side, for
+    /// viewers showing them in their page panel
+    #[arg(
+        long,
+        value_name = "SIZE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "96",
+        value_parser = clap::value_parser!(u32).range(1..=1024)
+    )]
+    thumbnails: Option<u32>,
+
+    /// Down-sample embedded base64 PNG and JPEG images to at most this many
+    /// pixels along their longer side
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    max_image_size: Option<u32>,
+
+    /// Fail on pages with a PNG or JPEG image of more than this many pixels,
+    /// embedded or linked, checked before it is decoded
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        default_value_t = DEFAULT_MAX_IMAGE_PIXELS,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_image_pixels: u64,
+
+    /// Resolution filters like blurs and drop shadows are rasterized at, in
+    /// pixels per inch
+    #[arg(long, value_name = "DPI", value_parser = clap::value_parser!(u32).range(1..=2400))]
+    dpi: Option<u32>,
+
+    /// Convert text to path outlines, so pages look the same without the
+    /// fonts, at the cost of text that can't be selected
+    #[arg(long)]
+    outline_text: bool,
+
+    /// Keep top-level Inkscape layers as layers viewers can show and hide
+    #[arg(long)]
+    preserve_layers: bool,
+
+    /// Also load the fonts in this directory, can be given multiple times
+    #[arg(long, value_name = "DIR")]
+    font_dir: Vec<PathBuf>,
+
+    /// Don't load the fonts installed on the system, only those from
+    /// `--font-dir`, for output that doesn't depend on the host
+    #[arg(long)]
+    no_system_fonts: bool,
+
+    /// Font for a generic family, e.g. `sans:"Noto Sans"`, one of default (text
+    /// without a font-family), serif, sans, monospace, cursive and fantasy;
+    /// can be given multiple times
+    #[arg(long, value_name = "GENERIC:NAME", value_parser = FontFamily::from_str)]
+    font_family: Vec<FontFamily>,
+
+    /// Languages `systemLanguage` attributes are matched against, comma
+    /// separated, e.g. `ar` or `hi,en`
+    #[arg(long, value_name = "LANG", value_delimiter = ',')]
+    lang: Vec<String>,
+
+    /// Parse every rendered page again before merging, to name the SVG a
+    /// malformed PDF came from
+    #[arg(long)]
+    validate: bool,
+
+    /// Title of the merged document, shown by viewers instead of the file name
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Author of the merged document
+    #[arg(long)]
+    author: Option<String>,
+
+    /// Subject of the merged document
+    #[arg(long)]
+    subject: Option<String>,
+
+    /// Keywords of the merged document
+    #[arg(long)]
+    keywords: Option<String>,
+
+    /// Application that created the SVGs, e.g. `typst`
+    #[arg(long, value_name = "NAME")]
+    creator: Option<String>,
+
+    /// Encrypt the merged PDF, asking for this password to open it
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        env = "PDF_POSTPROCESS_PASSWORD",
+        hide_env_values = true
+    )]
+    encrypt_password: Option<String>,
+
+    /// Password lifting the restrictions of `--permissions`, a random one by
+    /// default
+    #[arg(long, value_name = "PASSWORD", requires = "encrypt_password")]
+    owner_password: Option<String>,
+
+    /// What the encrypted PDF allows, comma separated; `--permissions` alone
+    /// allows nothing but reading
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        num_args = 0..,
+        default_value = "print",
+        requires = "encrypt_password"
+    )]
+    permissions: Vec<Permission>,
+
+    /// How to tell apart bookmarks sharing a title
+    #[arg(long, value_enum, default_value = "keep")]
+    duplicate_titles: DuplicateTitles,
+
+    /// Write a JSON summary of the run, with the skipped pages and per-file
+    /// render timings, to this file
+    #[arg(long, value_name = "FILE")]
+    report_json: Option<PathBuf>,
+
+    /// Flate compression level of the output, from 0 (none, fastest) to 9
+    /// (smallest, slowest); streams keep the level they were written with if unset
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u32).range(0..=9))]
+    compression_level: Option<u32>,
+
+    /// Store identical images drawn on several pages, e.g. a logo, only once
+    #[arg(long)]
+    dedup_images: bool,
+
+    /// Don't compress the merged document, e.g. to inspect its streams
+    #[arg(long)]
+    no_compress: bool,
+
+    /// Produce byte-identical output for identical inputs, dating the document
+    /// SOURCE_DATE_EPOCH or 1970-01-01 instead of now
+    #[arg(long)]
+    deterministic: bool,
+
+    /// PDF version the output declares
+    #[arg(long, value_enum, value_name = "VERSION", default_value = "1.5")]
+    pdf_version: PdfVersion,
+
+    /// Declare an output intent with this ICC profile, `sRGB` for the bundled
+    /// one or the path of an ICC file
+    #[arg(long, value_name = "sRGB|FILE")]
+    color_profile: Option<ColorProfile>,
+
+    /// Linearize the merged PDF for fast web view, so browsers show the first
+    /// page while the rest is still downloading
+    #[arg(long, conflicts_with = "encrypt_password")]
+    linearize: bool,
+
+    /// Show a progress bar with the number of pages rendered and the time left
+    /// on stderr
+    #[arg(long)]
+    progress: bool,
+
+    /// Only log warnings and errors, `-qq` only errors; takes precedence over
+    /// `RUST_LOG`
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// Also log debug messages, `-vv` everything; takes precedence over
+    /// `RUST_LOG`
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Log and skip pages that fail to render instead of aborting, merging the
+    /// rest and exiting with status 3 if any page was skipped
+    #[arg(long)]
+    skip_errors: bool,
+
+    /// Stamp "DRAFT — DO NOT DISTRIBUTE" across every page
+    #[arg(long, env = "PDF_POSTPROCESS_DRAFT", value_parser = clap::builder::BoolishValueParser::new())]
+    draft: bool,
+
+    /// Lay this text translucently over every page, e.g. "CONFIDENTIAL"
+    #[arg(long, value_name = "TEXT")]
+    watermark: Option<String>,
+
+    /// Opacity of the watermark, from 0 to 1
+    #[arg(long, value_name = "OPACITY", default_value_t = 0.25)]
+    watermark_opacity: f32,
+
+    /// Counterclockwise angle of the watermark in degrees, along the page's
+    /// diagonal by default
+    #[arg(long, value_name = "DEGREES", allow_negative_numbers = true)]
+    watermark_rotation: Option<f32>,
+
+    /// Prepend a table of contents page listing the bookmarks with their page
+    /// numbers, each line linking to its page
+    #[arg(long)]
+    toc: bool,
+
+    /// Grow every page by this many points of margin on every side into the
+    /// trim size, marked by its CropBox and TrimBox
+    #[arg(long, value_name = "PTS", default_value_t = 0.0)]
+    margin: f32,
+
+    /// Extend the MediaBox this many points beyond the trim size as bleed
+    #[arg(long, value_name = "PTS", default_value_t = 0.0)]
+    bleed: f32,
+
+    /// Color of the watermark as #RRGGBB
+    #[arg(long, value_name = "COLOR", default_value = "#808080", value_parser = Color::from_str)]
+    watermark_color: Color,
+
+    /// Print "Page N of M" at the bottom of every page
+    #[arg(long)]
+    page_numbers: bool,
+
+    /// Where to print the page numbers
+    #[arg(long, value_enum, default_value = "bottom-center")]
+    page_number_position: PageNumberPosition,
+
+    /// Footer text, with `{page}` replaced by the page number and `{total}` by
+    /// the number of pages
+    #[arg(long, value_name = "FORMAT", default_value = page_numbers::DEFAULT_FORMAT)]
+    page_number_format: String,
 }
 
-pub fn merge_pdfs(output_files: Vec<&[u8]>) -> Result<Document> {
-    let mut max_id = 1;
-    let mut pagenum = 1;
-    let mut documents_pages = BTreeMap::new();
-    let mut documents_objects = BTreeMap::new();
-    let mut document = Document::with_version("1.5");
-
-    for output_file in output_files {
-        let mut doc = Document::load_mem(output_file)?;
-        let mut first = false;
-        doc.renumber_objects_with(max_id);
-
-        max_id = doc.max_id + 1;
-
-        documents_pages.extend(
-            doc.get_pages()
-                .into_values()
-                .filter_map(|object_id| {
-                    if !first {
-                        let bookmark = lopdf::Bookmark::new(
-                            format!("Page_{}", pagenum),
-                            [0.0, 0.0, 1.0],
-                            0,
-                            object_id,
-                        );
-                        document.add_bookmark(bookmark, None);
-                        first = true;
-                        pagenum += 1;
-                    }
+impl Cli {
+    /// Options for rendering every page.
+    fn options(&self) -> Options {
+        Options {
+            sort: self.sort,
+            include_pdfs: self.include_pdfs,
+            strict: self.strict,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            pages: self.pages.clone(),
+            cache_dir: self.cache_dir.clone(),
+            skip_errors: self.skip_errors,
+            deadline: self.deadline,
+            timeout: self.timeout,
+            keep_intermediate: self.keep_intermediate.clone(),
+            split_by: self.split_by,
+            render: RenderOptions {
+                background: self.background,
+                autocrop: self.autocrop,
+                autocrop_box: self.autocrop_box,
+                thumbnail_size: self.thumbnails,
+                max_image_size: self.max_image_size,
+                max_image_pixels: Some(self.max_image_pixels),
+                raster_dpi: self.dpi,
+                outline_text: self.outline_text,
+                preserve_layers: self.preserve_layers,
+                font_dirs: self.font_dir.clone(),
+                skip_system_fonts: self.no_system_fonts,
+                font_families: self.font_family.clone(),
+                languages: self.lang.clone(),
+                validate: self.validate,
+                default_size: self.default_size,
+                page_size: self.page_size.map(|size| match self.orientation {
+                    Some(orientation) => size.oriented(orientation),
+                    None => size,
+                }),
+                // Set for every page by pipeline::render_file.
+                resources_dir: None,
+            },
+            heading_bookmarks: self.heading_bookmarks,
+            merge: MergeOptions {
+                outline: None,
+                field_clash: self.form_field_clash,
+                page_tree_fanout: self.page_tree_fanout.map(usize::from),
+                duplicate_titles: self.duplicate_titles,
+                preserve_source_trees: self.preserve_source_trees,
+                preserve_outlines: self.preserve_outlines,
+                drop_blank_pages: self.drop_blank,
+                dedup_images: self.dedup_images,
+                skip_compression: self.no_compress,
+                pdf_version: self.pdf_version,
+            },
+            preserve_sizes: self.preserve_sizes,
+            size_from_first: self.page_size_from_first,
+            warn_duplicates: self.warn_duplicates,
+            warn_blank: self.warn_blank,
+            base: self.base.clone(),
+            insert_at: self.insert_at,
+            draft: self.draft,
+            watermark: self.watermark.clone().map(|text| Watermark {
+                text,
+                opacity: self.watermark_opacity,
+                rotation: self.watermark_rotation,
+                color: self.watermark_color,
+            }),
+            toc: self.toc,
+            margin: self.margin,
+            bleed: self.bleed,
+            page_numbers: self.page_numbers,
+            page_number_position: self.page_number_position,
+            page_number_format: self.page_number_format.clone(),
+            continuous: self.continuous,
+            rotate: self.rotate.clone(),
+            page_labels: self.page_labels.clone(),
+            duplex_align: self.duplex_align,
+            embed_sources: self.embed_sources,
+            precision: self.precision,
+            compression_level: self.compression_level,
+            color_profile: self.color_profile.clone(),
+            metadata: Metadata {
+                title: self.title.clone(),
+                author: self.author.clone(),
+                subject: self.subject.clone(),
+                keywords: self.keywords.clone(),
+                creator: self.creator.clone(),
+            },
+            deterministic: self.deterministic,
+            encryption: self
+                .encrypt_password
+                .clone()
+                .map(|user_password| Encryption {
+                    user_password,
+                    owner_password: self.owner_password.clone(),
+                    permissions: self.permissions.clone(),
+                }),
+            linearize: self.linearize,
+        }
+    }
+
+    /// Takes every option not given on the command line or through its
+    /// environment variable from `config`.
+    fn apply_config(&mut self, matches: &ArgMatches, config: Config) -> Result<()> {
+        let unset = |id: &str| {
+            !matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
 
-                    match doc.get_object(object_id) {
-                        Ok(object) => Some((object_id, object.to_owned())),
-                        Err(_) => None,
+        macro_rules! fill {
+            ($($field:ident),*) => {
+                $(
+                    if unset(stringify!($field)) {
+                        if let Some(value) = config.$field {
+                            self.$field = value.into();
+                        }
                     }
-                })
-                .collect::<BTreeMap<ObjectId, Object>>(),
+                )*
+            };
+        }
+
+        if unset("svg_dirs") {
+            if let Some(svg_dirs) = config.svg_dir {
+                self.svg_dirs = svg_dirs;
+            }
+        }
+
+        fill!(
+            manifest,
+            output,
+            split_by,
+            include_pdfs,
+            strict,
+            sort,
+            autocrop,
+            autocrop_box,
+            page_size_from_first,
+            orientation,
+            background,
+            heading_bookmarks,
+            form_field_clash,
+            continuous,
+            duplex_align,
+            warn_duplicates,
+            warn_blank,
+            drop_blank,
+            embed_sources,
+            preserve_sizes,
+            base,
+            insert_at,
+            jobs,
+            page_tree_fanout,
+            precision,
+            thumbnails,
+            max_image_size,
+            max_image_pixels,
+            dpi,
+            outline_text,
+            preserve_layers,
+            font_dir,
+            no_system_fonts,
+            lang,
+            validate,
+            title,
+            author,
+            subject,
+            keywords,
+            creator,
+            encrypt_password,
+            owner_password,
+            permissions,
+            draft,
+            watermark,
+            watermark_opacity,
+            watermark_rotation,
+            toc,
+            margin,
+            bleed,
+            page_numbers,
+            page_number_position,
+            page_number_format,
+            duplicate_titles,
+            compression_level,
+            dedup_images,
+            no_compress,
+            deterministic,
+            pdf_version,
+            color_profile,
+            linearize,
+            preserve_source_trees,
+            preserve_outlines,
+            report_json,
+            keep_intermediate,
+            cache_dir,
+            progress,
+            skip_errors
         );
-        documents_objects.extend(doc.objects);
-    }
 
-    let mut catalog_object: Option<(ObjectId, Object)> = None;
-    let mut pages_object: Option<(ObjectId, Object)> = None;
+        if unset("page_size") {
+            if let Some(page_size) = &config.page_size {
+                self.page_size = Some(page_size.parse().map_err(|e: String| anyhow!(e))?);
+            }
+        }
 
-    for (object_id, object) in documents_objects.iter() {
-        match object.type_name().unwrap_or("") {
-            "Catalog" => {
-                catalog_object = Some((
-                    catalog_object.map_or(*object_id, |(id, _)| id),
-                    object.clone(),
-                ));
+        if unset("default_size") {
+            if let Some(default_size) = &config.default_size {
+                self.default_size = Some(default_size.parse().map_err(|e: String| anyhow!(e))?);
             }
-            "Pages" => {
-                if let Ok(dictionary) = object.as_dict() {
-                    let mut dictionary = dictionary.clone();
-                    if let Some((_, ref object)) = pages_object {
-                        if let Ok(old_dictionary) = object.as_dict() {
-                            dictionary.extend(old_dictionary);
-                        }
-                    }
+        }
 
-                    pages_object = Some((
-                        pages_object.map_or(*object_id, |(id, _)| id),
-                        Object::Dictionary(dictionary),
-                    ));
-                }
+        if unset("font_family") {
+            if let Some(font_family) = &config.font_family {
+                self.font_family = font_family
+                    .iter()
+                    .map(|family| family.parse().map_err(|e: String| anyhow!(e)))
+                    .collect::<Result<_>>()?;
             }
-            "Page" | "Outlines" | "Outline" => {}
-            _ => {
-                document.objects.insert(*object_id, object.clone());
+        }
+
+        if unset("rotate") {
+            if let Some(rotate) = &config.rotate {
+                self.rotate = rotate
+                    .iter()
+                    .map(|rotation| rotation.parse().map_err(|e: String| anyhow!(e)))
+                    .collect::<Result<_>>()?;
             }
         }
-    }
 
-    let pages_object = match pages_object {
-        Some(pages_object) => pages_object,
-        None => {
-            return Err(anyhow!("Pages root not found."));
+        if unset("page_labels") {
+            if let Some(page_labels) = &config.page_labels {
+                self.page_labels = Some(page_labels.parse().map_err(|e: String| anyhow!(e))?);
+            }
         }
-    };
 
-    for (object_id, object) in documents_pages.iter() {
-        if let Ok(dictionary) = object.as_dict() {
-            let mut dictionary = dictionary.clone();
-            dictionary.set("Parent", pages_object.0);
+        for (name, globs, patterns) in [
+            ("include", &mut self.include, &config.include),
+            ("exclude", &mut self.exclude, &config.exclude),
+        ] {
+            if unset(name) {
+                if let Some(patterns) = patterns {
+                    *globs = patterns
+                        .iter()
+                        .map(|pattern| Glob::new(pattern).map_err(|e| anyhow!(e)))
+                        .collect::<Result<_>>()?;
+                }
+            }
+        }
 
-            document
-                .objects
-                .insert(*object_id, Object::Dictionary(dictionary));
+        if unset("pages") {
+            if let Some(pages) = &config.pages {
+                self.pages = Some(pages.parse().map_err(|e: String| anyhow!(e))?);
+            }
         }
-    }
 
-    let catalog_object = match catalog_object {
-        Some(catalog_object) => catalog_object,
-        None => {
-            return Err(anyhow!("Catalog root not found."));
+        if unset("watermark_color") {
+            if let Some(color) = &config.watermark_color {
+                self.watermark_color = color.parse().map_err(|e: String| anyhow!(e))?;
+            }
         }
-    };
 
-    if let Ok(dictionary) = pages_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
-        dictionary.set("Count", documents_pages.len() as u32);
-        dictionary.set(
-            "Kids",
-            documents_pages
-                .into_keys()
-                .map(Object::Reference)
-                .collect::<Vec<_>>(),
-        );
+        if unset("deadline") {
+            if let Some(deadline) = &config.deadline {
+                self.deadline = Some(deadline::parse_duration(deadline).map_err(|e| anyhow!(e))?);
+            }
+        }
+
+        if unset("timeout") {
+            if let Some(timeout) = &config.timeout {
+                self.timeout = Some(deadline::parse_duration(timeout).map_err(|e| anyhow!(e))?);
+            }
+        }
 
-        document
-            .objects
-            .insert(pages_object.0, Object::Dictionary(dictionary));
+        Ok(())
     }
+}
 
-    if let Ok(dictionary) = catalog_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
-        dictionary.set("Pages", pages_object.0);
-        dictionary.remove(b"Outlines");
+/// Parses the command line, filling in options it leaves out from the config
+/// file, and checks the combined options.
+fn parse_cli() -> Result<Cli> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_logging(&cli);
 
-        document
-            .objects
-            .insert(catalog_object.0, Object::Dictionary(dictionary));
+    if let Some(config_path) = cli.config.clone().or_else(Config::find) {
+        info!("Reading options from {:?}", config_path);
+        cli.apply_config(&matches, Config::load(&config_path)?)?;
     }
 
-    document.trailer.set("Root", catalog_object.0);
-    document.max_id = document.objects.len() as u32;
-    document.renumber_objects();
-    document.adjust_zero_pages();
-
-    if let Some(n) = document.build_outline() {
-        if let Ok(Object::Dictionary(ref mut dict)) = document.get_object_mut(catalog_object.0) {
-            dict.set("Outlines", Object::Reference(n));
+    let mut command = Cli::command();
+    if cli.svg_dirs.is_empty() && cli.manifest.is_none() && cli.debug_file.is_none() {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "no <SVG_DIR> given on the command line or in the config file",
+            )
+            .exit();
+    }
+    // Only read without a manifest or debug file, and would otherwise just
+    // turn up no pages.
+    if cli.manifest.is_none() && cli.debug_file.is_none() {
+        for svg_dir in &cli.svg_dirs {
+            let problem = if !svg_dir.exists() {
+                "does not exist"
+            } else if svg_dir.is_dir() || pipeline::is_zip(svg_dir) {
+                continue;
+            } else if matches!(
+                svg_dir.extension().and_then(|s| s.to_str()),
+                Some("svg" | "svgz")
+            ) {
+                "is a file, not a directory; give the directory it is in, or use --debug-file to render just this file"
+            } else {
+                "is neither a directory nor a ZIP archive"
+            };
+            command
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("<SVG_DIR> {:?} {}", svg_dir, problem),
+                )
+                .exit();
+        }
+    }
+    if cli.split_by.is_some()
+        && cli
+            .output
+            .as_deref()
+            .is_none_or(|output| output == Path::new(STDOUT_PATH))
+    {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--split-by requires --output naming the directory to write to",
+            )
+            .exit();
+    }
+    if cli.svg_dirs.len() > 1 && cli.manifest.is_none() && cli.output.is_none() {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--output is required with more than one <SVG_DIR>",
+            )
+            .exit();
+    }
+    if cli.orientation.is_some() && cli.page_size.is_none() {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--orientation requires --page-size",
+            )
+            .exit();
+    }
+    if cli.insert_at.is_some() && cli.base.is_none() {
+        command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--insert-at requires --base",
+            )
+            .exit();
+    }
+    if cli.preserve_source_trees && cli.page_tree_fanout.is_some() {
+        command
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--preserve-source-trees cannot be used with --page-tree-fanout",
+            )
+            .exit();
+    }
+    if cli.encrypt_password.is_some() && cli.pdf_version < PdfVersion::V1_4 {
+        command
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--encrypt-password needs --pdf-version 1.4 or later",
+            )
+            .exit();
+    }
+    if cli.linearize && cli.encrypt_password.is_some() {
+        command
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--linearize cannot be used with --encrypt-password",
+            )
+            .exit();
+    }
+    if cli.no_compress && cli.compression_level.is_some() {
+        command
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--no-compress cannot be used with --compression-level",
+            )
+            .exit();
+    }
+    if !(0.0..=1.0).contains(&cli.watermark_opacity) {
+        command
+            .error(
+                ErrorKind::InvalidValue,
+                format!(
+                    "--watermark-opacity {} is not between 0 and 1",
+                    cli.watermark_opacity
+                ),
+            )
+            .exit();
+    }
+    for (name, points) in [("--margin", cli.margin), ("--bleed", cli.bleed)] {
+        if points.is_nan() || points < 0.0 {
+            command
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("{} {} must not be negative", name, points),
+                )
+                .exit();
+        }
+    }
+    if (cli.margin > 0.0 || cli.bleed > 0.0) && cli.continuous {
+        command
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--margin and --bleed cannot be used with --continuous",
+            )
+            .exit();
+    }
+    // The `conflicts_with` of these flags only sees the command line, so a
+    // config file that sets one of them is checked here.
+    for (name, set, other, other_set) in [
+        ("--toc", cli.toc, "--continuous", cli.continuous),
+        (
+            "--preserve-sizes",
+            cli.preserve_sizes,
+            "--continuous",
+            cli.continuous,
+        ),
+        (
+            "--duplex-align",
+            cli.duplex_align,
+            "--continuous",
+            cli.continuous,
+        ),
+        (
+            "--duplex-align",
+            cli.duplex_align,
+            "--preserve-sizes",
+            cli.preserve_sizes,
+        ),
+        (
+            "--drop-blank",
+            cli.drop_blank,
+            "--preserve-sizes",
+            cli.preserve_sizes,
+        ),
+        (
+            "--page-size-from-first",
+            cli.page_size_from_first.is_some(),
+            "--preserve-sizes",
+            cli.preserve_sizes,
+        ),
+    ] {
+        if set && other_set {
+            command
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{} cannot be used with {}", name, other),
+                )
+                .exit();
         }
     }
+    if let Some(font_dir) = cli.font_dir.iter().find(|font_dir| !font_dir.is_dir()) {
+        command
+            .error(
+                ErrorKind::InvalidValue,
+                format!("--font-dir {:?} is not a directory", font_dir),
+            )
+            .exit();
+    }
+    if cli.split_by.is_none() && cli.output.as_deref().is_some_and(Path::is_dir) {
+        command
+            .error(
+                ErrorKind::InvalidValue,
+                format!("--output {:?} is a directory", cli.output.unwrap()),
+            )
+            .exit();
+    }
+
+    Ok(cli)
+}
+
+/// Writes the expanded SVG the renderer sees for `svg_path` next to it,
+/// along with the PDF rendered from it.
+fn debug_render(svg_path: &Path, cli: &Cli) -> Result<()> {
+    let svg_content = read_svg(svg_path)?;
 
-    document.compress();
+    let expanded_path = svg_path.with_extension(&DEBUG_SVG_SUFFIX[1..]);
+    info!("Writing expanded SVG to {:?}", &expanded_path);
+    write(&expanded_path, expand_base64_svgs(&svg_content)?)?;
 
-    Ok(document)
+    let pdf_path = svg_path.with_extension("pdf");
+    info!("Rendering file {:?} to {:?}", svg_path, &pdf_path);
+    let render_options = RenderOptions {
+        resources_dir: svg_path.parent().map(Path::to_path_buf),
+        ..cli.options().render
+    };
+    write(&pdf_path, render_page(&svg_content, &render_options)?.pdf)?;
+
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let filter = EnvFilter::new("info");
+/// Logs at the level `--quiet` or `--verbose` ask for, or else as `RUST_LOG`
+/// says, `info` if it isn't set.
+fn init_logging(cli: &Cli) {
+    let level = match (cli.quiet, cli.verbose) {
+        (0, 0) => None,
+        (0, 1) => Some("debug"),
+        (0, _) => Some("trace"),
+        (1, _) => Some("warn"),
+        _ => Some("error"),
+    };
+    let filter = match level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
 
     tracing_subscriber::fmt()
         .with_env_filter(filter)
-        .with_test_writer()
+        // Keep stdout free for `--output -`.
+        .with_writer(progress::LogWriter)
         .init();
+}
 
-    let svg_dir = std::env::args()
-        .nth(1)
-        .expect("Please provide a directory path");
+/// Exit status used when only part of the pages ended up in the merged
+/// document because pages were skipped with `--skip-errors`.
+const PARTIAL_EXIT_CODE: u8 = 3;
 
-    let svg_entries: Vec<_> = WalkDir::new(&svg_dir)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|entry| entry.path().extension().and_then(|s| s.to_str()) == Some("svg"))
-        .collect();
+/// Exit status used when the deadline cut the batch short, which takes
+/// precedence over [`PARTIAL_EXIT_CODE`] if pages were skipped as well.
+const DEADLINE_EXIT_CODE: u8 = 4;
+
+fn main() -> Result<ExitCode> {
+    let start = Instant::now();
+    let cli = parse_cli()?;
+    if let Some(jobs) = cli.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.into())
+            .build_global()?;
+    }
+    if let Some(debug_file) = &cli.debug_file {
+        debug_render(debug_file, &cli)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let options = cli.options();
+    let pages_dirs = match &cli.manifest {
+        Some(manifest_path) => vec![manifest_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf()],
+        None => cli.svg_dirs.clone(),
+    };
+    // parse_cli asks for --output with several directories, and with
+    // --split-by, where it is the directory the PDFs go to.
+    let merged_output_path = cli.output.clone().unwrap_or_else(|| {
+        let pages_dir = &pages_dirs[0];
+        if pipeline::is_zip(pages_dir) {
+            pages_dir.with_file_name("merged.pdf")
+        } else {
+            pages_dir.join("merged.pdf")
+        }
+    });
+
+    let mut svg_entries = match &cli.manifest {
+        Some(manifest_path) => manifest::read_manifest(manifest_path)?,
+        // Each directory is sorted on its own, keeping the order they were
+        // given in.
+        None => {
+            let mut svg_entries = Vec::new();
+            for pages_dir in &pages_dirs {
+                svg_entries.extend(pipeline::find_pages(
+                    pages_dir,
+                    &options,
+                    Some(&merged_output_path),
+                )?);
+            }
+            svg_entries
+        }
+    };
 
     if svg_entries.is_empty() {
-        error!("No pages found.");
-        process::exit(1);
+        return Err(anyhow!("No pages found."));
+    }
+    if let Some(pages) = &cli.pages {
+        svg_entries = pages.select(svg_entries)?;
     }
 
-    let rendered_pages: Vec<(PathBuf, Vec<u8>)> = svg_entries
-        .par_iter()
-        .filter_map(|entry| {
-            let svg_path = entry.path();
-            match read_to_string(svg_path) {
-                Ok(svg_content) => match render_svg_to_pdf(&svg_content) {
-                    Ok(pdf_data) => {
-                        info!("Rendering file: {:?}", &svg_path);
-                        Some((svg_path.to_path_buf(), pdf_data))
-                    }
-                    Err(e) => {
-                        error!("Error reading SVG file {:?}: {:?}", svg_path, e);
-                        process::exit(1)
+    if cli.dry_run {
+        match cli.split_by {
+            Some(SplitBy::Dir) => {
+                let chapters =
+                    pipeline::split_by_dir(svg_entries, &pages_dirs, |path| path.as_path())?;
+                for chapter in chapters {
+                    for svg_path in &chapter.pages {
+                        println!("{}", svg_path.display());
                     }
-                },
-                Err(e) => {
-                    error!("Error reading SVG file {:?}: {:?}", svg_path, e);
-                    process::exit(1)
+                    let pdf_path = merged_output_path.join(format!("{}.pdf", chapter.name));
+                    println!("-> {}", pdf_path.display());
                 }
             }
-        })
-        .collect();
-
-    // Sort the output files by their path
-    let mut output_files = rendered_pages;
-    output_files.sort_by_key(|(path, _)| path.clone());
-
-    info!("Merging all files into a single report");
-    let merged_output_path = PathBuf::from(&svg_dir).join("merged.pdf");
-    let mut merged_pdf = merge_pdfs(
-        output_files
-            .iter()
-            .map(|(_, data)| data.as_slice())
-            .collect(),
-    )?;
-
-    match merged_pdf.save(merged_output_path.clone()) {
-        Ok(_) => {
-            info!("Document converted successfuly.");
-        }
-        Err(e) => {
-            error!("Merging PDF error: {}", e);
-            process::exit(1);
+            None => {
+                for svg_path in &svg_entries {
+                    println!("{}", svg_path.display());
+                }
+                println!("-> {}", merged_output_path.display());
+            }
         }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let progress = cli.progress.then(|| progress::start(svg_entries.len()));
+    let summary = pipeline::run(
+        &svg_entries,
+        &pages_dirs,
+        &merged_output_path,
+        &options,
+        &|| {
+            if let Some(bar) = progress {
+                bar.inc(1);
+            }
+        },
+    );
+    if let Some(bar) = progress {
+        bar.finish_and_clear();
+    }
+    let summary = summary?;
+    let nothing_rendered = summary.pages.is_empty();
+    if nothing_rendered {
+        error!("No page was rendered before the deadline.");
+    } else {
+        info!("Document converted successfuly.");
+    }
+
+    if let Some(report_path) = &cli.report_json {
+        let report = Report {
+            found: svg_entries.len(),
+            rendered: summary.pages.len(),
+            skipped: summary.skipped,
+            total_ms: report::milliseconds(start.elapsed()),
+            output: (!nothing_rendered).then_some(merged_output_path),
+            page_count: summary.page_count,
+            files: report::file_reports(&summary.pages),
+        };
+        report::write_report(report_path, &report)?;
+    }
+    if nothing_rendered {
+        return Ok(ExitCode::from(DEADLINE_EXIT_CODE));
     }
 
     // for (path, _) in output_files {
@@ -318,5 +1134,12 @@ fn main() -> Result<()> {
     //     remove_file(path)?;
     // }
 
-    Ok(())
+    if summary.deadline_exceeded {
+        return Ok(ExitCode::from(DEADLINE_EXIT_CODE));
+    }
+    if summary.pages.len() < svg_entries.len() {
+        return Ok(ExitCode::from(PARTIAL_EXIT_CODE));
+    }
+
+    Ok(ExitCode::SUCCESS)
 }
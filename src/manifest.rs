@@ -0,0 +1,49 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Reads the SVG files listed in the manifest at `path`, in the order given.
+///
+/// A `.json` manifest holds an array of paths, any other file one path per
+/// line, skipping blank lines and lines starting with `#`. Relative paths are
+/// resolved against the directory containing the manifest, and every listed
+/// file has to exist.
+pub fn read_manifest(path: &Path) -> Result<Vec<PathBuf>> {
+    let content =
+        read_to_string(path).with_context(|| format!("Cannot read manifest {:?}", path))?;
+
+    let entries: Vec<PathBuf> = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&content).with_context(|| format!("Invalid manifest {:?}", path))?
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(PathBuf::from)
+            .collect()
+    };
+
+    let manifest_dir = path.parent().unwrap_or(Path::new(""));
+    let svg_paths: Vec<PathBuf> = entries
+        .iter()
+        .map(|entry| manifest_dir.join(entry))
+        .collect();
+
+    let missing: Vec<&PathBuf> = svg_paths
+        .iter()
+        .filter(|svg_path| !svg_path.is_file())
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Files listed in manifest {:?} not found: {:?}",
+            path,
+            missing
+        ));
+    }
+
+    Ok(svg_paths)
+}
@@ -0,0 +1,114 @@
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use lopdf::{Dictionary, Document, Object, StringFormat};
+
+/// Entries of the document information dictionary shown by viewers and
+/// document management systems.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    /// Application that created the original content, e.g. `typst`.
+    pub creator: Option<String>,
+}
+
+/// Replaces the document information dictionary of `doc` with `metadata`,
+/// setting `CreationDate` and `ModDate` to `time` and `Producer` to this
+/// crate.
+pub fn set_metadata(doc: &mut Document, metadata: &Metadata, time: SystemTime) {
+    let mut info = Dictionary::new();
+
+    for (key, value) in [
+        ("Title", &metadata.title),
+        ("Author", &metadata.author),
+        ("Subject", &metadata.subject),
+        ("Keywords", &metadata.keywords),
+        ("Creator", &metadata.creator),
+    ] {
+        if let Some(value) = value {
+            info.set(key, text_string(value));
+        }
+    }
+
+    info.set(
+        "Producer",
+        text_string(concat!("pdf-postprocess ", env!("CARGO_PKG_VERSION"))),
+    );
+
+    let date = Object::string_literal(pdf_date(time));
+    info.set("CreationDate", date.clone());
+    info.set("ModDate", date);
+
+    let info_id = doc.add_object(info);
+    doc.trailer.set("Info", info_id);
+}
+
+/// Time to date a reproducible document with: the `SOURCE_DATE_EPOCH`
+/// environment variable, in seconds since the Unix epoch, as set by
+/// reproducible build tools, or the Unix epoch itself.
+pub fn reproducible_time() -> Result<SystemTime> {
+    let epoch = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch,
+        Err(_) => return Ok(UNIX_EPOCH),
+    };
+    let seconds: u64 = epoch.trim().parse().map_err(|_| {
+        anyhow!(
+            "SOURCE_DATE_EPOCH must be a number of seconds, not {:?}.",
+            epoch
+        )
+    })?;
+
+    Ok(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Encodes `text` as a PDF text string: ASCII as is, anything else as
+/// UTF-16BE with a byte order mark.
+pub(crate) fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+
+    let mut bytes = vec![0xFE, 0xFF];
+    bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Formats `time` as a PDF date in UTC, e.g. `D:20240131235959Z`.
+fn pdf_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since the epoch, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60
+    )
+}
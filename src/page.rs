@@ -0,0 +1,209 @@
+use anyhow::{anyhow, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+/// Page attributes that may be inherited from an ancestor `Pages` node.
+pub const INHERITABLE_PAGE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Guards against cyclic `Parent` chains in malformed documents.
+const MAX_TREE_DEPTH: usize = 1 << 16;
+
+/// Looks up `key` on a page, walking up the page tree if the page doesn't set
+/// it directly.
+pub fn inherited_page_attribute(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+
+    for _ in 0..MAX_TREE_DEPTH {
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+
+    None
+}
+
+/// Copies attributes inherited from ancestor `Pages` nodes onto the pages
+/// themselves so they survive re-parenting.
+pub fn flatten_page_attributes(doc: &mut Document, page_ids: &[ObjectId]) {
+    for page_id in page_ids {
+        for key in INHERITABLE_PAGE_KEYS {
+            if let Some(value) = inherited_page_attribute(doc, *page_id, key) {
+                if let Ok(page) = doc.get_dictionary_mut(*page_id) {
+                    page.set(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// Reads the page box named `key` (e.g. `MediaBox`), honoring inheritance,
+/// as `[left, bottom, right, top]`.
+pub fn page_box(doc: &Document, page_id: ObjectId, key: &[u8]) -> Result<Option<[f32; 4]>> {
+    let page_box = match inherited_page_attribute(doc, page_id, key) {
+        Some(page_box) => page_box,
+        None => return Ok(None),
+    };
+
+    let values = doc
+        .dereference(&page_box)?
+        .1
+        .as_array()?
+        .iter()
+        .map(|value| {
+            value
+                .as_float()
+                .or_else(|_| value.as_i64().map(|v| v as f32))
+        })
+        .collect::<lopdf::Result<Vec<f32>>>()?;
+
+    match values[..] {
+        [x1, y1, x2, y2] => Ok(Some([x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)])),
+        _ => Err(anyhow!(
+            "Page {:?} has a malformed {}.",
+            page_id,
+            String::from_utf8_lossy(key)
+        )),
+    }
+}
+
+/// The page's MediaBox, which every valid page has.
+pub fn media_box(doc: &Document, page_id: ObjectId) -> Result<[f32; 4]> {
+    page_box(doc, page_id, b"MediaBox")?
+        .ok_or_else(|| anyhow!("Page {:?} has no MediaBox.", page_id))
+}
+
+/// Surrounds the existing content streams of a page with `prefix` and
+/// `suffix`, leaving the existing streams themselves untouched.
+pub fn wrap_page_contents(
+    doc: &mut Document,
+    page_id: ObjectId,
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+) -> Result<()> {
+    let mut contents: Vec<Object> = doc
+        .get_page_contents(page_id)
+        .into_iter()
+        .map(Object::Reference)
+        .collect();
+
+    let mut prefix = Stream::new(Dictionary::new(), prefix);
+    let _ = prefix.compress();
+    let mut suffix = Stream::new(Dictionary::new(), suffix);
+    let _ = suffix.compress();
+
+    contents.insert(0, Object::Reference(doc.add_object(prefix)));
+    contents.push(Object::Reference(doc.add_object(suffix)));
+
+    doc.get_dictionary_mut(page_id)?.set("Contents", contents);
+
+    Ok(())
+}
+
+/// Gives the page a resource dictionary of its own that also holds
+/// `resources`, each a resource category (e.g. `Font`), the name to add to it
+/// and the object it refers to.
+pub fn add_page_resources(
+    doc: &mut Document,
+    page_id: ObjectId,
+    resources: &[(&[u8], &str, ObjectId)],
+) -> Result<()> {
+    let mut page_resources = match inherited_page_attribute(doc, page_id, b"Resources") {
+        Some(page_resources) => doc.dereference(&page_resources)?.1.as_dict()?.clone(),
+        None => Dictionary::new(),
+    };
+
+    for (category, name, id) in resources {
+        let mut entries = match page_resources.get(category) {
+            Ok(entries) => doc.dereference(entries)?.1.as_dict()?.clone(),
+            Err(_) => Dictionary::new(),
+        };
+        entries.set(*name, Object::Reference(*id));
+        page_resources.set(*category, entries);
+    }
+
+    doc.get_dictionary_mut(page_id)?
+        .set("Resources", page_resources);
+
+    Ok(())
+}
+
+/// Rebuilds the page tree of `doc` as a balanced tree in which no node has
+/// more than `fanout` kids, keeping the page order.
+///
+/// All pages end up at the same depth below the root `Pages` node, which
+/// keeps its inheritable attributes. Intermediate nodes of the previous tree
+/// are removed after copying their inheritable attributes onto the pages.
+pub fn balance_page_tree(doc: &mut Document, fanout: usize) -> Result<()> {
+    if fanout < 2 {
+        return Err(anyhow!("A page tree node needs room for at least 2 kids."));
+    }
+
+    let root_pages_id = doc
+        .catalog()?
+        .get(b"Pages")
+        .and_then(Object::as_reference)?;
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+
+    for page_id in &page_ids {
+        for key in INHERITABLE_PAGE_KEYS {
+            if let Some(value) = inherited_page_attribute(doc, *page_id, key) {
+                let root_value = doc
+                    .get_dictionary(root_pages_id)
+                    .and_then(|root| root.get(key))
+                    .ok();
+                if root_value != Some(&value) {
+                    doc.get_dictionary_mut(*page_id)?.set(key, value);
+                }
+            }
+        }
+    }
+
+    doc.objects.retain(|id, object| {
+        *id == root_pages_id || object.type_name().map_or(true, |name| name != "Pages")
+    });
+
+    // Each level holds (node, number of pages below it), built bottom-up
+    // until the remaining nodes fit into the root.
+    let mut level: Vec<(ObjectId, usize)> = page_ids.iter().map(|id| (*id, 1)).collect();
+
+    while level.len() > fanout {
+        let mut parents = Vec::with_capacity(level.len().div_ceil(fanout));
+
+        for kids in level.chunks(fanout) {
+            let count: usize = kids.iter().map(|(_, count)| count).sum();
+            let node_id = doc.add_object(dictionary! {
+                "Type" => "Pages",
+                "Parent" => root_pages_id,
+                "Count" => count as u32,
+                "Kids" => kids.iter().map(|(id, _)| Object::Reference(*id)).collect::<Vec<_>>(),
+            });
+
+            for (kid_id, _) in kids {
+                doc.get_dictionary_mut(*kid_id)?.set("Parent", node_id);
+            }
+
+            parents.push((node_id, count));
+        }
+
+        level = parents;
+    }
+
+    for (kid_id, _) in &level {
+        doc.get_dictionary_mut(*kid_id)?
+            .set("Parent", root_pages_id);
+    }
+
+    let root_pages = doc.get_dictionary_mut(root_pages_id)?;
+    root_pages.set("Count", page_ids.len() as u32);
+    root_pages.set(
+        "Kids",
+        level
+            .into_iter()
+            .map(|(id, _)| Object::Reference(id))
+            .collect::<Vec<_>>(),
+    );
+
+    Ok(())
+}
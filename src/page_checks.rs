@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use md5::{Digest, Md5};
+
+/// Operators that put something on the page: painting paths, showing text,
+/// shadings, inline images and XObjects other than forms.
+const PAINTING_OPERATORS: [&str; 16] = [
+    "S", "s", "f", "F", "f*", "B", "B*", "b", "b*", "sh", "BI", "Tj", "TJ", "'", "\"", "Do",
+];
+
+/// How deep form XObjects drawing other forms are followed before a page is
+/// assumed to draw something.
+const MAX_FORM_DEPTH: usize = 16;
+
+/// Whether the page at `page_id` draws nothing at all: its content, and that
+/// of the form XObjects it draws, only sets up graphics state. Drawing that
+/// ends up invisible, e.g. white on white or clipped away, still counts.
+///
+/// Content that can't be parsed counts as drawing something.
+pub fn is_blank_page(doc: &Document, page_id: ObjectId) -> Result<bool> {
+    let content = doc.get_page_content(page_id)?;
+    let resources = page_resources(doc, doc.get_dictionary(page_id)?);
+
+    Ok(!draws_anything(doc, &content, resources, 0))
+}
+
+fn draws_anything(
+    doc: &Document,
+    content: &[u8],
+    resources: Option<&Dictionary>,
+    depth: usize,
+) -> bool {
+    let content = match Content::decode(content) {
+        Ok(content) => content,
+        Err(_) => return true,
+    };
+
+    for operation in &content.operations {
+        let operator = operation.operator.as_str();
+        if !PAINTING_OPERATORS.contains(&operator) {
+            continue;
+        }
+        if operator != "Do" || depth >= MAX_FORM_DEPTH {
+            return true;
+        }
+
+        let form = operation
+            .operands
+            .first()
+            .and_then(|name| name.as_name().ok())
+            .zip(resources)
+            .and_then(|(name, resources)| {
+                let xobjects = resources.get(b"XObject").ok()?;
+                let xobjects = doc.dereference(xobjects).ok()?.1.as_dict().ok()?;
+                doc.dereference(xobjects.get(name).ok()?)
+                    .ok()?
+                    .1
+                    .as_stream()
+                    .ok()
+            })
+            .filter(|stream| {
+                stream
+                    .dict
+                    .get(b"Subtype")
+                    .and_then(Object::as_name)
+                    .is_ok_and(|subtype| subtype == b"Form")
+            });
+        let form = match form {
+            Some(form) => form,
+            // Images, or XObjects that can't be found.
+            None => return true,
+        };
+        let form_content = form
+            .decompressed_content()
+            .unwrap_or_else(|_| form.content.clone());
+        // Forms without resources of their own use the page's.
+        let form_resources = page_resources(doc, &form.dict).or(resources);
+        if draws_anything(doc, &form_content, form_resources, depth + 1) {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn page_resources<'a>(doc: &'a Document, dict: &'a Dictionary) -> Option<&'a Dictionary> {
+    let resources = dict.get(b"Resources").ok()?;
+
+    doc.dereference(resources).ok()?.1.as_dict().ok()
+}
+
+/// Hash of everything the page at `page_id` consists of: its decoded content
+/// and, by value rather than by object number, its resources and other
+/// entries, so pages rendered from identical SVGs hash the same. Other pages
+/// it refers to, e.g. from links, only count as a page.
+pub fn page_fingerprint(doc: &Document, page_id: ObjectId) -> Result<[u8; 16]> {
+    let mut hasher = Md5::new();
+    hasher.update(doc.get_page_content(page_id)?);
+
+    let mut visited = BTreeMap::new();
+    visited.insert(page_id, 0);
+    for (key, value) in doc.get_dictionary(page_id)?.iter() {
+        if key == b"Parent" || key == b"Contents" {
+            continue;
+        }
+        hasher.update(key);
+        hash_object(doc, value, &mut hasher, &mut visited);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Feeds `object` to `hasher`, following references. An object reached
+/// again is hashed as the position it was first reached at, which keeps
+/// cycles finite and tells apart structures that only differ in sharing.
+fn hash_object(
+    doc: &Document,
+    object: &Object,
+    hasher: &mut Md5,
+    visited: &mut BTreeMap<ObjectId, usize>,
+) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(position) = visited.get(id) {
+                hasher.update(format!("R{}", position));
+                return;
+            }
+            visited.insert(*id, visited.len());
+            match doc.objects.get(id) {
+                Some(Object::Dictionary(dict))
+                    if dict.get(b"Type").and_then(Object::as_name).ok() == Some(b"Page") =>
+                {
+                    hasher.update(b"Page");
+                }
+                Some(object) => hash_object(doc, object, hasher, visited),
+                None => hasher.update(b"null"),
+            }
+        }
+        Object::Array(items) => {
+            hasher.update(b"[");
+            for item in items {
+                hash_object(doc, item, hasher, visited);
+            }
+            hasher.update(b"]");
+        }
+        Object::Dictionary(dict) => hash_dictionary(doc, dict, hasher, visited),
+        Object::Stream(stream) => {
+            hash_dictionary(doc, &stream.dict, hasher, visited);
+            hasher.update(format!("stream{}", stream.content.len()));
+            hasher.update(&stream.content);
+        }
+        object => hasher.update(format!("{:?} ", object)),
+    }
+}
+
+fn hash_dictionary(
+    doc: &Document,
+    dict: &Dictionary,
+    hasher: &mut Md5,
+    visited: &mut BTreeMap<ObjectId, usize>,
+) {
+    hasher.update(b"<<");
+    for (key, value) in dict.iter() {
+        if key == b"Parent" {
+            continue;
+        }
+        hasher.update(format!("/{}", String::from_utf8_lossy(key)));
+        hash_object(doc, value, hasher, visited);
+    }
+    hasher.update(b">>");
+}
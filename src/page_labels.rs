@@ -0,0 +1,260 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use lopdf::{dictionary, Document, Object};
+
+use crate::page_ranges::PageRanges;
+
+/// How the pages of a [`PageLabels`] range are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelStyle {
+    /// 1, 2, 3
+    Decimal,
+    /// i, ii, iii
+    Roman,
+    /// I, II, III
+    UpperRoman,
+    /// a, b, ..., z, aa, bb
+    Letters,
+    /// A, B, ..., Z, AA, BB
+    UpperLetters,
+}
+
+impl LabelStyle {
+    /// Value of the `/S` entry of a page label dictionary.
+    fn name(self) -> &'static str {
+        match self {
+            LabelStyle::Decimal => "D",
+            LabelStyle::Roman => "r",
+            LabelStyle::UpperRoman => "R",
+            LabelStyle::Letters => "a",
+            LabelStyle::UpperLetters => "A",
+        }
+    }
+}
+
+impl FromStr for LabelStyle {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<LabelStyle, String> {
+        match value.trim() {
+            "decimal" => Ok(LabelStyle::Decimal),
+            "roman" => Ok(LabelStyle::Roman),
+            "upper-roman" => Ok(LabelStyle::UpperRoman),
+            "letters" => Ok(LabelStyle::Letters),
+            "upper-letters" => Ok(LabelStyle::UpperLetters),
+            _ => Err(format!(
+                "invalid page label style {:?}, expected decimal, roman, upper-roman, letters or upper-letters",
+                value
+            )),
+        }
+    }
+}
+
+/// The labels viewers show for pages in their page navigator instead of the
+/// page's position, e.g. `roman:1-3,decimal:4-` for front matter numbered
+/// i to iii followed by the body numbered from 1.
+///
+/// Every range is numbered from 1. Pages no range covers are labeled with
+/// their position, as if there were no labels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageLabels {
+    ranges: Vec<(LabelStyle, PageRanges)>,
+}
+
+impl FromStr for PageLabels {
+    type Err = String;
+
+    /// Parses comma-separated `STYLE:PAGES` ranges, with `PAGES` a single
+    /// page or range of them as accepted by [`PageRanges`], e.g. `4-` or
+    /// `1-3`.
+    fn from_str(value: &str) -> Result<PageLabels, String> {
+        let ranges = value
+            .split(',')
+            .map(|range| {
+                let (style, pages) = range.split_once(':').ok_or_else(|| {
+                    format!(
+                        "invalid page labels {:?}, expected e.g. roman:1-3,decimal:4-",
+                        value
+                    )
+                })?;
+                Ok((style.parse()?, pages.parse()?))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(PageLabels { ranges })
+    }
+}
+
+/// Replaces the `/PageLabels` number tree of `doc` with `labels`, pages
+/// counted from 1 in the final document. The tree is a single node listing
+/// where every range starts, which viewers read no matter how many pages
+/// there are.
+///
+/// Fails if ranges overlap or select a page past the last one.
+pub fn set_page_labels(doc: &mut Document, labels: &PageLabels) -> Result<()> {
+    let page_count = doc.get_pages().len();
+
+    let mut ranges = Vec::with_capacity(labels.ranges.len());
+    for (style, pages) in &labels.ranges {
+        let indices = pages.indices(page_count)?;
+        if let (Some(first), Some(last)) = (indices.first(), indices.last()) {
+            ranges.push((*first, *last, *style));
+        }
+    }
+    ranges.sort_by_key(|(first, _, _)| *first);
+    for pair in ranges.windows(2) {
+        if pair[1].0 <= pair[0].1 {
+            return Err(anyhow!(
+                "Page labels overlap at page {}, every page can only be in one range.",
+                pair[1].0 + 1
+            ));
+        }
+    }
+
+    // Every page needs a label once there are some, the first one included,
+    // so gaps are labeled with the page position.
+    let position_label =
+        |index: usize| -> Object { dictionary! { "S" => "D", "St" => index as i64 + 1 }.into() };
+    let mut nums = Vec::new();
+    let mut next_page = 0;
+    for (first, last, style) in ranges {
+        if first > next_page {
+            nums.extend([Object::Integer(next_page as i64), position_label(next_page)]);
+        }
+        nums.extend([
+            Object::Integer(first as i64),
+            dictionary! { "S" => style.name() }.into(),
+        ]);
+        next_page = last + 1;
+    }
+    if next_page < page_count {
+        nums.extend([Object::Integer(next_page as i64), position_label(next_page)]);
+    }
+
+    let tree_id = doc.add_object(dictionary! { "Nums" => nums });
+    doc.catalog_mut()?
+        .set("PageLabels", Object::Reference(tree_id));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(page_count: usize) -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..page_count)
+            .map(|_| {
+                doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id })
+                    .into()
+            })
+            .collect();
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => page_count as i64,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        doc
+    }
+
+    /// The `/Nums` of the page label tree of `doc` as the index where every
+    /// range starts, its style and the number it starts at.
+    fn nums(doc: &Document) -> Vec<(i64, String, Option<i64>)> {
+        let tree_id = doc
+            .catalog()
+            .unwrap()
+            .get(b"PageLabels")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let nums = doc
+            .get_dictionary(tree_id)
+            .unwrap()
+            .get(b"Nums")
+            .unwrap()
+            .as_array()
+            .unwrap();
+
+        nums.chunks(2)
+            .map(|pair| {
+                let label = pair[1].as_dict().unwrap();
+                (
+                    pair[0].as_i64().unwrap(),
+                    String::from_utf8(label.get(b"S").unwrap().as_name().unwrap().to_vec())
+                        .unwrap(),
+                    label.get(b"St").and_then(Object::as_i64).ok(),
+                )
+            })
+            .collect()
+    }
+
+    fn labels(value: &str) -> PageLabels {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_styles_of_page_ranges() {
+        assert_eq!(
+            labels("roman:1-3, decimal:4-").ranges,
+            [
+                (LabelStyle::Roman, "1-3".parse().unwrap()),
+                (LabelStyle::Decimal, "4-".parse().unwrap()),
+            ]
+        );
+        for value in ["roman", "bold:1-3", "roman:0", "roman:1-3,", "decimal:3-1"] {
+            assert!(
+                value.parse::<PageLabels>().is_err(),
+                "{:?} was accepted",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn labels_gaps_with_the_page_position() {
+        let mut doc = document(6);
+        set_page_labels(&mut doc, &labels("decimal:4-5,roman:2-3")).unwrap();
+
+        assert_eq!(
+            nums(&doc),
+            [
+                (0, "D".to_string(), Some(1)),
+                (1, "r".to_string(), None),
+                (3, "D".to_string(), None),
+                (5, "D".to_string(), Some(6)),
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_every_page_of_ranges_that_cover_them_all() {
+        let mut doc = document(5);
+        set_page_labels(&mut doc, &labels("upper-roman:1-2,letters:3-")).unwrap();
+
+        assert_eq!(
+            nums(&doc),
+            [(0, "R".to_string(), None), (2, "a".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_ranges() {
+        let mut doc = document(6);
+        let error = set_page_labels(&mut doc, &labels("roman:1-3,decimal:3-")).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Page labels overlap at page 3, every page can only be in one range."
+        );
+        assert!(set_page_labels(&mut doc, &labels("decimal:7")).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use lopdf::{dictionary, Document};
+use serde::Deserialize;
+
+use crate::page::{add_page_resources, media_box, page_box, wrap_page_contents};
+
+/// Footer printed when no format is given.
+pub const DEFAULT_FORMAT: &str = "Page {page} of {total}";
+
+/// Advance widths of the printable ASCII characters, from space to `~`, in
+/// Helvetica, in thousandths of the font size.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// Punctuation WinAnsiEncoding has beyond Latin-1, with its code and
+/// Helvetica width.
+const WIN_ANSI_EXTRAS: [(char, u8, u16); 11] = [
+    ('€', 0x80, 556),
+    ('‚', 0x82, 222),
+    ('„', 0x84, 333),
+    ('…', 0x85, 1000),
+    ('‘', 0x91, 222),
+    ('’', 0x92, 222),
+    ('“', 0x93, 333),
+    ('”', 0x94, 333),
+    ('•', 0x95, 350),
+    ('–', 0x96, 556),
+    ('—', 0x97, 1000),
+];
+
+/// Width assumed for Latin-1 letters and symbols, which the tables leave out.
+const FALLBACK_WIDTH: u16 = 556;
+
+const FONT_SIZE: f32 = 9.0;
+
+/// Distance of the baseline from the bottom edge, and of right-aligned
+/// numbers from the right edge, in points.
+const MARGIN: f32 = 20.0;
+
+/// Resource name used for the footer font, unlikely to clash with the page's
+/// own.
+const FONT_NAME: &str = "PageNumberFont";
+
+/// Where on the page the footer goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageNumberPosition {
+    /// Centered along the bottom edge
+    #[default]
+    BottomCenter,
+    /// Right-aligned along the bottom edge
+    BottomRight,
+}
+
+/// Prints a footer such as "Page 3 of 12" at the bottom of every page of
+/// `doc`, inside its visible area.
+///
+/// `{page}` in `format` is replaced with the 1-based page number and
+/// `{total}` with the number of pages. The footer uses the standard Helvetica
+/// font, so nothing is embedded, which limits it to Latin-1 text and common
+/// punctuation like dashes and curly quotes; other characters are printed as
+/// `?`.
+pub fn number_pages(doc: &mut Document, format: &str, position: PageNumberPosition) -> Result<()> {
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+
+    let page_ids: Vec<_> = doc.page_iter().collect();
+    let total = page_ids.len();
+
+    for (index, page_id) in page_ids.into_iter().enumerate() {
+        add_page_resources(doc, page_id, &[(b"Font", FONT_NAME, font_id)])?;
+
+        let [left, bottom, right, _] = match page_box(doc, page_id, b"CropBox")? {
+            Some(crop_box) => crop_box,
+            None => media_box(doc, page_id)?,
+        };
+
+        let text = format
+            .replace("{page}", &(index + 1).to_string())
+            .replace("{total}", &total.to_string());
+        let (encoded, width) = encode(&text);
+        let width = width * FONT_SIZE;
+
+        let x = match position {
+            PageNumberPosition::BottomCenter => (left + right - width) / 2.0,
+            PageNumberPosition::BottomRight => right - MARGIN - width,
+        };
+        let y = bottom + MARGIN;
+
+        let mut footer = format!(
+            "Q\nq 0 g BT /{} {} Tf {} {} Td (",
+            FONT_NAME, FONT_SIZE, x, y
+        )
+        .into_bytes();
+        footer.extend_from_slice(&encoded);
+        footer.extend_from_slice(b") Tj ET Q\n");
+
+        wrap_page_contents(doc, page_id, b"q\n".to_vec(), footer)?;
+    }
+
+    Ok(())
+}
+
+/// Encodes `text` as an escaped WinAnsiEncoding string literal body, along
+/// with its width in Helvetica relative to the font size.
+pub(crate) fn encode(text: &str) -> (Vec<u8>, f32) {
+    let mut encoded = Vec::with_capacity(text.len());
+    let mut width: u32 = 0;
+
+    for c in text.chars() {
+        let (byte, char_width) = match c as u32 {
+            code @ 0x20..=0x7E => (code as u8, HELVETICA_WIDTHS[code as usize - 0x20]),
+            // WinAnsiEncoding agrees with Latin-1 for these.
+            code @ 0xA0..=0xFF => (code as u8, FALLBACK_WIDTH),
+            _ => match WIN_ANSI_EXTRAS.iter().find(|(extra, _, _)| *extra == c) {
+                Some((_, byte, char_width)) => (*byte, *char_width),
+                None => (b'?', HELVETICA_WIDTHS[usize::from(b'?' - 0x20)]),
+            },
+        };
+        width += u32::from(char_width);
+
+        if matches!(byte, b'(' | b')' | b'\\') {
+            encoded.push(b'\\');
+        }
+        encoded.push(byte);
+    }
+
+    (encoded, width as f32 / 1000.0)
+}
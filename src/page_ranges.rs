@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+/// A selection of pages by their 1-based position, such as `1-5,8,10-`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRanges {
+    /// Inclusive ranges, open-ended ones running through the last page.
+    ranges: Vec<(usize, Option<usize>)>,
+}
+
+impl PageRanges {
+    /// The zero-based indices of the selected pages out of `count`, in
+    /// ascending order and each only once, however often it is selected.
+    ///
+    /// Fails if a range reaches past the last page.
+    pub fn indices(&self, count: usize) -> Result<Vec<usize>> {
+        let mut indices = BTreeSet::new();
+        for (first, last) in &self.ranges {
+            let last = last.unwrap_or(count);
+            if *first > count || last > count {
+                return Err(anyhow!(
+                    "Page {} was selected, but there are only {} pages.",
+                    last.max(*first),
+                    count
+                ));
+            }
+            indices.extend(first - 1..last);
+        }
+
+        Ok(indices.into_iter().collect())
+    }
+
+    /// Keeps the items at the selected positions, see
+    /// [`PageRanges::indices`].
+    pub fn select<T>(&self, items: Vec<T>) -> Result<Vec<T>> {
+        let indices = self.indices(items.len())?;
+
+        Ok(items
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| indices.binary_search(index).is_ok())
+            .map(|(_, item)| item)
+            .collect())
+    }
+}
+
+impl FromStr for PageRanges {
+    type Err = String;
+
+    /// Parses comma-separated page numbers and ranges of them, e.g.
+    /// `1-5,8,10-`, where `10-` runs through the last page.
+    fn from_str(value: &str) -> Result<PageRanges, String> {
+        let invalid = || {
+            format!(
+                "invalid page ranges {:?}, expected e.g. 1-5,8,10- with pages counted from 1",
+                value
+            )
+        };
+        let page = |number: &str| {
+            number
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|number| *number > 0)
+                .ok_or_else(invalid)
+        };
+
+        let ranges = value
+            .split(',')
+            .map(|range| match range.split_once('-') {
+                Some((first, last)) if last.trim().is_empty() => Ok((page(first)?, None)),
+                Some((first, last)) => {
+                    let (first, last) = (page(first)?, page(last)?);
+                    if first > last {
+                        return Err(invalid());
+                    }
+                    Ok((first, Some(last)))
+                }
+                None => {
+                    let number = page(range)?;
+                    Ok((number, Some(number)))
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(PageRanges { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(ranges: &str, count: usize) -> Result<Vec<usize>> {
+        ranges.parse::<PageRanges>().unwrap().indices(count)
+    }
+
+    #[test]
+    fn selects_pages_and_open_ended_ranges() {
+        assert_eq!(
+            indices("1-5,8,10-", 12).unwrap(),
+            [0, 1, 2, 3, 4, 7, 9, 10, 11]
+        );
+        assert_eq!(indices(" 2 - 3 , 1 ", 5).unwrap(), [0, 1, 2]);
+        assert_eq!(indices("4-", 4).unwrap(), [3]);
+    }
+
+    #[test]
+    fn selects_overlapping_pages_once_in_order() {
+        assert_eq!(indices("3-4,1-3,2", 5).unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for ranges in ["0", "0-2", "5-3", "1,,3", "", "-3", "a", "1-2-3"] {
+            assert!(
+                ranges.parse::<PageRanges>().is_err(),
+                "{:?} was accepted",
+                ranges
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_last_page() {
+        assert_eq!(
+            indices("3-5", 4).unwrap_err().to_string(),
+            "Page 5 was selected, but there are only 4 pages."
+        );
+        assert!(indices("5-", 4).is_err());
+        assert!(indices("5", 4).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use lopdf::{Document, Object, ObjectId};
+use serde::Deserialize;
+
+use crate::links;
+use crate::page::{
+    flatten_page_attributes, media_box, page_box, wrap_page_contents, INHERITABLE_PAGE_KEYS,
+};
+
+/// Named page sizes in points, portrait.
+const NAMED_SIZES: [(&str, f32, f32); 6] = [
+    ("a3", 841.89, 1190.55),
+    ("a4", 595.28, 841.89),
+    ("a5", 419.53, 595.28),
+    ("letter", 612.0, 792.0),
+    ("legal", 612.0, 1008.0),
+    ("tabloid", 792.0, 1224.0),
+];
+
+/// Size every rendered page is fitted onto, in points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PageSize {
+    /// Turns the page so its longer side runs as `orientation` asks.
+    pub fn oriented(self, orientation: Orientation) -> PageSize {
+        let (short, long) = (self.width.min(self.height), self.width.max(self.height));
+        match orientation {
+            Orientation::Portrait => PageSize {
+                width: short,
+                height: long,
+            },
+            Orientation::Landscape => PageSize {
+                width: long,
+                height: short,
+            },
+        }
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = String;
+
+    /// Parses a named size such as `a4` or `letter`, or `WIDTHxHEIGHT` in
+    /// points such as `500x700`.
+    fn from_str(value: &str) -> Result<PageSize, String> {
+        let value = value.trim();
+
+        if let Some((_, width, height)) = NAMED_SIZES
+            .iter()
+            .find(|(name, _, _)| name.eq_ignore_ascii_case(value))
+        {
+            return Ok(PageSize {
+                width: *width,
+                height: *height,
+            });
+        }
+
+        let invalid = || {
+            let names: Vec<&str> = NAMED_SIZES.iter().map(|(name, _, _)| *name).collect();
+            format!(
+                "invalid page size {:?}, expected one of {} or WIDTHxHEIGHT in points",
+                value,
+                names.join(", ")
+            )
+        };
+
+        let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let parse = |side: &str| {
+            side.trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|side| side.is_finite() && *side > 0.0)
+                .ok_or_else(invalid)
+        };
+
+        Ok(PageSize {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+/// Which way round a [`PageSize`] is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    /// Taller than wide
+    Portrait,
+    /// Wider than tall
+    Landscape,
+}
+
+/// Scales the visible area of every page in `pdf` (its CropBox, or else its
+/// MediaBox) uniformly to fit `size` and centers it, making `size` the new
+/// MediaBox.
+///
+/// The aspect ratio is kept, so content whose aspect ratio differs from the
+/// target's is letterboxed: the leftover space along one axis stays blank,
+/// split evenly between both sides. Pages already of exactly `size` are left
+/// alone.
+pub fn fit_to_page_size(pdf: &[u8], size: PageSize) -> Result<Vec<u8>> {
+    let mut doc = Document::load_mem(pdf)?;
+
+    for page_id in doc.page_iter().collect::<Vec<_>>() {
+        fit_page(&mut doc, page_id, size)?;
+    }
+
+    let mut output = Vec::new();
+    doc.save_to(&mut output)?;
+
+    Ok(output)
+}
+
+/// How [`match_first_page_size`] gives pages the size of the first one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeMatch {
+    /// Scale the content uniformly to fit the first page and center it, like `--page-size`
+    #[default]
+    Scale,
+    /// Only set the MediaBox to the first page's, keeping the content where it is
+    Resize,
+}
+
+/// Gives every page of `doc` the size of its first page's MediaBox, for
+/// pages meant to be the same size that differ by rounding, returning how
+/// many pages had to change.
+///
+/// With [`SizeMatch::Scale`], pages are fitted like [`fit_to_page_size`]
+/// does, with [`SizeMatch::Resize`] they get a copy of the first page's
+/// MediaBox. Inherited page attributes are moved onto the pages first, so a
+/// MediaBox or CropBox of the page tree can't apply to them anymore once
+/// they have their own.
+pub fn match_first_page_size(doc: &mut Document, mode: SizeMatch) -> Result<usize> {
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+    let first_box = match page_ids.first() {
+        Some(first_id) => media_box(doc, *first_id)?,
+        None => return Ok(0),
+    };
+
+    flatten_page_attributes(doc, &page_ids);
+    for object in doc.objects.values_mut() {
+        if object.type_name().is_ok_and(|name| name == "Pages") {
+            if let Object::Dictionary(node) = object {
+                for key in INHERITABLE_PAGE_KEYS {
+                    node.remove(key);
+                }
+            }
+        }
+    }
+
+    let [left, bottom, right, top] = first_box;
+    let size = PageSize {
+        width: right - left,
+        height: top - bottom,
+    };
+    let mut changed = 0;
+    for page_id in page_ids.into_iter().skip(1) {
+        if media_box(doc, page_id)? == first_box {
+            continue;
+        }
+        match mode {
+            SizeMatch::Scale => fit_page(doc, page_id, size)?,
+            SizeMatch::Resize => {
+                // A CropBox reaching past the new MediaBox is cut to it.
+                let crop_box = page_box(doc, page_id, b"CropBox")?.map(|crop_box| {
+                    [
+                        crop_box[0].max(left),
+                        crop_box[1].max(bottom),
+                        crop_box[2].min(right),
+                        crop_box[3].min(top),
+                    ]
+                });
+                let page = doc.get_dictionary_mut(page_id)?;
+                page.set("MediaBox", box_object(first_box));
+                if let Some(crop_box) = crop_box {
+                    page.set("CropBox", box_object(crop_box));
+                }
+            }
+        }
+        changed += 1;
+    }
+
+    Ok(changed)
+}
+
+fn box_object(page_box: [f32; 4]) -> Object {
+    Object::Array(page_box.iter().map(|side| Object::Real(*side)).collect())
+}
+
+fn fit_page(doc: &mut Document, page_id: ObjectId, size: PageSize) -> Result<()> {
+    let [left, bottom, right, top] = match page_box(doc, page_id, b"CropBox")? {
+        Some(crop_box) => crop_box,
+        None => media_box(doc, page_id)?,
+    };
+    let (width, height) = (right - left, top - bottom);
+    if left == 0.0 && bottom == 0.0 && width == size.width && height == size.height {
+        return Ok(());
+    }
+
+    let scale = (size.width / width).min(size.height / height);
+    let x = (size.width - width * scale) / 2.0 - left * scale;
+    let y = (size.height - height * scale) / 2.0 - bottom * scale;
+
+    // The content may reach beyond the visible area, keep it off the margins.
+    let clip = format!("{} {} {} {} re W n\n", left, bottom, width, height);
+    let prefix = format!("q {} 0 0 {} {} {} cm\n{}", scale, scale, x, y, clip);
+    wrap_page_contents(doc, page_id, prefix.into_bytes(), b"\nQ".to_vec())?;
+    links::transform_links(doc, page_id, scale, x, y)?;
+
+    let page = doc.get_dictionary_mut(page_id)?;
+    page.set(
+        "MediaBox",
+        vec![
+            Object::Real(0.0),
+            Object::Real(0.0),
+            Object::Real(size.width),
+            Object::Real(size.height),
+        ],
+    );
+    page.remove(b"CropBox");
+
+    Ok(())
+}
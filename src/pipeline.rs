@@ -0,0 +1,1536 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, error, info, warn};
+use lopdf::Document;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+use crate::attachments::{self, Attachment};
+use crate::bleed;
+use crate::cache::RenderCache;
+use crate::color_profile::{self, ColorProfile};
+use crate::deadline;
+use crate::encryption::{self, Encryption};
+use crate::metadata::{self, Metadata};
+use crate::page_checks;
+use crate::page_labels::{self, PageLabels};
+use crate::page_numbers::{self, PageNumberPosition};
+use crate::page_ranges::PageRanges;
+use crate::page_size::{self, SizeMatch};
+use crate::rotation::{self, PageRotation};
+use crate::sort::SortOrder;
+use crate::watermark::{self, Watermark};
+use crate::{
+    compression, continuous, draft, insert, linearize, precision, render_page, toc, user_unit,
+    verify_page_sizes, MergeOptions, OutlineEntry, PdfMerger, RenderOptions, RenderedPage,
+    MAX_SVG_SIZE, MERGE_BATCH_PER_THREAD,
+};
+
+/// Suffix of the expanded SVGs written by `--debug-file`, which are never
+/// picked up as pages.
+pub const DEBUG_SVG_SUFFIX: &str = ".expanded.svg";
+
+/// Everything [`render_dir`] needs to turn a directory of SVGs into a single
+/// PDF, mirroring the command line options.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Order the pages found in the directory are merged in.
+    pub sort: SortOrder,
+    /// Merge the PDF files found along with the SVGs, as is.
+    pub include_pdfs: bool,
+    /// Fail instead of skipping directory entries that can't be read.
+    pub strict: bool,
+    /// Only pick up files whose path relative to the scanned directory
+    /// matches one of these, if there are any, see [`find_pages`].
+    pub include: Vec<Glob>,
+    /// Skip files whose path relative to the scanned directory matches one
+    /// of these, even if they match [`Options::include`].
+    pub exclude: Vec<Glob>,
+    /// Only merge the pages at these positions in [`Options::sort`] order.
+    pub pages: Option<PageRanges>,
+    /// Reuse pages rendered by earlier runs, see [`RenderCache`].
+    pub cache_dir: Option<PathBuf>,
+    /// Log and leave out pages that fail to render in [`run`] instead of
+    /// failing.
+    pub skip_errors: bool,
+    /// Start no new renders in [`run`] once this long has passed, and merge
+    /// the pages finished by then.
+    pub deadline: Option<Duration>,
+    /// Fail pages that take longer than this to render, see
+    /// [`deadline::run_with_timeout`].
+    pub timeout: Option<Duration>,
+    /// Also write the PDF rendered from every page to this directory.
+    pub keep_intermediate: Option<PathBuf>,
+    /// Write one PDF per chapter in [`run`] instead of merging all pages.
+    pub split_by: Option<SplitBy>,
+    pub render: RenderOptions,
+    /// Build the outline from the headings marked up in the SVGs, falling
+    /// back to one bookmark per file if there are none.
+    pub heading_bookmarks: bool,
+    /// How the rendered pages are merged. The outline is always replaced,
+    /// see [`Options::heading_bookmarks`].
+    pub merge: MergeOptions,
+    /// Check that every page kept its size in the merged document.
+    pub preserve_sizes: bool,
+    /// Give every merged page the size of the first one, see
+    /// [`page_size::match_first_page_size`].
+    pub size_from_first: Option<SizeMatch>,
+    /// Warn about merged pages identical to an earlier one, see
+    /// [`page_checks::page_fingerprint`].
+    pub warn_duplicates: bool,
+    /// Warn about merged pages that draw nothing, see
+    /// [`page_checks::is_blank_page`].
+    pub warn_blank: bool,
+    /// Existing PDF to insert the pages into.
+    pub base: Option<PathBuf>,
+    /// 1-based page of [`Options::base`] the pages are inserted before,
+    /// after its last page if not set.
+    pub insert_at: Option<u32>,
+    /// Prepend a table of contents listing the bookmarks, see
+    /// [`toc::insert_toc`].
+    pub toc: bool,
+    /// Points the trim size extends beyond the visible area of every page,
+    /// see [`bleed::add_bleed`].
+    pub margin: f32,
+    /// Points the MediaBox extends beyond the trim size.
+    pub bleed: f32,
+    /// Stamp every page with a diagonal "DRAFT" watermark.
+    pub draft: bool,
+    pub watermark: Option<Watermark>,
+    /// Print a footer with the page number on every page.
+    pub page_numbers: bool,
+    pub page_number_position: PageNumberPosition,
+    /// Footer text, see [`page_numbers::number_pages`].
+    pub page_number_format: String,
+    /// Stack all pages into a single tall page.
+    pub continuous: bool,
+    /// Turn pages of the final document for viewing, see
+    /// [`rotation::rotate_pages`].
+    pub rotate: Vec<PageRotation>,
+    /// Labels the page navigator of viewers shows, see
+    /// [`page_labels::set_page_labels`].
+    pub page_labels: Option<PageLabels>,
+    /// Start the pages of every directory on an odd page, inserting a blank
+    /// page before it where needed.
+    pub duplex_align: bool,
+    /// Attach every source SVG to the merged document.
+    pub embed_sources: bool,
+    /// Round the numbers in content streams to this many decimals.
+    pub precision: Option<u8>,
+    /// Recompress every stream at this zlib level, 0 to 9.
+    pub compression_level: Option<u32>,
+    /// Declare this ICC profile the output intent, see
+    /// [`color_profile::set_output_intent`].
+    pub color_profile: Option<ColorProfile>,
+    pub metadata: Metadata,
+    /// Make the output byte for byte the same for the same inputs, see
+    /// [`metadata::reproducible_time`].
+    pub deterministic: bool,
+    /// Protect the merged document with a password, as the very last step.
+    pub encryption: Option<Encryption>,
+    /// Write the document linearized for fast web view, see
+    /// [`linearize::save_linearized`].
+    pub linearize: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            sort: SortOrder::default(),
+            include_pdfs: false,
+            strict: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            pages: None,
+            cache_dir: None,
+            skip_errors: false,
+            deadline: None,
+            timeout: None,
+            keep_intermediate: None,
+            split_by: None,
+            render: RenderOptions::default(),
+            heading_bookmarks: false,
+            merge: MergeOptions::default(),
+            preserve_sizes: false,
+            size_from_first: None,
+            warn_duplicates: false,
+            warn_blank: false,
+            base: None,
+            insert_at: None,
+            toc: false,
+            margin: 0.0,
+            bleed: 0.0,
+            draft: false,
+            watermark: None,
+            page_numbers: false,
+            page_number_position: PageNumberPosition::default(),
+            page_number_format: page_numbers::DEFAULT_FORMAT.to_string(),
+            continuous: false,
+            rotate: Vec::new(),
+            page_labels: None,
+            duplex_align: false,
+            embed_sources: false,
+            precision: None,
+            compression_level: None,
+            color_profile: None,
+            metadata: Metadata::default(),
+            deterministic: false,
+            encryption: None,
+            linearize: false,
+        }
+    }
+}
+
+/// Renders every page found in `dir`, a directory or ZIP archive, in
+/// parallel and merges them, returning the merged PDF without writing any
+/// file.
+///
+/// Fails on the first page that can't be rendered, or if `dir` holds no
+/// pages at all.
+pub fn render_dir(dir: &Path, options: &Options) -> Result<Vec<u8>> {
+    let mut document = merge_dir(dir, options)?;
+    let mut pdf = Vec::new();
+    save_document(&mut document, options, &mut pdf)?;
+
+    Ok(pdf)
+}
+
+/// Like [`render_dir`], but returns the merged [`Document`] before it is
+/// saved, for changes of the caller's own. [`save_document`] then writes it
+/// as `render_dir` would.
+pub fn merge_dir(dir: &Path, options: &Options) -> Result<Document> {
+    let mut paths = find_pages(dir, options, None)?;
+    if paths.is_empty() {
+        return Err(anyhow!("No pages found in {:?}.", dir));
+    }
+    if let Some(pages) = &options.pages {
+        paths = pages.select(paths)?;
+    }
+
+    let cache = options
+        .cache_dir
+        .as_deref()
+        .map(RenderCache::new)
+        .transpose()?;
+    // Pages are rendered in batches and merged as they come, so only one
+    // batch of PDFs is held in memory at a time.
+    let mut merge = RenderedMerge::new(options);
+    let mut pages = Vec::with_capacity(paths.len());
+    for paths in paths.chunks(rayon::current_num_threads() * MERGE_BATCH_PER_THREAD) {
+        let mut rendered = paths
+            .par_iter()
+            .map(|path| render_file_cached(path, &options.render, cache.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        merge.add(&mut rendered)?;
+        pages.append(&mut rendered);
+    }
+
+    finish_merge(merge, &pages, &[dir.to_path_buf()], options)
+}
+
+/// Path that [`run`] takes as stdout instead of a file.
+pub const STDOUT_PATH: &str = "-";
+
+/// A page that isn't part of the output, and why.
+#[derive(Debug, Serialize)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// What [`run`] rendered and wrote.
+#[derive(Debug)]
+pub struct RunSummary {
+    /// The merged pages, in order, with their PDFs freed once merged but
+    /// their timings kept. Empty if [`Options::deadline`] passed before any
+    /// page was rendered, in which case nothing was written.
+    pub pages: Vec<(PathBuf, RenderedPage)>,
+    /// Pages left out by [`Options::skip_errors`] or [`Options::deadline`].
+    pub skipped: Vec<SkippedFile>,
+    /// Number of pages written, over all PDFs with [`Options::split_by`].
+    pub page_count: usize,
+    /// Whether [`Options::deadline`] passed before every page was rendered.
+    pub deadline_exceeded: bool,
+}
+
+/// Renders the pages at `paths`, found in `pages_dirs`, and writes them
+/// merged to `output`, or to stdout if it is [`STDOUT_PATH`]. With
+/// [`Options::split_by`], `output` is the directory the PDF of every
+/// chapter is written to.
+///
+/// Pages are rendered in parallel batches, which are merged as they come
+/// unless they are split, as that needs all of them first. A page that
+/// fails to render fails the run, or with [`Options::skip_errors`] is
+/// logged and left out; [`Options::timeout`] fails pages that take too
+/// long. Once [`Options::deadline`] has passed, no new page is started and
+/// the ones finished by then are written. `on_rendered` is called after
+/// every page, e.g. to advance a progress bar.
+pub fn run(
+    paths: &[PathBuf],
+    pages_dirs: &[PathBuf],
+    output: &Path,
+    options: &Options,
+    on_rendered: &(dyn Fn() + Sync),
+) -> Result<RunSummary> {
+    let cache = options
+        .cache_dir
+        .as_deref()
+        .map(RenderCache::new)
+        .transpose()?;
+    let deadline = options.deadline.map(|duration| Instant::now() + duration);
+    let render = |path: &PathBuf| {
+        let page = render_entry(path, pages_dirs, options, cache.as_ref());
+        on_rendered();
+        page
+    };
+
+    let mut merge = options
+        .split_by
+        .is_none()
+        .then(|| RenderedMerge::new(options));
+    let mut pages = Vec::with_capacity(paths.len());
+    let mut skipped = Vec::new();
+    let mut attempted_count = 0;
+    // Without `skip_errors`, a failure stops the run before the remaining
+    // batches are rendered for nothing.
+    for batch in paths.chunks(rayon::current_num_threads() * MERGE_BATCH_PER_THREAD) {
+        let attempted: Vec<Result<(PathBuf, RenderedPage)>> = match deadline {
+            Some(deadline) => {
+                deadline::render_in_order(batch.len(), deadline, |index| render(&batch[index]))
+                    .into_iter()
+                    .map_while(|page| page)
+                    .collect()
+            }
+            None => batch.par_iter().map(render).collect(),
+        };
+        attempted_count += attempted.len();
+        let cut_short = attempted.len() < batch.len();
+
+        let mut rendered = Vec::with_capacity(attempted.len());
+        // Results come in the order of `batch`, cut short by a deadline.
+        for (path, page) in batch.iter().zip(attempted) {
+            match page {
+                Ok(page) => rendered.push(page),
+                Err(e) if options.skip_errors => {
+                    error!("{:#}, skipping it", e);
+                    skipped.push(SkippedFile {
+                        path: path.clone(),
+                        error: format!("{:#}", e),
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(merge) = &mut merge {
+            merge.add(&mut rendered)?;
+        }
+        pages.append(&mut rendered);
+
+        if cut_short {
+            break;
+        }
+    }
+
+    if options.skip_errors {
+        info!("{} pages rendered, {} failed", pages.len(), skipped.len());
+    }
+    let deadline_exceeded = attempted_count < paths.len();
+    if let Some(duration) = options.deadline.filter(|_| deadline_exceeded) {
+        warn!(
+            "Deadline of {:?} exceeded, {} of {} pages made the cut",
+            duration,
+            attempted_count,
+            paths.len()
+        );
+    }
+    skipped.extend(paths[attempted_count..].iter().map(|path| SkippedFile {
+        path: path.clone(),
+        error: "Deadline exceeded before rendering started".to_string(),
+    }));
+    if pages.is_empty() {
+        if attempted_count == 0 && deadline_exceeded {
+            return Ok(RunSummary {
+                pages,
+                skipped,
+                page_count: 0,
+                deadline_exceeded,
+            });
+        }
+        return Err(anyhow!("No page could be rendered."));
+    }
+
+    let page_count = match merge {
+        Some(merge) => {
+            let mut merged_pdf = finish_merge(merge, &pages, pages_dirs, options)?;
+            write_document(&mut merged_pdf, options, output)
+                .with_context(|| format!("Cannot write the merged PDF to {:?}", output))?;
+            merged_pdf.get_pages().len()
+        }
+        None => {
+            let mut chapters = split_by_dir(pages, pages_dirs, |(path, _)| path.as_path())?;
+            let mut page_count = 0;
+            for chapter in &mut chapters {
+                let pdf_path = output.join(format!("{}.pdf", chapter.name));
+                info!("Writing {:?}", pdf_path);
+                let mut chapter_pdf = merge_rendered(
+                    &mut chapter.pages,
+                    std::slice::from_ref(&chapter.dir),
+                    options,
+                )?;
+                page_count += chapter_pdf.get_pages().len();
+                write_document(&mut chapter_pdf, options, &pdf_path)
+                    .with_context(|| format!("Cannot write the PDF to {:?}", pdf_path))?;
+            }
+            pages = chapters
+                .into_iter()
+                .flat_map(|chapter| chapter.pages)
+                .collect();
+            page_count
+        }
+    };
+
+    Ok(RunSummary {
+        pages,
+        skipped,
+        page_count,
+        deadline_exceeded,
+    })
+}
+
+/// Renders a single page as [`run`] does, with [`Options::timeout`],
+/// [`Options::keep_intermediate`] and [`Options::cache_dir`].
+fn render_entry(
+    path: &Path,
+    pages_dirs: &[PathBuf],
+    options: &Options,
+    cache: Option<&RenderCache>,
+) -> Result<(PathBuf, RenderedPage)> {
+    let (path, page) = match options.timeout {
+        Some(timeout) => {
+            let page_path = path.to_path_buf();
+            let render_options = options.render.clone();
+            let cache = cache.cloned();
+            // At most one runaway page per render job keeps running in the
+            // background, doubling the CPU use of `--jobs` at worst.
+            deadline::run_with_timeout(
+                format!("rendering SVG file {:?}", path),
+                timeout,
+                rayon::current_num_threads(),
+                move || render_file_cached(&page_path, &render_options, cache.as_ref()),
+            )?
+            .ok_or_else(|| {
+                anyhow!(
+                    "Rendering SVG file {:?} timed out after {:?}",
+                    path,
+                    timeout
+                )
+            })?
+        }
+        None => render_file_cached(path, &options.render, cache)?,
+    };
+    if let Some(dir) = &options.keep_intermediate {
+        keep_intermediate(dir, pages_dirs, &path, &page)?;
+    }
+
+    Ok((path, page))
+}
+
+/// Writes the PDF rendered from `page_path` to `dir`, at the page's
+/// [`relative_path`], so pages in subdirectories don't overwrite each other.
+fn keep_intermediate(
+    dir: &Path,
+    pages_dirs: &[PathBuf],
+    page_path: &Path,
+    page: &RenderedPage,
+) -> Result<()> {
+    let pdf_path = dir
+        .join(relative_path(page_path, pages_dirs))
+        .with_extension("pdf");
+
+    if let Some(parent) = pdf_path.parent() {
+        create_dir_all(parent)?;
+    }
+    std::fs::write(&pdf_path, &page.pdf)
+        .with_context(|| format!("Cannot write intermediate PDF {:?}", pdf_path))?;
+
+    Ok(())
+}
+
+/// Writes `document` with [`save_document`] to the file at `path`, creating
+/// the directories it is in, or to stdout if `path` is [`STDOUT_PATH`].
+fn write_document(document: &mut Document, options: &Options, path: &Path) -> Result<()> {
+    if path == Path::new(STDOUT_PATH) {
+        return save_document(document, options, &mut std::io::stdout().lock());
+    }
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)
+            .with_context(|| format!("Cannot create output directory {:?}", parent))?;
+    }
+    save_document(document, options, &mut File::create(path)?)
+}
+
+/// Collects the SVG files in `dir` and its subdirectories, and with
+/// [`Options::include_pdfs`] the PDF files, in [`Options::sort`] order.
+///
+/// `exclude`, e.g. the output of an earlier run, is never picked up, nor
+/// are PDFs rendered from an SVG of the same name next to them, such as the
+/// ones `--debug-file` writes, which are told apart by not being older than
+/// the SVG. Entries that can't be read are skipped with a warning, or with
+/// [`Options::strict`] fail the scan.
+///
+/// [`Options::include`] and [`Options::exclude`] are matched against the
+/// path relative to `dir` with `/` separators, e.g. `chapter1/01-intro.svg`,
+/// where `*` also matches across directories.
+///
+/// If `dir` is a ZIP archive, its entries are listed instead, see
+/// [`find_zip_pages`].
+pub fn find_pages(dir: &Path, options: &Options, exclude: Option<&Path>) -> Result<Vec<PathBuf>> {
+    if is_zip(dir) {
+        return find_zip_pages(dir, options);
+    }
+
+    let filter = PathFilter::new(options)?;
+    let exclude = exclude.map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+
+    let mut walk_errors = 0;
+    let mut svg_paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Skipping unreadable entry: {}", e);
+                walk_errors += 1;
+                None
+            }
+        })
+        .filter(
+            |entry| match entry.path().extension().and_then(|s| s.to_str()) {
+                Some("svg" | "svgz") => true,
+                Some("pdf") => options.include_pdfs,
+                _ => false,
+            },
+        )
+        // Leftovers of `--debug-file` are not pages, nor is the output of an
+        // earlier run.
+        .filter(|entry| {
+            let path = entry.path();
+            if is_pdf(path) {
+                rendered_from(path).is_none()
+                    && path
+                        .canonicalize()
+                        .map_or(true, |path| Some(path) != exclude)
+            } else {
+                !entry
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(DEBUG_SVG_SUFFIX)
+            }
+        })
+        .map(|entry| entry.into_path())
+        .filter(|path| filter.matches(path, dir))
+        .collect();
+
+    // Sort the input files by their path, so that a deadline keeps a
+    // contiguous run of leading pages.
+    svg_paths.sort_by(|a, b| options.sort.compare(a, b));
+
+    if walk_errors > 0 {
+        if options.strict {
+            return Err(anyhow!(
+                "Could not read all of {:?}, {} entries failed.",
+                dir,
+                walk_errors
+            ));
+        }
+        warn!(
+            "Could not read all of {:?}, {} entries were skipped",
+            dir, walk_errors
+        );
+    }
+
+    Ok(svg_paths)
+}
+
+/// Lists the SVG files in the ZIP archive at `zip_path`, and with
+/// [`Options::include_pdfs`] the PDF files, in [`Options::sort`] order of
+/// their entry names.
+///
+/// Entries are returned as paths inside the archive, e.g.
+/// `pages.zip/chapter1/01-summary.svg`, which [`read_source`] and
+/// [`render_file`] read from the archive. macOS metadata under `__MACOSX/`
+/// is skipped.
+pub fn find_zip_pages(zip_path: &Path, options: &Options) -> Result<Vec<PathBuf>> {
+    let archive = ZipArchive::new(File::open(zip_path)?)
+        .with_context(|| format!("Cannot read ZIP archive {:?}", zip_path))?;
+    let filter = PathFilter::new(options)?;
+
+    let mut svg_paths: Vec<PathBuf> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && !name.starts_with("__MACOSX/"))
+        .map(|name| zip_path.join(name))
+        .filter(|path| match path.extension().and_then(|s| s.to_str()) {
+            Some("svg" | "svgz") => !path.to_string_lossy().ends_with(DEBUG_SVG_SUFFIX),
+            Some("pdf") => options.include_pdfs,
+            _ => false,
+        })
+        .filter(|path| filter.matches(path, zip_path))
+        .collect();
+    svg_paths.sort_by(|a, b| options.sort.compare(a, b));
+
+    Ok(svg_paths)
+}
+
+/// [`Options::include`] and [`Options::exclude`], compiled for matching.
+struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    fn new(options: &Options) -> Result<PathFilter> {
+        let build = |globs: &[Glob]| {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(glob.clone());
+            }
+            builder.build()
+        };
+
+        Ok(PathFilter {
+            include: if options.include.is_empty() {
+                None
+            } else {
+                Some(build(&options.include)?)
+            },
+            exclude: build(&options.exclude)?,
+        })
+    }
+
+    /// Whether the file at `path`, found in `dir`, is to be picked up.
+    fn matches(&self, path: &Path, dir: &Path) -> bool {
+        let relative = path
+            .strip_prefix(dir)
+            .unwrap_or(path)
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(&relative))
+            && !self.exclude.is_match(&relative)
+    }
+}
+
+/// Whether `path` is a ZIP archive to read the pages from rather than a
+/// directory.
+pub fn is_zip(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|extension| extension == "zip")
+}
+
+/// The SVG next to `pdf_path` that it was rendered from, e.g. by
+/// `--debug-file`, logging that the PDF is skipped for it.
+///
+/// That's an SVG of the same name, unless the PDF is older than it: a PDF
+/// that was there before its SVG was last changed isn't its rendering, and
+/// is merged as a page of its own.
+fn rendered_from(pdf_path: &Path) -> Option<PathBuf> {
+    let modified = |path: &Path| {
+        path.metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let pdf_modified = modified(pdf_path);
+    let svg_path = ["svg", "svgz"]
+        .iter()
+        .map(|extension| pdf_path.with_extension(extension))
+        .filter(|svg_path| svg_path.is_file())
+        .find(|svg_path| match (pdf_modified, modified(svg_path)) {
+            (Some(pdf_modified), Some(svg_modified)) => pdf_modified >= svg_modified,
+            _ => true,
+        })?;
+    warn!(
+        "Skipping {:?}, it was rendered from {:?} which is merged instead",
+        pdf_path, svg_path
+    );
+
+    Some(svg_path)
+}
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "pdf")
+}
+
+/// A ZIP archive opened by [`read_source`], shared by the pages read from it.
+type SharedArchive = Arc<Mutex<ZipArchive<File>>>;
+
+/// The ZIP archives [`read_source`] read entries from, by path, so an
+/// archive's central directory is parsed once rather than once per page.
+static ARCHIVES: Mutex<Vec<(PathBuf, SharedArchive)>> = Mutex::new(Vec::new());
+
+/// The archive at `zip_path`, opened on first use.
+fn open_archive(zip_path: &Path) -> Result<SharedArchive> {
+    let mut archives = ARCHIVES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((_, archive)) = archives.iter().find(|(path, _)| path == zip_path) {
+        return Ok(archive.clone());
+    }
+
+    let archive = Arc::new(Mutex::new(
+        ZipArchive::new(File::open(zip_path)?)
+            .with_context(|| format!("Cannot read ZIP archive {:?}", zip_path))?,
+    ));
+    archives.push((zip_path.to_path_buf(), archive.clone()));
+
+    Ok(archive)
+}
+
+/// Reads a page file, or an entry of a ZIP archive given as a path inside
+/// it, as listed by [`find_zip_pages`].
+///
+/// Entries larger than [`MAX_SVG_SIZE`] are rejected.
+pub fn read_source(path: &Path) -> Result<Vec<u8>> {
+    if !path.exists() {
+        if let Some(zip_path) = path.ancestors().skip(1).find(|ancestor| is_zip(ancestor)) {
+            let name = path
+                .strip_prefix(zip_path)?
+                .iter()
+                .map(|part| part.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let archive = open_archive(zip_path)?;
+            let mut archive = archive
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            // Stops right past the size limit, like `read_svg`, so an entry
+            // can't expand into gigabytes before it is rejected.
+            let mut content = Vec::new();
+            archive
+                .by_name(&name)?
+                .take(MAX_SVG_SIZE as u64 + 1)
+                .read_to_end(&mut content)?;
+            if content.len() > MAX_SVG_SIZE {
+                return Err(anyhow!(
+                    "The ZIP entry {:?} is larger than the {} bytes allowed",
+                    name,
+                    MAX_SVG_SIZE
+                ));
+            }
+            return Ok(content);
+        }
+    }
+
+    Ok(read(path)?)
+}
+
+/// Reads an SVG file, decompressing it if it is a gzipped `.svgz`.
+pub fn read_svg(path: &Path) -> Result<String> {
+    let content = read_source(path)?;
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "svgz")
+    {
+        // Stops right past the size limit, so a small file can't expand into
+        // gigabytes before it is rejected.
+        let mut svg = Vec::new();
+        GzDecoder::new(content.as_slice())
+            .take(MAX_SVG_SIZE as u64 + 1)
+            .read_to_end(&mut svg)?;
+        if svg.len() > MAX_SVG_SIZE {
+            return Err(anyhow!(
+                "The SVG decompresses to more than the {} bytes allowed",
+                MAX_SVG_SIZE
+            ));
+        }
+        return Ok(String::from_utf8(svg)?);
+    }
+
+    Ok(String::from_utf8(content)?)
+}
+
+/// Reads and renders one SVG file, or reads a PDF file to merge as is.
+///
+/// Images the SVG links to by a relative path are looked up next to it.
+pub fn render_file(path: &Path, render_options: &RenderOptions) -> Result<(PathBuf, RenderedPage)> {
+    render_file_cached(path, render_options, None)
+}
+
+/// Like [`render_file`], but takes SVGs rendered before with the same
+/// options from `cache` instead of rendering them again, and stores the
+/// ones it renders there. Failing to store a page only logs a warning.
+pub fn render_file_cached(
+    path: &Path,
+    render_options: &RenderOptions,
+    cache: Option<&RenderCache>,
+) -> Result<(PathBuf, RenderedPage)> {
+    let page = if is_pdf(path) {
+        let pdf = read_source(path).with_context(|| format!("Cannot read PDF file {:?}", path))?;
+        let page = RenderedPage::from_pdf(pdf)
+            .with_context(|| format!("Cannot load PDF file {:?}", path))?;
+        info!("Adding PDF file: {:?}", &path);
+        page
+    } else {
+        let svg_content =
+            read_svg(path).with_context(|| format!("Cannot read SVG file {:?}", path))?;
+        let render_options = RenderOptions {
+            resources_dir: path.parent().map(Path::to_path_buf),
+            ..render_options.clone()
+        };
+        let key = cache.map(|_| RenderCache::key(&svg_content, &render_options));
+        let cached = cache
+            .zip(key.as_deref())
+            .and_then(|(cache, key)| cache.load(key));
+        let page = match cached {
+            Some(page) => {
+                info!("Reusing the cached rendering of {:?}", &path);
+                page
+            }
+            None => {
+                let page = render_page(&svg_content, &render_options)
+                    .with_context(|| format!("Cannot render SVG file {:?}", path))?;
+                info!("Rendering file: {:?}", &path);
+                debug!(
+                    "Rendered {:?} in {:?}: expansion {:?}, parsing {:?}, conversion {:?}, thumbnail {:?}",
+                    &path,
+                    page.total_time,
+                    page.timings.expansion,
+                    page.timings.parsing,
+                    page.timings.conversion,
+                    page.timings.thumbnail
+                );
+                if let Some((cache, key)) = cache.zip(key.as_deref()) {
+                    if let Err(e) = cache.store(key, &page) {
+                        warn!("{:#}", e);
+                    }
+                }
+                page
+            }
+        };
+        if page.default_size {
+            warn!("{:?} has no size of its own, using the default size", path);
+        }
+        page
+    };
+
+    Ok((path.to_path_buf(), page))
+}
+
+/// Merges rendered pages into a single document and applies every
+/// post-processing step `options` asks for, from inserting into
+/// [`Options::base`] to setting the metadata.
+///
+/// Bookmarks of files in subdirectories are nested relative to the entry of
+/// `pages_dirs` they were found in, see [`relative_path`]. Blank pages
+/// inserted by [`Options::duplex_align`] get no bookmark, but are numbered
+/// like any other page.
+///
+/// The pages' PDFs are parsed in parallel, in batches, and each batch is
+/// emptied as soon as it is merged, before the document is built, unless
+/// [`Options::preserve_sizes`] needs them afterwards. Their headings, titles
+/// and timings are kept.
+pub fn merge_rendered(
+    pages: &mut [(PathBuf, RenderedPage)],
+    pages_dirs: &[PathBuf],
+    options: &Options,
+) -> Result<Document> {
+    let mut merge = RenderedMerge::new(options);
+    merge.add(pages)?;
+
+    finish_merge(merge, pages, pages_dirs, options)
+}
+
+/// Feeds rendered pages to a [`PdfMerger`] as they come, freeing the PDFs of
+/// every batch once merged unless [`Options::preserve_sizes`] needs them.
+struct RenderedMerge<'a> {
+    merger: PdfMerger,
+    options: &'a Options,
+    /// Directory of the page added last, `None` before the first one.
+    previous_dir: Option<Option<PathBuf>>,
+}
+
+impl<'a> RenderedMerge<'a> {
+    fn new(options: &'a Options) -> RenderedMerge<'a> {
+        info!("Merging all files into a single report");
+        RenderedMerge {
+            merger: PdfMerger::new(options.merge.clone()),
+            options,
+            previous_dir: None,
+        }
+    }
+
+    /// Merges `pages` after the ones added before.
+    fn add(&mut self, pages: &mut [(PathBuf, RenderedPage)]) -> Result<()> {
+        // Inputs are added in parallel, in batches up to where a blank page
+        // may be needed.
+        let mut start = 0;
+        for index in 0..pages.len() {
+            // A page in another directory than the one before starts a new
+            // section, which belongs on a right-hand page when printed duplex.
+            let dir = pages[index].0.parent().map(Path::to_path_buf);
+            if self.options.duplex_align
+                && self
+                    .previous_dir
+                    .as_ref()
+                    .is_some_and(|previous_dir| *previous_dir != dir)
+            {
+                self.add_batch(&mut pages[start..index])?;
+                start = index;
+                if self.merger.page_count() % 2 == 1 {
+                    self.merger.add_blank_page();
+                }
+            }
+            self.previous_dir = Some(dir);
+        }
+
+        self.add_batch(&mut pages[start..])
+    }
+
+    fn add_batch(&mut self, pages: &mut [(PathBuf, RenderedPage)]) -> Result<()> {
+        let batch: Vec<&[u8]> = pages.iter().flat_map(|(_, page)| page.inputs()).collect();
+        self.merger.add_pages(&batch)?;
+
+        if !self.options.preserve_sizes {
+            for (_, page) in pages {
+                // Empty PDFs stay in place of the embedded ones, so
+                // `inputs` still counts them for the outline.
+                std::mem::take(&mut page.pdf);
+                page.embedded_pdfs.iter_mut().for_each(|pdf| {
+                    std::mem::take(pdf);
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds the document `merge` was fed `pages` for and applies the
+/// post-processing steps, see [`merge_rendered`].
+fn finish_merge(
+    merge: RenderedMerge,
+    pages: &[(PathBuf, RenderedPage)],
+    pages_dirs: &[PathBuf],
+    options: &Options,
+) -> Result<Document> {
+    let outline = if options.heading_bookmarks {
+        let outline = heading_outline(pages);
+        if outline.is_none() {
+            warn!("No headings found, falling back to one bookmark per page");
+        }
+        outline
+    } else {
+        None
+    };
+    let outline = outline.unwrap_or_else(|| file_outline(pages, pages_dirs));
+    let mut merger = merge.merger;
+    merger.set_outline(Some(outline));
+
+    let input_paths: Vec<&Path> = pages
+        .iter()
+        .flat_map(|(path, page)| page.inputs().map(|_| path.as_path()))
+        .collect();
+    for input in merger.dropped_blank_pages() {
+        info!("Dropping a blank page of {:?}", input_paths[*input]);
+    }
+    let page_inputs = merger.page_inputs().to_vec();
+    let mut merged_pdf = merger.finish()?;
+    if options.warn_blank || options.warn_duplicates {
+        check_pages(&merged_pdf, &page_inputs, &input_paths, options)?;
+    }
+
+    if options.preserve_sizes {
+        let inputs: Vec<&[u8]> = pages.iter().flat_map(|(_, page)| page.inputs()).collect();
+        verify_page_sizes(&inputs, &merged_pdf)?;
+        info!("Verified that all {} pages kept their size", inputs.len());
+    }
+
+    if let Some(mode) = options.size_from_first {
+        let changed = page_size::match_first_page_size(&mut merged_pdf, mode)?;
+        info!("Gave {} pages the size of the first page", changed);
+    }
+
+    if let Some(base_path) = &options.base {
+        info!("Inserting pages into {:?}", base_path);
+        let base = Document::load(base_path)?;
+        let index = match options.insert_at {
+            Some(page) => page as usize - 1,
+            None => base.get_pages().len(),
+        };
+        merged_pdf = insert::insert_pages(base, merged_pdf, index)?;
+        options.merge.pdf_version.apply(&mut merged_pdf);
+    }
+
+    if options.toc {
+        info!("Adding a table of contents");
+        toc::insert_toc(&mut merged_pdf, options.duplex_align)?;
+    }
+
+    // Before anything is placed relative to the visible area, which grows by
+    // the margin.
+    if options.bleed > 0.0 || options.margin > 0.0 {
+        info!("Adding bleed and margins");
+        bleed::add_bleed(&mut merged_pdf, options.bleed, options.margin)?;
+    }
+
+    if options.draft {
+        info!("Stamping pages as draft");
+        draft::stamp_draft(&mut merged_pdf)?;
+    }
+
+    if let Some(watermark) = &options.watermark {
+        info!("Adding the watermark {:?}", watermark.text);
+        watermark::apply_watermark(&mut merged_pdf, watermark)?;
+    }
+
+    if options.page_numbers {
+        info!("Numbering pages");
+        page_numbers::number_pages(
+            &mut merged_pdf,
+            &options.page_number_format,
+            options.page_number_position,
+        )?;
+    }
+
+    if options.continuous {
+        info!("Stacking pages into a single continuous page");
+        continuous::make_continuous(&mut merged_pdf)?;
+    }
+
+    if !options.rotate.is_empty() {
+        info!("Rotating pages");
+        rotation::rotate_pages(&mut merged_pdf, &options.rotate)?;
+    }
+
+    if let Some(labels) = &options.page_labels {
+        info!("Labeling pages");
+        page_labels::set_page_labels(&mut merged_pdf, labels)?;
+    }
+
+    user_unit::apply_user_units(&mut merged_pdf)?;
+
+    if let Some(decimals) = options.precision {
+        precision::round_content_numbers(&mut merged_pdf, decimals)?;
+    }
+
+    if options.embed_sources {
+        info!("Attaching the source SVGs");
+        attachments::embed_files(
+            &mut merged_pdf,
+            source_attachments(pages, pages_dirs)?,
+            !options.merge.skip_compression,
+        )?;
+    }
+
+    if let Some(profile) = &options.color_profile {
+        info!("Declaring the output intent");
+        color_profile::set_output_intent(
+            &mut merged_pdf,
+            &profile.load()?,
+            !options.merge.skip_compression,
+        )?;
+    }
+
+    if let Some(level) = options.compression_level {
+        compression::compress_with_level(&mut merged_pdf, level)?;
+    }
+
+    let time = if options.deterministic {
+        metadata::reproducible_time()?
+    } else {
+        SystemTime::now()
+    };
+    metadata::set_metadata(&mut merged_pdf, &options.metadata, time);
+
+    if let Some(encryption) = &options.encryption {
+        info!("Encrypting the document");
+        let file_id = if options.deterministic {
+            encryption::content_file_id(&mut merged_pdf)?
+        } else {
+            encryption::unique_file_id(&merged_pdf)
+        };
+        encryption::encrypt(&mut merged_pdf, encryption, file_id)?;
+    }
+
+    Ok(merged_pdf)
+}
+
+/// Warns about the pages of `document` that [`Options::warn_blank`] and
+/// [`Options::warn_duplicates`] ask for, `page_inputs` telling which of
+/// `input_paths` every page came from. Blank pages inserted for
+/// [`Options::duplex_align`] are left alone.
+fn check_pages(
+    document: &Document,
+    page_inputs: &[Option<usize>],
+    input_paths: &[&Path],
+    options: &Options,
+) -> Result<()> {
+    let mut fingerprints: HashMap<[u8; 16], (usize, &Path)> = HashMap::new();
+    for (index, (page_id, input)) in document.page_iter().zip(page_inputs).enumerate() {
+        let path = match input {
+            Some(input) => input_paths[*input],
+            None => continue,
+        };
+
+        if options.warn_blank && page_checks::is_blank_page(document, page_id)? {
+            warn!("Page {} ({:?}) is blank", index + 1, path);
+        }
+
+        if options.warn_duplicates {
+            let fingerprint = page_checks::page_fingerprint(document, page_id)?;
+            match fingerprints.get(&fingerprint) {
+                Some((original, original_path)) => {
+                    warn!(
+                        "Page {} ({:?}) is identical to page {} ({:?}){}",
+                        index + 1,
+                        path,
+                        original + 1,
+                        original_path,
+                        if original + 1 == index {
+                            ", the page before it"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+                None => {
+                    fingerprints.insert(fingerprint, (index, path));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a document [`merge_rendered`] returned to `target`, linearized
+/// with [`Options::linearize`], and else with [`crate::save_document`].
+pub fn save_document<W: Write>(
+    document: &mut Document,
+    options: &Options,
+    target: &mut W,
+) -> Result<()> {
+    if options.linearize {
+        info!("Linearizing the document");
+        linearize::save_linearized(document, target)
+    } else {
+        crate::save_document(document, target)
+    }
+}
+
+/// How `--split-by` divides the pages into several PDFs instead of merging
+/// them all into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitBy {
+    /// One PDF per top-level subdirectory, named after it
+    Dir,
+}
+
+/// Pages that go into one PDF of their own with [`SplitBy::Dir`].
+#[derive(Debug)]
+pub struct Chapter<T> {
+    /// File name of the PDF without `.pdf`, the name of the subdirectory.
+    pub name: String,
+    /// The subdirectory, which the pages' bookmarks and attachment names are
+    /// relative to.
+    pub dir: PathBuf,
+    pub pages: Vec<T>,
+}
+
+/// Groups `pages` by the top-level subdirectory of `pages_dirs` they are in,
+/// see [`relative_path`], in the order the first page of every group comes
+/// in. Pages directly in an entry of `pages_dirs`, not in any subdirectory,
+/// form a group named after that entry, e.g. `pages` for `pages.zip`.
+///
+/// Fails if two groups would get the same name, e.g. a `chapter1` in two of
+/// `pages_dirs`, as they would be written to the same file.
+pub fn split_by_dir<T>(
+    pages: Vec<T>,
+    pages_dirs: &[PathBuf],
+    path: impl Fn(&T) -> &Path,
+) -> Result<Vec<Chapter<T>>> {
+    let mut chapters: Vec<Chapter<T>> = Vec::new();
+    for page in pages {
+        let page_path = path(&page);
+        let relative = relative_path(page_path, pages_dirs);
+        let depth = relative.components().count();
+        // The subdirectory is as many levels up as the relative path has
+        // below it, the entry itself for pages right in it.
+        let levels_up = if depth > 1 { depth - 1 } else { 1 };
+        let dir = page_path
+            .ancestors()
+            .nth(levels_up)
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+
+        match chapters.iter_mut().find(|chapter| chapter.dir == dir) {
+            Some(chapter) => chapter.pages.push(page),
+            None => {
+                let name = if depth > 1 {
+                    relative
+                        .iter()
+                        .next()
+                        .map(|name| name.to_string_lossy().into_owned())
+                } else {
+                    // `.` or the working directory has no name of its own.
+                    dir.canonicalize()
+                        .unwrap_or_else(|_| dir.clone())
+                        .file_stem()
+                        .map(|name| name.to_string_lossy().into_owned())
+                }
+                .unwrap_or_else(|| "merged".to_string());
+
+                if let Some(other) = chapters.iter().find(|chapter| chapter.name == name) {
+                    return Err(anyhow!(
+                        "Pages in {:?} and {:?} would both be written to {}.pdf.",
+                        other.dir,
+                        dir,
+                        name
+                    ));
+                }
+                chapters.push(Chapter {
+                    name,
+                    dir,
+                    pages: vec![page],
+                });
+            }
+        }
+    }
+
+    Ok(chapters)
+}
+
+/// Path of the page at `path` relative to the entry of `pages_dirs` it was
+/// found in, the innermost if they are nested, or just its file name if it
+/// is in none of them, e.g. a manifest entry outside the manifest's
+/// directory.
+pub fn relative_path<'a>(path: &'a Path, pages_dirs: &[PathBuf]) -> &'a Path {
+    pages_dirs
+        .iter()
+        .filter_map(|pages_dir| path.strip_prefix(pages_dir).ok())
+        .min_by_key(|relative| relative.components().count())
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path)
+}
+
+/// The SVG files `pages` were rendered from, named by their
+/// [`relative_path`], e.g. `chapter1/01-summary.svg`.
+fn source_attachments(
+    pages: &[(PathBuf, RenderedPage)],
+    pages_dirs: &[PathBuf],
+) -> Result<Vec<Attachment>> {
+    pages
+        .iter()
+        .map(|(path, _)| path)
+        .filter(|path| !is_pdf(path))
+        .map(|path| {
+            let name = relative_path(path, pages_dirs)
+                .iter()
+                .map(|part| part.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let content =
+                read_source(path).with_context(|| format!("Cannot read SVG file {:?}", path))?;
+
+            Ok(Attachment {
+                name,
+                mime_type: "image/svg+xml".to_string(),
+                content,
+            })
+        })
+        .collect()
+}
+
+/// Turns the headings of all pages into an outline, or `None` if no page has
+/// any headings.
+fn heading_outline(pages: &[(PathBuf, RenderedPage)]) -> Option<Vec<OutlineEntry>> {
+    let mut outline = Vec::new();
+    let mut input = 0;
+
+    for (_, page) in pages {
+        outline.extend(page.headings.iter().map(|heading| OutlineEntry {
+            level: heading.level,
+            title: heading.title.clone(),
+            input,
+        }));
+        input += page.inputs().count();
+    }
+
+    if outline.is_empty() {
+        return None;
+    }
+
+    Some(outline)
+}
+
+/// One bookmark per SVG file, titled with the SVG's `<title>` if it has one,
+/// or else its file name without the extension, e.g. `01-summary`.
+///
+/// Files in subdirectories of `pages_dirs` are nested under a bookmark per
+/// subdirectory, e.g. `chapter1`, which points at the first page in it.
+fn file_outline(pages: &[(PathBuf, RenderedPage)], pages_dirs: &[PathBuf]) -> Vec<OutlineEntry> {
+    let mut outline = Vec::new();
+    let mut open_dirs: Vec<String> = Vec::new();
+    let mut input = 0;
+
+    for (path, page) in pages {
+        let dirs: Vec<String> =
+            relative_path(path, pages_dirs)
+                .parent()
+                .map_or_else(Vec::new, |parent| {
+                    parent
+                        .iter()
+                        .map(|dir| dir.to_string_lossy().into_owned())
+                        .collect()
+                });
+
+        let shared = open_dirs
+            .iter()
+            .zip(&dirs)
+            .take_while(|(open_dir, dir)| open_dir == dir)
+            .count();
+        for (depth, dir) in dirs.iter().enumerate().skip(shared) {
+            outline.push(OutlineEntry {
+                level: depth as u32 + 1,
+                title: dir.clone(),
+                input,
+            });
+        }
+
+        let title = page.title.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+        });
+        outline.push(OutlineEntry {
+            level: dirs.len() as u32 + 1,
+            title,
+            input,
+        });
+
+        open_dirs = dirs;
+        input += page.inputs().count();
+    }
+
+    outline
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, Object};
+
+    use super::*;
+
+    fn rendered_page() -> RenderedPage {
+        RenderedPage::from_pdf(
+            crate::render_svg_to_pdf(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn nests_bookmarks_of_files_two_levels_deep() {
+        let root = PathBuf::from("report");
+        let pages: Vec<(PathBuf, RenderedPage)> = [
+            "ch1/intro.svg",
+            "ch1/sub/page.svg",
+            "ch1/sub/more.svg",
+            "ch2.svg",
+        ]
+        .iter()
+        .map(|path| (root.join(path), rendered_page()))
+        .collect();
+
+        let outline = file_outline(&pages, &[root]);
+        let outline: Vec<(u32, &str, usize)> = outline
+            .iter()
+            .map(|entry| (entry.level, entry.title.as_str(), entry.input))
+            .collect();
+
+        assert_eq!(
+            outline,
+            [
+                (1, "ch1", 0),
+                (2, "intro", 0),
+                (2, "sub", 1),
+                (3, "page", 1),
+                (3, "more", 2),
+                (1, "ch2", 3),
+            ]
+        );
+    }
+
+    /// A directory of its own under the system's temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pdf-postprocess-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rejects_zip_entries_over_the_size_limit() {
+        let dir = temp_dir("zip-limit");
+        let zip_path = dir.join("pages.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("small.svg", options).unwrap();
+        zip.write_all(b"<svg/>").unwrap();
+        zip.start_file("large.svg", options).unwrap();
+        zip.write_all(&vec![b' '; MAX_SVG_SIZE + 1]).unwrap();
+        zip.finish().unwrap();
+
+        assert_eq!(read_source(&zip_path.join("small.svg")).unwrap(), b"<svg/>");
+        let error = read_source(&zip_path.join("large.svg")).unwrap_err();
+        assert!(error.to_string().contains("bytes allowed"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renders_an_empty_svg_to_one_page() {
+        let dir = temp_dir("empty-svg");
+        let path = dir.join("empty.svg");
+        std::fs::write(&path, r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#).unwrap();
+
+        let (_, page) = render_file(&path, &RenderOptions::default()).unwrap();
+
+        assert_eq!(Document::load_mem(&page.pdf).unwrap().get_pages().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_the_file_without_pages() {
+        let dir = temp_dir("no-pages");
+        let path = dir.join("nothing.pdf");
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.add_object(dictionary! {
+            "Type" => "Pages",
+            "Kids" => Vec::<Object>::new(),
+            "Count" => 0,
+        });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc.save(&path).unwrap();
+
+        let error = format!(
+            "{:#}",
+            render_file(&path, &RenderOptions::default()).unwrap_err()
+        );
+
+        assert!(error.contains("nothing.pdf"), "{}", error);
+        assert!(error.contains("no pages"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_only_pdfs_rendered_from_their_svg() {
+        let dir = temp_dir("rendered-pdfs");
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        for name in ["cover", "page"] {
+            std::fs::write(dir.join(format!("{}.svg", name)), svg).unwrap();
+            std::fs::write(dir.join(format!("{}.pdf", name)), b"%PDF-1.5").unwrap();
+        }
+        // The cover PDF was there before its SVG was last edited.
+        File::options()
+            .write(true)
+            .open(dir.join("cover.pdf"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let options = Options {
+            include_pdfs: true,
+            ..Options::default()
+        };
+
+        let pages = find_pages(&dir, &options, None).unwrap();
+
+        let names: Vec<&str> = pages
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["cover.pdf", "cover.svg", "page.svg"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn frees_the_pdfs_of_merged_pages() {
+        let mut embedding = rendered_page();
+        embedding.embedded_pdfs.push(rendered_page().pdf);
+        let mut pages = vec![
+            (PathBuf::from("a.svg"), embedding),
+            (PathBuf::from("b.svg"), rendered_page()),
+        ];
+
+        let merged = merge_rendered(&mut pages, &[], &Options::default()).unwrap();
+
+        assert_eq!(merged.get_pages().len(), 3);
+        for (_, page) in &pages {
+            assert!(page.inputs().all(<[u8]>::is_empty));
+        }
+        assert_eq!(pages[0].1.inputs().count(), 2);
+        let titles: Vec<String> = merged
+            .get_toc()
+            .unwrap()
+            .toc
+            .into_iter()
+            .map(|entry| entry.title)
+            .collect();
+        assert_eq!(titles, ["a", "b"]);
+    }
+
+    #[test]
+    fn runs_past_pages_that_fail_to_render() {
+        let dir = temp_dir("skip-errors");
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        std::fs::write(dir.join("a.svg"), svg).unwrap();
+        std::fs::write(dir.join("b.svg"), "<svg").unwrap();
+        std::fs::write(dir.join("c.svg"), svg).unwrap();
+        let output = dir.join("out").join("merged.pdf");
+        let options = Options {
+            skip_errors: true,
+            ..Options::default()
+        };
+        let paths = find_pages(&dir, &options, None).unwrap();
+
+        let summary = run(
+            &paths,
+            std::slice::from_ref(&dir),
+            &output,
+            &options,
+            &|| {},
+        )
+        .unwrap();
+
+        assert_eq!(summary.page_count, 2);
+        assert_eq!(summary.pages.len(), 2);
+        assert!(!summary.deadline_exceeded);
+        let skipped: Vec<&Path> = summary
+            .skipped
+            .iter()
+            .map(|skipped| skipped.path.as_path())
+            .collect();
+        assert_eq!(skipped, [dir.join("b.svg")]);
+        assert_eq!(Document::load(&output).unwrap().get_pages().len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::Result;
+use log::warn;
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId};
+
+/// Operators whose operands are color components. Rounding those would shift
+/// colors visibly, so they keep their full precision.
+const COLOR_OPERATORS: [&str; 10] = ["sc", "scn", "SC", "SCN", "g", "G", "rg", "RG", "k", "K"];
+
+/// Operators whose operands are a transformation matrix. Every coordinate
+/// drawn after them is scaled by these, so rounding e.g. `0.01 0 0 0.01 cm`
+/// to zero would collapse the whole page.
+const MATRIX_OPERATORS: [&str; 2] = ["cm", "Tm"];
+
+/// Rounds the real number operands in the content streams of `doc` (pages
+/// and Form XObjects) to `decimals` decimal places, leaving colors and
+/// transformation matrices alone.
+///
+/// This trades sub-point accuracy for smaller files and output that stays
+/// byte-identical across tiny floating point differences between runs.
+/// Streams that can't be parsed as content are left unchanged.
+pub fn round_content_numbers(doc: &mut Document, decimals: u8) -> Result<()> {
+    let scale = 10f32.powi(decimals.into());
+
+    let mut stream_ids: Vec<ObjectId> = doc
+        .page_iter()
+        .flat_map(|page_id| doc.get_page_contents(page_id))
+        .collect();
+    stream_ids.extend(doc.objects.iter().filter_map(|(id, object)| {
+        let stream = object.as_stream().ok()?;
+        let subtype = stream.dict.get(b"Subtype").and_then(Object::as_name).ok()?;
+        (subtype == b"Form").then_some(*id)
+    }));
+
+    for stream_id in stream_ids {
+        let stream = match doc
+            .get_object_mut(stream_id)
+            .and_then(Object::as_stream_mut)
+        {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let compressed = stream.dict.has(b"Filter");
+        let data = match stream.decompressed_content() {
+            Ok(data) => data,
+            Err(_) => stream.content.clone(),
+        };
+
+        let mut content = match Content::decode(&data) {
+            Ok(content) => content,
+            Err(_) => {
+                warn!(
+                    "Could not parse content stream {:?}, leaving its numbers unrounded",
+                    stream_id
+                );
+                continue;
+            }
+        };
+
+        for operation in &mut content.operations {
+            let operator = operation.operator.as_str();
+            if COLOR_OPERATORS.contains(&operator) || MATRIX_OPERATORS.contains(&operator) {
+                continue;
+            }
+
+            for operand in &mut operation.operands {
+                round_object(operand, scale);
+            }
+        }
+
+        stream.set_plain_content(content.encode()?);
+        if compressed {
+            let _ = stream.compress();
+        }
+    }
+
+    Ok(())
+}
+
+fn round_object(object: &mut Object, scale: f32) {
+    match object {
+        Object::Real(value) => *value = (*value * scale).round() / scale,
+        Object::Array(items) => {
+            for item in items {
+                round_object(item, scale);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::content::Operation;
+
+    use super::*;
+    use crate::{merge_pdfs_with, render_svg_to_pdf, MergeOptions};
+
+    fn operations(doc: &Document) -> Vec<Operation> {
+        let page_id = doc.page_iter().next().unwrap();
+        Content::decode(&doc.get_page_content(page_id).unwrap())
+            .unwrap()
+            .operations
+    }
+
+    #[test]
+    fn keeps_the_scale_of_large_view_boxes() {
+        let pdf = render_svg_to_pdf(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100" viewBox="0 0 10000 10000"><rect x="1234.5678" y="1000" width="5000" height="5000"/></svg>"#,
+        )
+        .unwrap();
+        let merge_options = MergeOptions {
+            skip_compression: true,
+            ..MergeOptions::default()
+        };
+        let mut doc = merge_pdfs_with(vec![&pdf], &merge_options).unwrap();
+        let matrices = |doc: &Document| -> Vec<Vec<Object>> {
+            operations(doc)
+                .into_iter()
+                .filter(|operation| operation.operator == "cm")
+                .map(|operation| operation.operands)
+                .collect()
+        };
+        let before = matrices(&doc);
+        assert!(
+            before.iter().flatten().any(|operand| operand
+                .as_float()
+                .is_ok_and(|value| value != 0.0 && value.abs() < 0.05)),
+            "{:?}",
+            before
+        );
+
+        round_content_numbers(&mut doc, 1).unwrap();
+
+        assert_eq!(matrices(&doc), before);
+        // The rectangle is still drawn where it was, under the same scale.
+        let start = operations(&doc)
+            .into_iter()
+            .find(|operation| operation.operator == "m")
+            .unwrap();
+        let start: Vec<f32> = start
+            .operands
+            .iter()
+            .map(|operand| operand.as_float().unwrap())
+            .collect();
+        assert_eq!(start, [1234.6, 1000.0]);
+    }
+}
@@ -0,0 +1,46 @@
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Bar shown by `--progress`, set once rendering starts.
+static PROGRESS_BAR: OnceLock<ProgressBar> = OnceLock::new();
+
+/// Shows a progress bar for rendering `total` pages on stderr, which hides
+/// itself when stderr isn't a terminal.
+pub fn start(total: usize) -> &'static ProgressBar {
+    PROGRESS_BAR.get_or_init(|| {
+        let bar = ProgressBar::new(total as u64);
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} pages, ETA {eta}")
+                .expect("the progress bar template is valid"),
+        );
+        bar
+    })
+}
+
+/// Writes log messages to stderr, hiding the progress bar while doing so,
+/// so they don't end up drawn across it.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match PROGRESS_BAR.get() {
+            Some(bar) if !bar.is_finished() => bar.suspend(|| io::stderr().write(buf)),
+            _ => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> LogWriter {
+        LogWriter
+    }
+}
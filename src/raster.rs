@@ -0,0 +1,159 @@
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use log::{info, warn};
+use xmltree::Element;
+
+/// Data URI prefixes of the raster images that are validated, and the
+/// format their data is in.
+const RASTER_DATA_PREFIXES: [(&str, ImageFormat); 3] = [
+    ("data:image/png;base64,", ImageFormat::Png),
+    ("data:image/jpeg;base64,", ImageFormat::Jpeg),
+    ("data:image/jpg;base64,", ImageFormat::Jpeg),
+];
+
+/// Quality of JPEGs re-encoded after down-sampling.
+const JPEG_QUALITY: u8 = 90;
+
+/// Checks an `<image>` element embedding a base64 PNG or JPEG, returning
+/// whether to keep it.
+///
+/// Images whose data doesn't decode are reported and dropped instead of
+/// leaving a broken image on the page. With `max_side`, images whose longer
+/// side exceeds that many pixels are down-sampled to it. Any other element is
+/// kept as is.
+///
+/// Fails for images of more than `max_pixels` pixels, see
+/// [`check_image_pixels`].
+pub fn check_raster_image(
+    element: &mut Element,
+    max_side: Option<u32>,
+    max_pixels: u64,
+) -> Result<bool> {
+    if element.name != "image" {
+        return Ok(true);
+    }
+
+    let (key, prefix, format, base64_data) =
+        match crate::href_attribute(element).and_then(|(key, href)| {
+            RASTER_DATA_PREFIXES.iter().find_map(|(prefix, format)| {
+                let base64_data = href.strip_prefix(prefix)?;
+                Some((key, *prefix, *format, base64_data))
+            })
+        }) {
+            Some(raster) => raster,
+            None => return Ok(true),
+        };
+
+    let label = match element.attributes.get("id") {
+        Some(id) => format!("image {:?}", id),
+        None => "image without id".to_string(),
+    };
+
+    let data = match crate::decode_base64(base64_data) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!(
+                "Dropping embedded {}, its base64 data is invalid: {}",
+                label, e
+            );
+            return Ok(false);
+        }
+    };
+
+    check_image_pixels(
+        &data,
+        Some(format),
+        max_pixels,
+        &format!("Embedded {}", label),
+    )?;
+
+    let image = match image::load_from_memory_with_format(&data, format) {
+        Ok(image) => image,
+        Err(e) => {
+            warn!("Dropping embedded {}, it cannot be decoded: {}", label, e);
+            return Ok(false);
+        }
+    };
+
+    let max_side = match max_side {
+        Some(max_side) if image.width().max(image.height()) > max_side => max_side,
+        _ => return Ok(true),
+    };
+
+    let resized = image.resize(max_side, max_side, FilterType::Lanczos3);
+    match encode(&resized, format) {
+        Ok(encoded) => {
+            info!(
+                "Down-sampled embedded {} from {}x{} to {}x{}",
+                label,
+                image.width(),
+                image.height(),
+                resized.width(),
+                resized.height()
+            );
+            let href = format!(
+                "{}{}",
+                prefix,
+                base64::prelude::BASE64_STANDARD.encode(encoded)
+            );
+            element.attributes.insert(key.to_string(), href);
+        }
+        Err(e) => warn!("Keeping embedded {} at full size: {}", label, e),
+    }
+
+    Ok(true)
+}
+
+/// Fails if the PNG or JPEG in `data`, in `format` or else whichever its
+/// first bytes tell, has more than `max_pixels` pixels, `label` naming it in
+/// the error. Only the header is read, so an image too large to decode
+/// doesn't get decoded to find out.
+///
+/// Images in other formats, or whose header can't be read, pass.
+pub fn check_image_pixels(
+    data: &[u8],
+    format: Option<ImageFormat>,
+    max_pixels: u64,
+    label: &str,
+) -> Result<()> {
+    let reader = match format {
+        Some(format) => ImageReader::with_format(Cursor::new(data), format),
+        None => match ImageReader::new(Cursor::new(data)).with_guessed_format() {
+            Ok(reader) => reader,
+            Err(_) => return Ok(()),
+        },
+    };
+    let (width, height) = match reader.into_dimensions() {
+        Ok(dimensions) => dimensions,
+        Err(_) => return Ok(()),
+    };
+
+    if u64::from(width) * u64::from(height) > max_pixels {
+        return Err(anyhow!(
+            "{} is {}x{} pixels, more than the {} pixels allowed",
+            label,
+            width,
+            height,
+            max_pixels
+        ));
+    }
+
+    Ok(())
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> image::ImageResult<Vec<u8>> {
+    let mut output = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Jpeg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut output, JPEG_QUALITY))?
+        }
+        _ => image.write_to(&mut output, format)?,
+    }
+
+    Ok(output.into_inner())
+}
@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use pdf_postprocess::pipeline::SkippedFile;
+use pdf_postprocess::RenderedPage;
+
+/// Machine-readable summary of a run, written by `--report-json`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Number of pages found, in the directory or the manifest.
+    pub found: usize,
+    pub rendered: usize,
+    /// Pages left out by `--skip-errors` or `--deadline`.
+    pub skipped: Vec<SkippedFile>,
+    /// Wall-clock time of the whole run, in milliseconds.
+    pub total_ms: f64,
+    /// Where the merged PDF was written, `-` for stdout, or the directory
+    /// the PDFs were written to with `--split-by`. `None` if nothing was
+    /// written, no page having rendered before `--deadline`.
+    pub output: Option<PathBuf>,
+    /// Number of pages of the merged PDF, including blank and `--base` pages.
+    pub page_count: usize,
+    pub files: Vec<FileReport>,
+}
+
+/// Timings of a single rendered SVG file, in milliseconds.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub expansion_ms: f64,
+    pub parsing_ms: f64,
+    pub conversion_ms: f64,
+    pub thumbnail_ms: f64,
+    pub total_ms: f64,
+}
+
+/// Milliseconds with microsecond resolution, which is all that's meaningful.
+pub fn milliseconds(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Timings of every page in `pages`.
+pub fn file_reports(pages: &[(PathBuf, RenderedPage)]) -> Vec<FileReport> {
+    pages
+        .iter()
+        .map(|(page_path, page)| FileReport {
+            path: page_path.clone(),
+            expansion_ms: milliseconds(page.timings.expansion),
+            parsing_ms: milliseconds(page.timings.parsing),
+            conversion_ms: milliseconds(page.timings.conversion),
+            thumbnail_ms: milliseconds(page.timings.thumbnail),
+            total_ms: milliseconds(page.total_time),
+        })
+        .collect()
+}
+
+/// Writes `report` to `path` as pretty-printed JSON.
+pub fn write_report(path: &Path, report: &Report) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Cannot create report {:?}", path))?;
+    serde_json::to_writer_pretty(file, report)?;
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use lopdf::{Document, Object, ObjectId};
+
+use crate::page_ranges::PageRanges;
+
+/// Turns pages for viewing by a multiple of 90 degrees clockwise, either all
+/// of them, e.g. `90`, or those at some positions, e.g. `3:90` or
+/// `1-5,8:270`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRotation {
+    /// Pages turned, all of them if `None`.
+    pages: Option<PageRanges>,
+    /// Clockwise, one of 0, 90, 180 and 270.
+    degrees: i64,
+}
+
+impl FromStr for PageRotation {
+    type Err = String;
+
+    /// Parses `DEGREES` or `PAGES:DEGREES`, with `PAGES` as accepted by
+    /// [`PageRanges`]. Degrees beyond a full turn or negative ones are
+    /// brought into `0..360`.
+    fn from_str(value: &str) -> Result<PageRotation, String> {
+        let (pages, degrees) = match value.rsplit_once(':') {
+            Some((pages, degrees)) => (Some(pages.parse::<PageRanges>()?), degrees),
+            None => (None, value),
+        };
+        let degrees = degrees
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|degrees| degrees % 90 == 0)
+            .ok_or_else(|| {
+                format!(
+                    "invalid rotation {:?}, expected a multiple of 90 degrees, e.g. 90 or 3:90",
+                    value
+                )
+            })?;
+
+        Ok(PageRotation {
+            pages,
+            degrees: degrees.rem_euclid(360),
+        })
+    }
+}
+
+/// Sets the `/Rotate` entry of the pages `rotations` select, which viewers
+/// and printers honor without the content being touched. Later rotations
+/// win over earlier ones for the same page, and the rotation pages had
+/// before, e.g. inherited from their page tree, is replaced.
+///
+/// Pages are counted from 1 in the final document. Fails if a rotation
+/// selects a page past the last one.
+pub fn rotate_pages(doc: &mut Document, rotations: &[PageRotation]) -> Result<()> {
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+
+    for rotation in rotations {
+        let indices = match &rotation.pages {
+            Some(pages) => pages.indices(page_ids.len())?,
+            None => (0..page_ids.len()).collect(),
+        };
+        for index in indices {
+            doc.get_dictionary_mut(page_ids[index])?
+                .set("Rotate", Object::Integer(rotation.degrees));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::dictionary;
+
+    use super::*;
+
+    fn rotation(value: &str) -> PageRotation {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_degrees_for_all_or_some_pages() {
+        assert_eq!(
+            rotation("90"),
+            PageRotation {
+                pages: None,
+                degrees: 90
+            }
+        );
+        assert_eq!(
+            rotation("1-5,8:270"),
+            PageRotation {
+                pages: Some("1-5,8".parse().unwrap()),
+                degrees: 270
+            }
+        );
+        assert_eq!(rotation(" 3: 180 ").degrees, 180);
+    }
+
+    #[test]
+    fn brings_degrees_into_one_turn() {
+        assert_eq!(rotation("-90").degrees, 270);
+        assert_eq!(rotation("450").degrees, 90);
+        assert_eq!(rotation("360").degrees, 0);
+    }
+
+    #[test]
+    fn rejects_invalid_rotations() {
+        for value in ["45", "", "3:", "0:90", "right", "2:90:90"] {
+            assert!(
+                value.parse::<PageRotation>().is_err(),
+                "{:?} was accepted",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn later_rotations_win() {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..3)
+            .map(|_| {
+                doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id })
+                    .into()
+            })
+            .collect();
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 3 }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        rotate_pages(&mut doc, &[rotation("90"), rotation("2-:180")]).unwrap();
+
+        let degrees: Vec<i64> = doc
+            .page_iter()
+            .map(|id| {
+                doc.get_dictionary(id)
+                    .unwrap()
+                    .get(b"Rotate")
+                    .unwrap()
+                    .as_i64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(degrees, [90, 180, 180]);
+        assert!(rotate_pages(&mut doc, &[rotation("4:90")]).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Order in which the SVG pages of a directory are merged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Compare runs of digits by their value, so `page2` comes before `page10`
+    #[default]
+    Natural,
+    /// Compare paths character by character, so `page10` comes before `page2`
+    Lexical,
+}
+
+impl SortOrder {
+    /// Compares two page paths in this order.
+    pub fn compare(self, a: &Path, b: &Path) -> Ordering {
+        match self {
+            SortOrder::Natural => natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()),
+            SortOrder::Lexical => a.cmp(b),
+        }
+    }
+}
+
+/// Compares `a` and `b` chunk by chunk, where runs of ASCII digits compare as
+/// integers and everything else compares as text.
+///
+/// Numbers that are equal in value but differ in leading zeros (`7` and
+/// `007`) fall back to a plain comparison, so the order stays total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_rest, mut b_rest) = (a, b);
+
+    loop {
+        let (a_chunk, a_tail) = split_chunk(a_rest);
+        let (b_chunk, b_tail) = split_chunk(b_rest);
+
+        let ordering = match (a_chunk, b_chunk) {
+            ("", "") => return a.cmp(b),
+            (a_chunk, b_chunk) if is_number(a_chunk) && is_number(b_chunk) => {
+                let a_digits = a_chunk.trim_start_matches('0');
+                let b_digits = b_chunk.trim_start_matches('0');
+                a_digits
+                    .len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits))
+            }
+            (a_chunk, b_chunk) => a_chunk.cmp(b_chunk),
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        (a_rest, b_rest) = (a_tail, b_tail);
+    }
+}
+
+/// Splits off the leading run of either digits or non-digits.
+fn split_chunk(text: &str) -> (&str, &str) {
+    let digits = text.starts_with(|c: char| c.is_ascii_digit());
+    let end = text
+        .find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(text.len());
+
+    text.split_at(end)
+}
+
+fn is_number(chunk: &str) -> bool {
+    chunk.starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 12] = [
+        "page10.svg",
+        "page2.svg",
+        "page02.svg",
+        "page1.svg",
+        "page",
+        "page2a.svg",
+        "page2.svg.bak",
+        "007",
+        "7",
+        "07",
+        "18446744073709551616",
+        "chapter1/page3.svg",
+    ];
+
+    #[test]
+    fn orders_runs_of_digits_by_value() {
+        let mut names = NAMES.to_vec();
+        names.sort_by(|a, b| natural_cmp(a, b));
+
+        assert_eq!(
+            names,
+            [
+                "007",
+                "07",
+                "7",
+                "18446744073709551616",
+                "chapter1/page3.svg",
+                "page",
+                "page1.svg",
+                "page02.svg",
+                "page2.svg",
+                "page2.svg.bak",
+                "page2a.svg",
+                "page10.svg",
+            ]
+        );
+    }
+
+    #[test]
+    fn is_a_total_order() {
+        for a in NAMES {
+            assert_eq!(natural_cmp(a, a), Ordering::Equal);
+            for b in NAMES {
+                assert_eq!(
+                    natural_cmp(a, b),
+                    natural_cmp(b, a).reverse(),
+                    "{} {}",
+                    a,
+                    b
+                );
+                if a != b {
+                    assert_ne!(natural_cmp(a, b), Ordering::Equal, "{} {}", a, b);
+                }
+                for c in NAMES {
+                    if natural_cmp(a, b).is_lt() && natural_cmp(b, c).is_lt() {
+                        assert!(natural_cmp(a, c).is_lt(), "{} {} {}", a, b, c);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lexical_order_compares_characters() {
+        let (a, b) = (Path::new("page10.svg"), Path::new("page2.svg"));
+
+        assert_eq!(SortOrder::Lexical.compare(a, b), Ordering::Less);
+        assert_eq!(SortOrder::Natural.compare(a, b), Ordering::Greater);
+    }
+}
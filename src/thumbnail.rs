@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use lopdf::{dictionary, Document, Object, Stream};
+use resvg::tiny_skia::{Pixmap, Transform};
+use svg2pdf::usvg::Tree;
+
+use crate::BoundingBox;
+
+/// A small opaque RGB raster of a page.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major RGB samples, 8 bits per component.
+    pub rgb: Vec<u8>,
+}
+
+/// Rasterizes `region` of the page (the whole canvas if `None`) so that its
+/// longer side is `max_side` pixels, composited onto white.
+pub fn render_thumbnail(
+    tree: &Tree,
+    region: Option<BoundingBox>,
+    max_side: u32,
+) -> Result<Thumbnail> {
+    let size = tree.size();
+    let region = region.unwrap_or(BoundingBox {
+        left: 0.0,
+        bottom: 0.0,
+        right: size.width(),
+        top: size.height(),
+    });
+
+    let (region_width, region_height) = (region.right - region.left, region.top - region.bottom);
+    let scale = max_side as f32 / region_width.max(region_height);
+    let width = ((region_width * scale).round() as u32).max(1);
+    let height = ((region_height * scale).round() as u32).max(1);
+
+    let mut pixmap =
+        Pixmap::new(width, height).ok_or_else(|| anyhow!("Cannot allocate the thumbnail."))?;
+
+    // The region is in PDF coordinates with y pointing up, the canvas has
+    // y pointing down from the top of the page.
+    let transform = Transform::from_scale(scale, scale)
+        .pre_translate(-region.left, -(size.height() - region.top));
+    crate::catch_render_panic(|| {
+        resvg::render(tree, transform, &mut pixmap.as_mut());
+        Ok(())
+    })?;
+
+    // Pixels are premultiplied, so blending onto white adds the uncovered part.
+    let rgb = pixmap
+        .data()
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let uncovered = 255 - pixel[3];
+            [
+                pixel[0] + uncovered,
+                pixel[1] + uncovered,
+                pixel[2] + uncovered,
+            ]
+        })
+        .collect();
+
+    Ok(Thumbnail { width, height, rgb })
+}
+
+/// Attaches `thumbnail` as the `/Thumb` image of every page in `pdf`.
+pub fn set_page_thumbnail(pdf: &[u8], thumbnail: &Thumbnail) -> Result<Vec<u8>> {
+    let mut doc = Document::load_mem(pdf)?;
+
+    let mut image = Stream::new(
+        dictionary! {
+            "Width" => thumbnail.width,
+            "Height" => thumbnail.height,
+            "ColorSpace" => "DeviceRGB",
+            "BitsPerComponent" => 8,
+        },
+        thumbnail.rgb.clone(),
+    );
+    let _ = image.compress();
+    let image_id = doc.add_object(image);
+
+    for page_id in doc.page_iter().collect::<Vec<_>>() {
+        doc.get_dictionary_mut(page_id)?
+            .set("Thumb", Object::Reference(image_id));
+    }
+
+    let mut output = Vec::new();
+    doc.save_to(&mut output)?;
+
+    Ok(output)
+}
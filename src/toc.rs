@@ -0,0 +1,357 @@
+use anyhow::Result;
+use log::warn;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::insert::shift_page_labels;
+use crate::page::media_box;
+use crate::page_numbers;
+
+/// Heading printed at the top of the first table of contents page.
+const TITLE: &str = "Contents";
+
+const TITLE_SIZE: f32 = 18.0;
+const FONT_SIZE: f32 = 11.0;
+const LINE_HEIGHT: f32 = 16.0;
+
+/// Distance of the text from the page edges, and indentation per outline
+/// level, in points.
+const MARGIN: f32 = 54.0;
+const INDENT: f32 = 14.0;
+
+/// Space kept between a title, its dot leader and its page number.
+const GAP: f32 = 6.0;
+
+/// Share of the font size glyphs reach below the baseline, to make links
+/// cover the whole line.
+const DESCENT: f32 = 0.25;
+
+/// Resource name used for the font, unlikely to clash with anything else.
+const FONT_NAME: &str = "TocFont";
+
+/// A bookmark of the outline, listed as a line of the table of contents.
+struct TocEntry {
+    level: usize,
+    title: String,
+    page_id: ObjectId,
+    /// The bookmark's `/A` action or `/Dest`, as `(key, value)`, reused for
+    /// the link.
+    target: (&'static str, Object),
+}
+
+/// Prepends a table of contents to `doc` listing every outline item with
+/// the number of the page it points at, and returns how many pages it took.
+///
+/// Each line links to the same destination as its bookmark, and page
+/// numbers count the contents pages themselves. The pages take the size of
+/// the first page and use the standard Helvetica font, like
+/// [`page_numbers::number_pages`], so titles are limited to Latin-1; titles
+/// too long for a line are cut off with an ellipsis. With `pad_to_even`, a
+/// blank page follows an odd number of contents pages so the pages after it
+/// keep their parity for duplex printing.
+pub fn insert_toc(doc: &mut Document, pad_to_even: bool) -> Result<usize> {
+    let entries = collect_entries(doc)?;
+    if entries.is_empty() {
+        warn!("The document has no bookmarks, skipping the table of contents");
+        return Ok(0);
+    }
+
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+    let [left, bottom, right, top] = match page_ids.first() {
+        Some(page_id) => media_box(doc, *page_id)?,
+        None => [0.0, 0.0, 612.0, 792.0],
+    };
+    let (width, height) = (right - left, top - bottom);
+    let margin = MARGIN.min(width.min(height) / 8.0);
+
+    // Baseline of the first line of a page, and the lowest one allowed.
+    let first_line = top - margin - FONT_SIZE;
+    let last_line = bottom + margin;
+    // The title takes up two lines of the first page.
+    let first_page_lines = lines_between(first_line - 2.0 * LINE_HEIGHT, last_line);
+    let lines_per_page = lines_between(first_line, last_line).max(1);
+    let toc_pages = 1 + entries
+        .len()
+        .saturating_sub(first_page_lines)
+        .div_ceil(lines_per_page);
+    let padding = usize::from(pad_to_even && toc_pages % 2 == 1);
+    let shift = toc_pages + padding;
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let resources = dictionary! { "Font" => dictionary! { FONT_NAME => font_id } };
+
+    let mut new_pages = Vec::new();
+    let mut lines = entries.into_iter();
+    for toc_page in 0..toc_pages {
+        let mut content = Vec::new();
+        let mut annotations = Vec::new();
+        let mut y = first_line;
+        let mut page_lines = lines_per_page;
+
+        if toc_page == 0 {
+            let (title, _) = page_numbers::encode(TITLE);
+            draw_text(&mut content, &title, TITLE_SIZE, left + margin, y);
+            y -= 2.0 * LINE_HEIGHT;
+            page_lines = first_page_lines;
+        }
+
+        for entry in lines.by_ref().take(page_lines) {
+            let page_number = page_ids
+                .iter()
+                .position(|page_id| *page_id == entry.page_id)
+                .map_or_else(String::new, |index| (index + shift + 1).to_string());
+
+            let x = left + margin + entry.level as f32 * INDENT;
+            let (number, number_width) = page_numbers::encode(&page_number);
+            let number_x = right - margin - number_width * FONT_SIZE;
+            let (title, title_width) = fit(&entry.title, number_x - GAP - x);
+
+            draw_text(&mut content, &title, FONT_SIZE, x, y);
+            draw_text(&mut content, &number, FONT_SIZE, number_x, y);
+
+            let (leader, leader_width) = page_numbers::encode(". ");
+            let leader_start = x + title_width * FONT_SIZE + GAP;
+            let leaders = ((number_x - GAP - leader_start) / (leader_width * FONT_SIZE)).max(0.0);
+            draw_text(
+                &mut content,
+                &leader.repeat(leaders as usize),
+                FONT_SIZE,
+                leader_start,
+                y,
+            );
+
+            let (key, target) = entry.target;
+            let mut annotation = dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Link",
+                "Rect" => vec![
+                    x.into(),
+                    (y - DESCENT * FONT_SIZE).into(),
+                    (right - margin).into(),
+                    (y + FONT_SIZE).into(),
+                ],
+                "Border" => vec![0.into(), 0.into(), 0.into()],
+            };
+            annotation.set(key, target);
+            annotations.push(Object::Reference(doc.add_object(annotation)));
+
+            y -= LINE_HEIGHT;
+        }
+
+        let contents_id = doc.add_object(Stream::new(Dictionary::new(), content));
+        let mut page = dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![left.into(), bottom.into(), right.into(), top.into()],
+            "Resources" => resources.clone(),
+            "Contents" => contents_id,
+        };
+        if !annotations.is_empty() {
+            page.set("Annots", annotations);
+        }
+        new_pages.push(doc.add_object(page));
+    }
+
+    if padding > 0 {
+        let contents_id = doc.add_object(Stream::new(Dictionary::new(), Vec::new()));
+        new_pages.push(doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![left.into(), bottom.into(), right.into(), top.into()],
+            "Resources" => Dictionary::new(),
+            "Contents" => contents_id,
+        }));
+    }
+
+    let pages = doc.get_dictionary_mut(pages_id)?;
+    let mut kids: Vec<Object> = new_pages.into_iter().map(Object::Reference).collect();
+    kids.extend(pages.get(b"Kids")?.as_array()?.iter().cloned());
+    let count = pages.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+    pages.set("Kids", kids);
+    pages.set("Count", count + shift as i64);
+
+    shift_page_labels(doc, 0, shift as i64)?;
+
+    Ok(shift)
+}
+
+/// Number of lines with a baseline from `first` down to `last`, one every
+/// [`LINE_HEIGHT`].
+fn lines_between(first: f32, last: f32) -> usize {
+    if first < last {
+        return 0;
+    }
+
+    ((first - last) / LINE_HEIGHT) as usize + 1
+}
+
+/// Walks the outline of `doc` depth first, keeping the items that point at
+/// a page.
+fn collect_entries(doc: &Document) -> Result<Vec<TocEntry>> {
+    let outlines = match doc.catalog()?.get(b"Outlines") {
+        Ok(outlines) => doc.dereference(outlines)?.1.as_dict()?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut entries = Vec::new();
+    // Items still to visit, with their level, in reverse order.
+    let mut pending: Vec<(usize, ObjectId)> = Vec::new();
+    if let Ok(first) = outlines.get(b"First").and_then(Object::as_reference) {
+        pending.push((0, first));
+    }
+
+    let mut visited = 0;
+    while let Some((level, item_id)) = pending.pop() {
+        // Guards against cyclic outlines in malformed documents.
+        visited += 1;
+        if visited > doc.objects.len() {
+            break;
+        }
+        let item = doc.get_dictionary(item_id)?;
+
+        if let Ok(next) = item.get(b"Next").and_then(Object::as_reference) {
+            pending.push((level, next));
+        }
+        if let Ok(first) = item.get(b"First").and_then(Object::as_reference) {
+            pending.push((level + 1, first));
+        }
+
+        let (target, page_id) = match item_target(doc, item) {
+            Some(target) => target,
+            None => continue,
+        };
+        let title = item
+            .get(b"Title")
+            .and_then(Object::as_str)
+            .map(decode_text_string)
+            .unwrap_or_default();
+
+        entries.push(TocEntry {
+            level,
+            title,
+            page_id,
+            target,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// The `/A` go-to action or `/Dest` of an outline item, and the page it
+/// leads to. Named destinations and other actions are left out.
+fn item_target(doc: &Document, item: &Dictionary) -> Option<((&'static str, Object), ObjectId)> {
+    let (target, dest) = match item.get(b"A") {
+        Ok(action) => (
+            ("A", action.clone()),
+            doc.dereference(action)
+                .ok()?
+                .1
+                .as_dict()
+                .ok()?
+                .get(b"D")
+                .ok()?,
+        ),
+        Err(_) => {
+            let dest = item.get(b"Dest").ok()?;
+            (("Dest", dest.clone()), dest)
+        }
+    };
+
+    match doc.dereference(dest).ok()?.1.as_array().ok()?.first() {
+        Some(Object::Reference(page_id)) => Some((target, *page_id)),
+        _ => None,
+    }
+}
+
+/// Decodes a PDF text string, either UTF-16BE with a byte order mark or
+/// PDFDocEncoding, read as the Latin-1 it mostly agrees with.
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        None => bytes.iter().map(|byte| char::from(*byte)).collect(),
+    }
+}
+
+/// Encodes `title`, cut off with an ellipsis if it is wider than
+/// `max_width` points, returning it with its width relative to the font
+/// size.
+fn fit(title: &str, max_width: f32) -> (Vec<u8>, f32) {
+    let (encoded, width) = page_numbers::encode(title);
+    if width * FONT_SIZE <= max_width {
+        return (encoded, width);
+    }
+
+    let mut chars: Vec<char> = title.chars().collect();
+    while chars.pop().is_some() {
+        let shortened = format!("{}…", chars.iter().collect::<String>().trim_end());
+        let (encoded, width) = page_numbers::encode(&shortened);
+        if width * FONT_SIZE <= max_width {
+            return (encoded, width);
+        }
+    }
+
+    (Vec::new(), 0.0)
+}
+
+fn draw_text(content: &mut Vec<u8>, text: &[u8], size: f32, x: f32, y: f32) {
+    content.extend_from_slice(format!("BT /{} {} Tf {} {} Td (", FONT_NAME, size, x, y).as_bytes());
+    content.extend_from_slice(text);
+    content.extend_from_slice(b") Tj ET\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merge_pdfs_with, render_svg_to_pdf, MergeOptions};
+
+    /// A document of `count` A4 pages with a bookmark each.
+    fn a4_document(count: usize) -> Document {
+        let page = render_svg_to_pdf(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="595" height="842"/>"#,
+        )
+        .unwrap();
+        merge_pdfs_with(vec![&page; count], &MergeOptions::default()).unwrap()
+    }
+
+    fn link_counts(doc: &Document, pages: usize) -> Vec<usize> {
+        doc.page_iter()
+            .take(pages)
+            .map(|page_id| {
+                doc.get_dictionary(page_id)
+                    .unwrap()
+                    .get(b"Annots")
+                    .and_then(Object::as_array)
+                    .map_or(0, Vec::len)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fits_a_full_first_page_on_one_page() {
+        let mut doc = a4_document(44);
+
+        assert_eq!(insert_toc(&mut doc, false).unwrap(), 1);
+
+        assert_eq!(doc.get_pages().len(), 45);
+        assert_eq!(link_counts(&doc, 1), [44]);
+    }
+
+    #[test]
+    fn lists_every_bookmark_across_pages() {
+        let mut doc = a4_document(100);
+
+        assert_eq!(insert_toc(&mut doc, false).unwrap(), 3);
+
+        assert_eq!(link_counts(&doc, 3), [44, 46, 10]);
+    }
+}
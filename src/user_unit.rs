@@ -0,0 +1,104 @@
+use anyhow::Result;
+use log::warn;
+use lopdf::{Document, Object, ObjectId};
+
+use crate::page::{media_box, page_box, wrap_page_contents};
+
+/// Largest page side, in default user space units, that PDF viewers accept.
+pub const MAX_PAGE_SIDE: f32 = 14_400.0;
+
+/// Page boxes scaled along with the page.
+const PAGE_BOX_KEYS: [&[u8]; 5] = [b"MediaBox", b"CropBox", b"BleedBox", b"TrimBox", b"ArtBox"];
+
+/// Gives every page exceeding [`MAX_PAGE_SIDE`] a `/UserUnit` large enough to
+/// bring it back within the limit while keeping its physical size.
+///
+/// The page boxes, content and annotation rectangles of such pages are
+/// scaled down by the chosen unit. Returns the number of adjusted pages.
+pub fn apply_user_units(doc: &mut Document) -> Result<usize> {
+    let page_ids: Vec<ObjectId> = doc.page_iter().collect();
+    let mut adjusted = 0;
+
+    for (index, page_id) in page_ids.into_iter().enumerate() {
+        let [left, bottom, right, top] = media_box(doc, page_id)?;
+        let side = (right - left).max(top - bottom);
+        if side <= MAX_PAGE_SIDE {
+            continue;
+        }
+
+        // Round up to two decimals so the scaled page never ends up above
+        // the limit due to the rounding itself.
+        let user_unit = (side / MAX_PAGE_SIDE * 100.0).ceil() / 100.0;
+        scale_page(doc, page_id, 1.0 / user_unit)?;
+        doc.get_dictionary_mut(page_id)?
+            .set("UserUnit", Object::Real(user_unit));
+
+        warn!(
+            "Page {} is {} pt wide/tall, set its UserUnit to {}",
+            index + 1,
+            side,
+            user_unit
+        );
+        adjusted += 1;
+    }
+
+    // UserUnit was introduced in PDF 1.6.
+    if adjusted > 0 && doc.version.as_str() < "1.6" {
+        doc.version = "1.6".to_string();
+    }
+
+    Ok(adjusted)
+}
+
+fn scale_page(doc: &mut Document, page_id: ObjectId, factor: f32) -> Result<()> {
+    for key in PAGE_BOX_KEYS {
+        if let Some(page_box) = page_box(doc, page_id, key)? {
+            doc.get_dictionary_mut(page_id)?
+                .set(key, scale_rect(page_box, factor));
+        }
+    }
+
+    let annotations: Vec<ObjectId> = doc
+        .get_dictionary(page_id)?
+        .get(b"Annots")
+        .and_then(Object::as_array)
+        .map(|annots| {
+            annots
+                .iter()
+                .filter_map(|annot| annot.as_reference().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for annotation_id in annotations {
+        let rect = doc
+            .get_dictionary(annotation_id)
+            .and_then(|annotation| annotation.get(b"Rect"))
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|rect| {
+                rect.iter()
+                    .map(|v| v.as_float().or_else(|_| v.as_i64().map(|v| v as f32)).ok())
+                    .collect::<Option<Vec<f32>>>()
+            });
+
+        if let Some(&[x1, y1, x2, y2]) = rect.as_deref() {
+            doc.get_dictionary_mut(annotation_id)?
+                .set("Rect", scale_rect([x1, y1, x2, y2], factor));
+        }
+    }
+
+    wrap_page_contents(
+        doc,
+        page_id,
+        format!("q {} 0 0 {} 0 0 cm\n", factor, factor).into_bytes(),
+        b"\nQ".to_vec(),
+    )
+}
+
+fn scale_rect(rect: [f32; 4], factor: f32) -> Object {
+    rect.iter()
+        .map(|value| Object::Real(value * factor))
+        .collect::<Vec<_>>()
+        .into()
+}
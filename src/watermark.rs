@@ -0,0 +1,135 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use lopdf::{dictionary, Document};
+
+use crate::page::{add_page_resources, media_box, page_box, wrap_page_contents};
+use crate::page_numbers;
+
+/// Cap height of Helvetica, relative to the font size.
+const CAP_HEIGHT: f32 = 0.718;
+
+/// Share of the visible area's width and height the watermark may take up.
+const COVERAGE: f32 = 0.8;
+
+/// Resource names used for the watermark, unlikely to clash with the page's
+/// own.
+const FONT_NAME: &str = "WatermarkFont";
+const GRAPHICS_STATE_NAME: &str = "WatermarkGS";
+
+/// An RGB color with components from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    /// Parses a hex color such as `#c00000`, the `#` being optional.
+    fn from_str(value: &str) -> Result<Color, String> {
+        let invalid = || format!("invalid color {:?}, expected #RRGGBB", value);
+
+        let hex = value.trim();
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let component = |index: usize| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map(|component| f32::from(component) / 255.0)
+                .map_err(|_| invalid())
+        };
+
+        Ok(Color {
+            red: component(0)?,
+            green: component(2)?,
+            blue: component(4)?,
+        })
+    }
+}
+
+/// Text laid over every page by [`apply_watermark`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub text: String,
+    /// From 0, invisible, to 1, opaque.
+    pub opacity: f32,
+    /// Counterclockwise angle of the text in degrees, along the diagonal from
+    /// the bottom-left to the top-right corner if not set.
+    pub rotation: Option<f32>,
+    pub color: Color,
+}
+
+/// Draws `watermark` centered on the visible area of every page of `doc`, as
+/// large as fits.
+///
+/// The text uses the standard Helvetica font, so nothing is embedded, which
+/// limits it to the characters [`page_numbers::number_pages`] supports. It is
+/// drawn on top of the existing content, which is wrapped in `q`/`Q` so its
+/// graphics state can't leak into the watermark.
+pub fn apply_watermark(doc: &mut Document, watermark: &Watermark) -> Result<()> {
+    let (encoded, text_width) = page_numbers::encode(&watermark.text);
+    if text_width == 0.0 {
+        return Ok(());
+    }
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let graphics_state_id = doc.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => watermark.opacity,
+    });
+
+    for page_id in doc.page_iter().collect::<Vec<_>>() {
+        add_page_resources(
+            doc,
+            page_id,
+            &[
+                (b"Font", FONT_NAME, font_id),
+                (b"ExtGState", GRAPHICS_STATE_NAME, graphics_state_id),
+            ],
+        )?;
+
+        let [left, bottom, right, top] = match page_box(doc, page_id, b"CropBox")? {
+            Some(crop_box) => crop_box,
+            None => media_box(doc, page_id)?,
+        };
+        let (width, height) = (right - left, top - bottom);
+
+        let angle = match watermark.rotation {
+            Some(rotation) => rotation.to_radians(),
+            None => height.atan2(width),
+        };
+        let (sin, cos) = angle.sin_cos();
+
+        // The largest size at which the rotated text still fits.
+        let size = COVERAGE
+            * (width / (text_width * cos.abs() + CAP_HEIGHT * sin.abs()))
+                .min(height / (text_width * sin.abs() + CAP_HEIGHT * cos.abs()));
+
+        // Start the baseline so the text ends up centered on the page.
+        let (half_width, half_height) = (text_width * size / 2.0, CAP_HEIGHT * size / 2.0);
+        let x = left + width / 2.0 - cos * half_width + sin * half_height;
+        let y = bottom + height / 2.0 - sin * half_width - cos * half_height;
+
+        let Color { red, green, blue } = watermark.color;
+        let mut stamp = format!(
+            "Q\nq /{} gs {} {} {} rg BT /{} {} Tf {} {} {} {} {} {} Tm (",
+            GRAPHICS_STATE_NAME, red, green, blue, FONT_NAME, size, cos, sin, -sin, cos, x, y
+        )
+        .into_bytes();
+        stamp.extend_from_slice(&encoded);
+        stamp.extend_from_slice(b") Tj ET Q\n");
+
+        wrap_page_contents(doc, page_id, b"q\n".to_vec(), stamp)?;
+    }
+
+    Ok(())
+}